@@ -1,6 +1,7 @@
 // Filters operating on array
 use std::collections::HashMap;
 
+use crate::builtins::filters::Filter;
 use crate::context::{dotted_pointer, ValueRender};
 use crate::errors::{Error, Result};
 use crate::filter_utils::{get_sort_strategy_for_type, get_unique_strategy_for_type};
@@ -239,6 +240,20 @@ pub fn map(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
     Ok(to_value(arr).unwrap())
 }
 
+// The `map` filter as registered: given a `macro` argument, the renderer calls the macro with
+// each item instead
+pub struct MapFilter;
+
+impl Filter for MapFilter {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+        map(value, args)
+    }
+
+    fn renders_macro(&self) -> bool {
+        true
+    }
+}
+
 #[inline]
 fn get_index(i: f64, array: &[Value]) -> usize {
     if i >= 0.0 {
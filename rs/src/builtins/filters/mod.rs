@@ -1,5 +1,9 @@
 use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
 
+#[cfg(feature = "async")]
+use crate::builtins::functions::AsyncResult;
 use crate::errors::Result;
 use serde_json::value::Value;
 
@@ -18,6 +22,12 @@ pub trait Filter: Sync + Send {
     fn is_safe(&self) -> bool {
         false
     }
+
+    // Whether a `macro` argument makes the filter render each item of the array with that
+    // macro instead of calling `filter`, like the built-in `map`. Defaults to `false`
+    fn renders_macro(&self) -> bool {
+        false
+    }
 }
 
 impl<F> Filter for F
@@ -28,3 +38,26 @@ where
         self(value, args)
     }
 }
+
+// A filter that is awaited when rendering with `Lysine::render_async`
+#[cfg(feature = "async")]
+pub trait AsyncFilter: Sync + Send {
+    // The async filter function type definition
+    fn filter(&self, value: Value, args: HashMap<String, Value>) -> AsyncResult;
+
+    // Whether the current filter's output should be treated as safe, defaults to `false`
+    fn is_safe(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "async")]
+impl<F, Fut> AsyncFilter for F
+where
+    F: Fn(Value, HashMap<String, Value>) -> Fut + Sync + Send,
+    Fut: Future<Output = Result<Value>> + Send + 'static,
+{
+    fn filter(&self, value: Value, args: HashMap<String, Value>) -> AsyncResult {
+        Box::pin(self(value, args))
+    }
+}
@@ -1,4 +1,8 @@
 use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
 
 #[cfg(feature = "builtins")]
 use chrono::prelude::*;
@@ -17,6 +21,13 @@ pub trait Function: Sync + Send {
     fn is_safe(&self) -> bool {
         false
     }
+
+    // How many items the array returned for those arguments will have, if it is known before
+    // calling the function. The render limits are checked against it so a huge array isn't
+    // built for nothing. Defaults to `None`
+    fn len_hint(&self, _args: &HashMap<String, Value>) -> Option<usize> {
+        None
+    }
 }
 
 impl<F> Function for F
@@ -28,7 +39,36 @@ where
     }
 }
 
-pub fn range(args: &HashMap<String, Value>) -> Result<Value> {
+// The future returned by the async functions and filters
+#[cfg(feature = "async")]
+pub type AsyncResult = Pin<Box<dyn Future<Output = Result<Value>> + Send>>;
+
+// A global function that is awaited when rendering with `Lysine::render_async`, eg to load data
+// from a cache without blocking the thread
+#[cfg(feature = "async")]
+pub trait AsyncFunction: Sync + Send {
+    // The async global function type definition
+    fn call(&self, args: HashMap<String, Value>) -> AsyncResult;
+
+    // Whether the current function's output should be treated as safe, defaults to `false`
+    fn is_safe(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "async")]
+impl<F, Fut> AsyncFunction for F
+where
+    F: Fn(HashMap<String, Value>) -> Fut + Sync + Send,
+    Fut: Future<Output = Result<Value>> + Send + 'static,
+{
+    fn call(&self, args: HashMap<String, Value>) -> AsyncResult {
+        Box::pin(self(args))
+    }
+}
+
+// The start, end and step of a `range()` call
+fn range_args(args: &HashMap<String, Value>) -> Result<(usize, usize, usize)> {
     let start = match args.get("start") {
         Some(val) => match from_value::<usize>(val.clone()) {
             Ok(v) => v,
@@ -73,6 +113,15 @@ pub fn range(args: &HashMap<String, Value>) -> Result<Value> {
             "Function `range` was called with a `start` argument greater than the `end` one",
         ));
     }
+    if step_by == 0 {
+        return Err(Error::msg("Function `range` was called with a `step_by` argument of 0"));
+    }
+
+    Ok((start, end, step_by))
+}
+
+pub fn range(args: &HashMap<String, Value>) -> Result<Value> {
+    let (start, end, step_by) = range_args(args)?;
 
     let mut i = start;
     let mut res = vec![];
@@ -83,6 +132,20 @@ pub fn range(args: &HashMap<String, Value>) -> Result<Value> {
     Ok(to_value(res).unwrap())
 }
 
+// The built-in `range` function, knowing the length of the array before building it
+pub struct Range;
+
+impl Function for Range {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        range(args)
+    }
+
+    fn len_hint(&self, args: &HashMap<String, Value>) -> Option<usize> {
+        let (start, end, step_by) = range_args(args).ok()?;
+        Some((end - start).div_ceil(step_by))
+    }
+}
+
 #[cfg(feature = "builtins")]
 pub fn now(args: &HashMap<String, Value>) -> Result<Value> {
     let utc = match args.get("utc") {
@@ -240,6 +303,26 @@ mod tests {
         assert_eq!(res, to_value(vec![0, 2, 4, 6, 8]).unwrap());
     }
 
+    #[test]
+    fn range_step_by_zero() {
+        let mut args = HashMap::new();
+        args.insert("end".to_string(), to_value(10).unwrap());
+        args.insert("step_by".to_string(), to_value(0).unwrap());
+
+        assert!(range(&args).is_err());
+    }
+
+    #[test]
+    fn range_len_hint() {
+        let mut args = HashMap::new();
+        args.insert("start".to_string(), to_value(1).unwrap());
+        args.insert("end".to_string(), to_value(10).unwrap());
+        args.insert("step_by".to_string(), to_value(2).unwrap());
+
+        assert_eq!(Range.len_hint(&args), Some(5));
+        assert_eq!(range(&args).unwrap().as_array().unwrap().len(), 5);
+    }
+
     #[cfg(feature = "builtins")]
     #[test]
     fn now_default() {
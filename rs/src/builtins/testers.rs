@@ -101,11 +101,20 @@ pub fn number(value: Option<&Value>, params: &[Value]) -> Result<bool> {
     }
 }
 
+// Integers are checked exactly rather than going through a float
+fn as_integer(value: &Value) -> Option<i128> {
+    value.as_i64().map(i128::from).or_else(|| value.as_u64().map(i128::from))
+}
+
 // Returns true if `value` is an odd number. Otherwise, returns false.
 pub fn odd(value: Option<&Value>, params: &[Value]) -> Result<bool> {
     number_args_allowed("odd", 0, params.len())?;
     value_defined("odd", value)?;
 
+    if let Some(i) = value.and_then(as_integer) {
+        return Ok(i % 2 != 0);
+    }
+
     match value.and_then(|v| v.to_number().ok()) {
         Some(f) => Ok(f % 2.0 != 0.0),
         _ => Err(Error::msg("Tester `odd` was called on a variable that isn't a number")),
@@ -126,6 +135,11 @@ pub fn divisible_by(value: Option<&Value>, params: &[Value]) -> Result<bool> {
     number_args_allowed("divisibleby", 1, params.len())?;
     value_defined("divisibleby", value)?;
 
+    if let (Some(val), Some(p)) = (value.and_then(as_integer), params.first().and_then(as_integer))
+    {
+        return Ok(p != 0 && val % p == 0);
+    }
+
     match value.and_then(|v| v.to_number().ok()) {
         Some(val) => match params.first().and_then(|v| v.to_number().ok()) {
             Some(p) => Ok(val % p == 0.0),
@@ -222,8 +236,8 @@ mod tests {
     use std::collections::HashMap;
 
     use super::{
-        containing, defined, divisible_by, ending_with, iterable, matching, object, starting_with,
-        string,
+        containing, defined, divisible_by, ending_with, iterable, matching, object, odd,
+        starting_with, string,
     };
 
     use serde_json::value::to_value;
@@ -262,6 +276,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_divisible_by_and_odd_on_big_integers() {
+        let big = to_value(9_007_199_254_740_993u64).unwrap();
+        assert!(odd(Some(&big), &[]).unwrap());
+        assert!(!divisible_by(Some(&big), &[to_value(2).unwrap()]).unwrap());
+        assert!(divisible_by(Some(&to_value(u64::MAX).unwrap()), &[to_value(5).unwrap()]).unwrap());
+        assert!(!divisible_by(Some(&to_value(10).unwrap()), &[to_value(0).unwrap()]).unwrap());
+    }
+
     #[test]
     fn test_iterable() {
         assert!(iterable(Some(&to_value(vec!["1"]).unwrap()), &[]).unwrap());
@@ -1,10 +1,13 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::io::Write;
+use std::sync::Arc;
 
 use serde::ser::Serialize;
 use serde_json::value::{to_value, Map, Value};
 
 use crate::errors::{Error, Result as LysineResult};
+use crate::object::{Object, ObjectRef};
 
 // The struct that holds the context of a template rendering.
 ///
@@ -13,12 +16,14 @@ use crate::errors::{Error, Result as LysineResult};
 #[derive(Debug, Clone, PartialEq)]
 pub struct Context {
     data: BTreeMap<String, Value>,
+    // The objects resolved when the templates use them, a key is either in `data` or here
+    objects: BTreeMap<String, ObjectRef>,
 }
 
 impl Context {
     // Initializes an empty context
     pub fn new() -> Self {
-        Context { data: BTreeMap::new() }
+        Context { data: BTreeMap::new(), objects: BTreeMap::new() }
     }
 
     // Converts the `val` parameter to `Value` and insert it into the context.
@@ -31,7 +36,9 @@ impl Context {
     // context.insert("number_users", &42);
     // ```
     pub fn insert<T: Serialize + ?Sized, S: Into<String>>(&mut self, key: S, val: &T) {
-        self.data.insert(key.into(), to_value(val).unwrap());
+        let key = key.into();
+        self.objects.remove(&key);
+        self.data.insert(key, to_value(val).unwrap());
     }
 
     // Converts the `val` parameter to `Value` and insert it into the context.
@@ -58,11 +65,39 @@ impl Context {
         key: S,
         val: &T,
     ) -> LysineResult<()> {
-        self.data.insert(key.into(), to_value(val)?);
+        let val = to_value(val)?;
+        let key = key.into();
+        self.objects.remove(&key);
+        self.data.insert(key, val);
 
         Ok(())
     }
 
+    // Inserts an [`Object`] into the context, which is only resolved when a template uses it.
+    //
+    // ```rust
+    // # use lysine::{Context, Object, Value};
+    // struct Users;
+    // impl Object for Users {
+    //     fn get(&self, key: &str) -> Option<Value> {
+    //         // Loads a single user instead of all of them
+    //         Some(Value::from(format!("User {}", key)))
+    //     }
+    //
+    //     fn to_value(&self) -> Value {
+    //         Value::from("All the users")
+    //     }
+    // }
+    //
+    // let mut context = Context::new();
+    // context.insert_object("users", Users);
+    // ```
+    pub fn insert_object<O: Object + 'static, S: Into<String>>(&mut self, key: S, object: O) {
+        let key = key.into();
+        self.data.remove(&key);
+        self.objects.insert(key, ObjectRef(Arc::new(object)));
+    }
+
     // Appends the data of the `source` parameter to `self`, overwriting existing keys.
     // The source context will be dropped.
     ///
@@ -77,18 +112,40 @@ impl Context {
     // target.extend(source);
     // ```
     pub fn extend(&mut self, mut source: Context) {
+        for key in source.data.keys() {
+            self.objects.remove(key);
+        }
+        for key in source.objects.keys() {
+            self.data.remove(key);
+        }
         self.data.append(&mut source.data);
+        self.objects.append(&mut source.objects);
     }
 
     // Converts the context to a `serde_json::Value` consuming the context.
+    // The objects are converted with `Object::to_value`.
     pub fn into_json(self) -> Value {
         let mut m = Map::new();
         for (key, value) in self.data {
             m.insert(key, value);
         }
+        for (key, object) in self.objects {
+            m.insert(key, object.0.to_value());
+        }
         Value::Object(m)
     }
 
+    // Copies the values of the context in a JSON map, the objects are converted with
+    // `Object::to_value`
+    pub(crate) fn copy_to_json(&self, map: &mut Map<String, Value>) {
+        for (key, value) in &self.data {
+            map.insert(key.clone(), value.clone());
+        }
+        for (key, object) in &self.objects {
+            map.insert(key.clone(), object.0.to_value());
+        }
+    }
+
     // Takes a serde-json `Value` and convert it into a `Context` with no overhead/cloning.
     pub fn from_value(obj: Value) -> LysineResult<Self> {
         match obj {
@@ -97,7 +154,7 @@ impl Context {
                 for (key, value) in m {
                     data.insert(key, value);
                 }
-                Ok(Context { data })
+                Ok(Context { data, objects: BTreeMap::new() })
             }
             _ => Err(Error::msg(
                 "Creating a Context from a Value/Serialize requires it being a JSON object",
@@ -119,13 +176,64 @@ impl Context {
     }
 
     // Remove a key from the context, returning the value at the key if the key was previously inserted into the context.
+    // An object is returned converted with `Object::to_value`.
     pub fn remove(&mut self, index: &str) -> Option<Value> {
-        self.data.remove(index)
+        match self.objects.remove(index) {
+            Some(object) => Some(object.0.to_value()),
+            None => self.data.remove(index),
+        }
     }
 
-    // Checks if a value exists at a specific index.
+    // Checks if a value or an object exists at a specific index.
     pub fn contains_key(&self, index: &str) -> bool {
-        self.data.contains_key(index)
+        self.data.contains_key(index) || self.objects.contains_key(index)
+    }
+
+    // Returns the object at a given key index.
+    pub(crate) fn get_object(&self, index: &str) -> Option<&dyn Object> {
+        self.objects.get(index).map(|object| &*object.0)
+    }
+
+    // Returns the value at a dotted path like `cart.items`, an object resolving the first key
+    pub(crate) fn get_dotted(&self, path: &str) -> Option<Cow<'_, Value>> {
+        let (root, rest) = path.split_once('.').unwrap_or((path, ""));
+        match self.data.get(root) {
+            Some(val) => dotted_pointer(val, rest).map(Cow::Borrowed),
+            None => object_pointer(self.get_object(root)?, rest).map(Cow::Owned),
+        }
+    }
+
+    // Inserts a value at a dotted path like `cart.items`, creating the objects missing on the way.
+    // Returns `false` if something on the path exists but isn't an object
+    pub(crate) fn insert_dotted(&mut self, path: &str, val: Value) -> bool {
+        let mut keys = path.split('.');
+        let root = keys.next().unwrap();
+        let mut target = match keys.next() {
+            Some(key) => {
+                (self.data.entry(root.to_string()).or_insert_with(|| Map::new().into()), key)
+            }
+            None => {
+                self.data.insert(root.to_string(), val);
+                return true;
+            }
+        };
+
+        for next_key in keys {
+            let (parent, key) = target;
+            let child = match parent {
+                Value::Object(map) => map.entry(key).or_insert_with(|| Map::new().into()),
+                _ => return false,
+            };
+            target = (child, next_key);
+        }
+
+        match target {
+            (Value::Object(map), key) => {
+                map.insert(key.to_string(), val);
+                true
+            }
+            _ => false,
+        }
     }
 }
 
@@ -135,6 +243,165 @@ impl Default for Context {
     }
 }
 
+// A context made of layers, eg the data of the whole site, then the data of a page and then the
+// data of a request. Unlike a `Context`, it borrows what is given to it instead of owning it, so
+// large data doesn't need to be cloned for every render.
+//
+// A lookup falls through the layers, from the last one added to the first one.
+//
+// ```rust
+// # use lysine::{Context, LayeredContext, Value};
+// let mut site = Context::new();
+// site.insert("title", "My site");
+// site.insert("lang", "en");
+// let mut page = Context::new();
+// page.insert("title", "Home");
+// let user = Value::from("Bob");
+//
+// let mut context = LayeredContext::new();
+// context.push(&site);
+// context.push(&page);
+// context.insert_ref("user", &user);
+// assert_eq!(context.get("title"), Some(&Value::from("Home")));
+// assert_eq!(context.get("lang"), Some(&Value::from("en")));
+// ```
+#[derive(Debug, Clone, Default)]
+pub struct LayeredContext<'a> {
+    layers: Vec<Layer<'a>>,
+}
+
+#[derive(Debug, Clone)]
+enum Layer<'a> {
+    // A whole context
+    Context(&'a Context),
+    // Values inserted one by one, only the ones converted from a `Serialize` are owned
+    Values(BTreeMap<String, Cow<'a, Value>>),
+    // Another layered context, to add layers to a context without copying it
+    Layered(&'a LayeredContext<'a>),
+}
+
+impl<'a> LayeredContext<'a> {
+    // Initializes a context without any layer
+    pub fn new() -> Self {
+        LayeredContext { layers: Vec::new() }
+    }
+
+    // Adds a context as a new layer, its values take priority over the ones of the layers
+    // added before
+    pub fn push(&mut self, context: &'a Context) {
+        self.layers.push(Layer::Context(context));
+    }
+
+    // A context with all the layers of `context` as its first layer
+    pub(crate) fn on_top_of(context: &'a LayeredContext<'a>) -> Self {
+        LayeredContext { layers: vec![Layer::Layered(context)] }
+    }
+
+    // Inserts a borrowed value on top of the layers added so far
+    pub fn insert_ref<S: Into<String>>(&mut self, key: S, val: &'a Value) {
+        self.values_layer().insert(key.into(), Cow::Borrowed(val));
+    }
+
+    // Converts the `val` parameter to `Value` and inserts it on top of the layers added so far.
+    //
+    // Panics if the serialization fails.
+    pub fn insert<T: Serialize + ?Sized, S: Into<String>>(&mut self, key: S, val: &T) {
+        self.values_layer().insert(key.into(), Cow::Owned(to_value(val).unwrap()));
+    }
+
+    // Returns the value at a given key index, from the last layer that has it
+    pub fn get(&self, index: &str) -> Option<&Value> {
+        self.find(index).and_then(|found| found.ok())
+    }
+
+    // Checks if a value or an object exists at a specific index in any layer.
+    pub fn contains_key(&self, index: &str) -> bool {
+        self.find(index).is_some()
+    }
+
+    // Returns the object at a given key index, from the last layer that has it
+    pub(crate) fn get_object(&self, index: &str) -> Option<&dyn Object> {
+        self.find(index).and_then(|found| found.err())
+    }
+
+    // Finds the value or the object at a key index in the last layer that has the key
+    fn find(&self, index: &str) -> Option<Result<&Value, &dyn Object>> {
+        self.layers.iter().rev().find_map(|layer| match layer {
+            Layer::Context(context) => match context.get(index) {
+                Some(val) => Some(Ok(val)),
+                None => context.get_object(index).map(Err),
+            },
+            Layer::Values(values) => values.get(index).map(|val| Ok(&**val)),
+            Layer::Layered(context) => context.find(index),
+        })
+    }
+
+    // Returns the value at a dotted path like `cart.items`, an object resolving the first key
+    pub(crate) fn get_dotted(&self, path: &str) -> Option<Cow<'_, Value>> {
+        let (root, rest) = path.split_once('.').unwrap_or((path, ""));
+        match self.find(root)? {
+            Ok(val) => dotted_pointer(val, rest).map(Cow::Borrowed),
+            Err(object) => object_pointer(object, rest).map(Cow::Owned),
+        }
+    }
+
+    // Inserts a value at a dotted path like `cart.items` on top of the layers, the value at the
+    // root of the path is copied from the layer it comes from.
+    // Returns `false` if something on the path exists but isn't an object
+    pub(crate) fn insert_dotted(&mut self, path: &str, val: Value) -> bool {
+        let root = path.split('.').next().unwrap();
+        let mut context = Context::new();
+        let root_val = match self.find(root) {
+            Some(Ok(root_val)) => Some(root_val.clone()),
+            Some(Err(object)) => Some(object.to_value()),
+            None => None,
+        };
+        if let Some(root_val) = root_val {
+            context.data.insert(root.to_string(), root_val);
+        }
+        if !context.insert_dotted(path, val) {
+            return false;
+        }
+
+        let root_val = context.data.remove(root).unwrap();
+        self.values_layer().insert(root.to_string(), Cow::Owned(root_val));
+        true
+    }
+
+    // Copies the values of all the layers in a JSON map, the ones of the last layers
+    // replacing the others
+    pub(crate) fn copy_to_json(&self, map: &mut Map<String, Value>) {
+        for layer in &self.layers {
+            match layer {
+                Layer::Context(context) => context.copy_to_json(map),
+                Layer::Values(values) => {
+                    for (key, val) in values {
+                        map.insert(key.clone(), val.clone().into_owned());
+                    }
+                }
+                Layer::Layered(layered) => layered.copy_to_json(map),
+            }
+        }
+    }
+
+    // The layer values are inserted in, added on top of the contexts pushed so far
+    fn values_layer(&mut self) -> &mut BTreeMap<String, Cow<'a, Value>> {
+        if !matches!(self.layers.last(), Some(Layer::Values(_))) {
+            self.layers.push(Layer::Values(BTreeMap::new()));
+        }
+        match self.layers.last_mut() {
+            Some(Layer::Values(values)) => values,
+            _ => unreachable!("A values layer was just pushed"),
+        }
+    }
+}
+
+impl<'a> From<&'a Context> for LayeredContext<'a> {
+    fn from(context: &'a Context) -> Self {
+        LayeredContext { layers: vec![Layer::Context(context)] }
+    }
+}
+
 pub trait ValueRender {
     fn render(&self, write: &mut impl Write) -> std::io::Result<()>;
 }
@@ -150,6 +417,14 @@ impl ValueRender for Value {
                 } else if let Some(v) = i.as_u64() {
                     write!(write, "{}", v)
                 } else if let Some(v) = i.as_f64() {
+                    // Decimals computed in the decimal math mode keep all their digits
+                    #[cfg(feature = "decimal")]
+                    {
+                        let digits = i.to_string();
+                        if !digits.contains(['e', 'E']) {
+                            return write!(write, "{}", digits);
+                        }
+                    }
                     write!(write, "{}", v)
                 } else {
                     unreachable!()
@@ -357,14 +632,31 @@ pub fn dotted_pointer<'a>(value: &'a Value, pointer: &str) -> Option<&'a Value>
         return Some(value);
     }
 
-    PointerMachina::new(pointer).map(|mat| mat.replace("~1", "/").replace("~0", "~")).try_fold(
-        value,
-        |target, token| match target {
-            Value::Object(map) => map.get(&token),
-            Value::Array(list) => parse_index(&token).and_then(|x| list.get(x)),
-            _ => None,
-        },
-    )
+    follow_pointer(value, pointer_tokens(pointer))
+}
+
+// Lookups a dotted path in an object: only the first key is resolved by the object, the rest
+// of the path is looked up in the value it returns
+pub(crate) fn object_pointer(object: &dyn Object, pointer: &str) -> Option<Value> {
+    if pointer.is_empty() {
+        return Some(object.to_value());
+    }
+
+    let mut tokens = pointer_tokens(pointer);
+    let value = object.get(&tokens.next()?)?;
+    follow_pointer(&value, tokens).cloned()
+}
+
+fn pointer_tokens(pointer: &str) -> impl Iterator<Item = String> + '_ {
+    PointerMachina::new(pointer).map(|mat| mat.replace("~1", "/").replace("~0", "~"))
+}
+
+fn follow_pointer(value: &Value, mut tokens: impl Iterator<Item = String>) -> Option<&Value> {
+    tokens.try_fold(value, |target, token| match target {
+        Value::Object(map) => map.get(&token),
+        Value::Array(list) => parse_index(&token).and_then(|x| list.get(x)),
+        _ => None,
+    })
 }
 
 // serde jsons parse_index
@@ -509,4 +801,37 @@ mod tests {
         let mut context = Context::new();
         assert_eq!(context.remove("unknown"), None);
     }
+
+    #[test]
+    fn layered_context_looks_up_from_the_last_layer() {
+        let mut site = Context::new();
+        site.insert("title", "Site");
+        site.insert("lang", "en");
+        let mut page = Context::new();
+        page.insert("title", "Page");
+        let user = json!({"name": "Bob"});
+
+        let mut context = LayeredContext::from(&site);
+        context.insert_ref("user", &user);
+        context.push(&page);
+        context.insert("lang", "fr");
+
+        assert_eq!(context.get("title"), Some(&json!("Page")));
+        assert_eq!(context.get("lang"), Some(&json!("fr")));
+        assert_eq!(context.get_dotted("user.name").as_deref(), Some(&json!("Bob")));
+        assert!(!context.contains_key("missing"));
+        assert_eq!(site.get("lang"), Some(&json!("en")));
+    }
+
+    #[test]
+    fn layered_context_insert_dotted_copies_the_root() {
+        let mut base = Context::new();
+        base.insert("user", &json!({"name": "Bob"}));
+        let mut context = LayeredContext::from(&base);
+
+        assert!(context.insert_dotted("user.locale", json!("en")));
+        assert_eq!(context.get("user"), Some(&json!({"name": "Bob", "locale": "en"})));
+        assert_eq!(base.get("user"), Some(&json!({"name": "Bob"})));
+        assert!(!context.insert_dotted("user.name.first", json!("Bob")));
+    }
 }
@@ -1,6 +1,9 @@
 use std::convert::Into;
 use std::error::Error as StdError;
 use std::fmt;
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::time::Duration;
 
 // The kind of an error (non-exhaustive)
 #[derive(Debug)]
@@ -42,6 +45,13 @@ pub enum ErrorKind {
     CallTest(String),
     // An IO error occured
     Io(std::io::ErrorKind),
+    // Two values of types that can't be ordered were compared with `<`, `<=`, `>` or `>=`
+    InvalidComparison {
+        // The type of the left side, eg `a string`
+        lhs: String,
+        // The type of the right side
+        rhs: String,
+    },
     // UTF-8 conversion error
     ///
     // This should not occur unless invalid UTF8 chars are rendered
@@ -49,6 +59,22 @@ pub enum ErrorKind {
         // The context that indicates where the error occurs in the rendering process
         context: String,
     },
+    // The render wrote more bytes than `RenderLimits::max_output_bytes`
+    OutputLimitExceeded(usize),
+    // A string or an array built while rendering was bigger than `RenderLimits::max_value_bytes`
+    ValueLimitExceeded(usize),
+    // The loops of the render did more iterations than `RenderLimits::max_loop_iterations`
+    LoopIterationLimitExceeded(usize),
+    // Macro calls and includes were nested deeper than `RenderLimits::max_call_depth`
+    CallDepthLimitExceeded(usize),
+    // The render evaluated more nodes and expressions than `RenderLimits::max_fuel`
+    FuelExhausted(u64),
+    // The render took longer than `RenderLimits::timeout`
+    Timeout(Duration),
+    // An async render stopped to await an async function or filter, it is rendered again once
+    // the call is done. `Lysine::render_async` never returns it
+    #[cfg(feature = "async")]
+    AsyncCallPending,
     // This enum may grow additional variants, so this makes sure clients
     // don't count on exhaustive matching. (Otherwise, adding a new variant
     // could break existing code.)
@@ -92,9 +118,32 @@ impl fmt::Display for Error {
             ErrorKind::Io(ref io_error) => {
                 write!(f, "Io error while writing rendered value to output: {:?}", io_error)
             }
+            ErrorKind::InvalidComparison { ref lhs, ref rhs } => {
+                write!(f, "Tried to compare {} with {}", lhs, rhs)
+            }
             ErrorKind::Utf8Conversion { ref context } => {
                 write!(f, "UTF-8 conversion error occured while rendering template: {}", context)
             }
+            ErrorKind::OutputLimitExceeded(max) => {
+                write!(f, "The rendered output exceeded the limit of {} bytes", max)
+            }
+            ErrorKind::ValueLimitExceeded(max) => {
+                write!(f, "A value built while rendering exceeded the limit of {} bytes", max)
+            }
+            ErrorKind::LoopIterationLimitExceeded(max) => {
+                write!(f, "Loops exceeded the limit of {} iterations", max)
+            }
+            ErrorKind::CallDepthLimitExceeded(max) => {
+                write!(f, "Macro calls and includes exceeded the maximum depth of {}", max)
+            }
+            ErrorKind::FuelExhausted(max) => {
+                write!(f, "The render exceeded the limit of {} evaluations", max)
+            }
+            ErrorKind::Timeout(timeout) => {
+                write!(f, "The render took longer than the limit of {:?}", timeout)
+            }
+            #[cfg(feature = "async")]
+            ErrorKind::AsyncCallPending => write!(f, "The render is waiting for an async call"),
             ErrorKind::__Nonexhaustive => write!(f, "Nonexhaustive"),
         }
     }
@@ -195,6 +244,77 @@ impl Error {
         Self { kind: ErrorKind::Io(error.kind()), source: Some(Box::new(error)) }
     }
 
+    // Creates an error for a render going over one of its `RenderLimits`
+    pub(crate) fn limit_exceeded(kind: ErrorKind) -> Self {
+        Self { kind, source: None }
+    }
+
+    // Creates the error stopping an async render at a call that wasn't awaited yet
+    #[cfg(feature = "async")]
+    pub(crate) fn async_call_pending() -> Self {
+        Self { kind: ErrorKind::AsyncCallPending, source: None }
+    }
+
+    // Whether the error, or one of its sources, stopped an async render at a call that wasn't
+    // awaited yet. Always `false` without the `async` feature
+    pub(crate) fn is_async_call_pending(&self) -> bool {
+        #[cfg(feature = "async")]
+        {
+            self.has_kind(|kind| matches!(kind, ErrorKind::AsyncCallPending))
+        }
+        #[cfg(not(feature = "async"))]
+        false
+    }
+
+    // Whether the error, or one of its sources, is a render going over its `RenderLimits`
+    #[cfg(feature = "async")]
+    pub(crate) fn is_limit_exceeded(&self) -> bool {
+        self.has_kind(|kind| {
+            matches!(
+                kind,
+                ErrorKind::OutputLimitExceeded(_)
+                    | ErrorKind::ValueLimitExceeded(_)
+                    | ErrorKind::LoopIterationLimitExceeded(_)
+                    | ErrorKind::CallDepthLimitExceeded(_)
+                    | ErrorKind::FuelExhausted(_)
+                    | ErrorKind::Timeout(_)
+            )
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn has_kind(&self, is_kind: impl Fn(&ErrorKind) -> bool) -> bool {
+        let mut error: Option<&(dyn StdError + 'static)> = Some(self);
+        while let Some(e) = error {
+            if e.downcast_ref::<Error>().is_some_and(|e| is_kind(&e.kind)) {
+                return true;
+            }
+            error = e.source();
+        }
+        false
+    }
+
+    // Copies an error kept for all the places getting it, like the result of an async call
+    // used twice: the copy has the same message and sources
+    #[cfg(feature = "async")]
+    pub(crate) fn copy_shared(error: &Arc<Error>) -> Self {
+        Self {
+            kind: ErrorKind::Msg(error.to_string()),
+            source: error
+                .source
+                .as_ref()
+                .map(|_| Box::new(SharedSource(error.clone())) as Box<dyn StdError + Sync + Send>),
+        }
+    }
+
+    // Creates an invalid comparison error
+    pub fn invalid_comparison(lhs: impl ToString, rhs: impl ToString) -> Self {
+        Self {
+            kind: ErrorKind::InvalidComparison { lhs: lhs.to_string(), rhs: rhs.to_string() },
+            source: None,
+        }
+    }
+
     // Creates an utf8 conversion error
     pub fn utf8_conversion_error(error: std::string::FromUtf8Error, context: String) -> Self {
         Self { kind: ErrorKind::Utf8Conversion { context }, source: Some(Box::new(error)) }
@@ -203,6 +323,10 @@ impl Error {
 
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
+        // Writers can fail with one of our errors, eg when the output limit is reached
+        if error.get_ref().is_some_and(|e| e.is::<Error>()) {
+            return *error.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
         Self::io_error(error)
     }
 }
@@ -221,6 +345,28 @@ impl From<serde_json::Error> for Error {
         Self::json(e)
     }
 }
+// The source of an error copied with `Error::copy_shared`
+#[cfg(feature = "async")]
+#[derive(Debug)]
+struct SharedSource(Arc<Error>);
+
+#[cfg(feature = "async")]
+impl fmt::Display for SharedSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.source {
+            Some(ref source) => source.fmt(f),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl StdError for SharedSource {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.source.as_ref().and_then(|source| source.source())
+    }
+}
+
 // Convenient wrapper around std::Result.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
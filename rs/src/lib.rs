@@ -6,16 +6,22 @@ mod errors;
 mod filter_utils;
 mod parser;
 mod renderer;
+mod sandbox;
 mod template;
 mod lysine;
+mod object;
 mod utils;
 
 // Library exports.
 
+#[cfg(feature = "async")]
+pub use crate::builtins::filters::AsyncFilter;
 pub use crate::builtins::filters::Filter;
+#[cfg(feature = "async")]
+pub use crate::builtins::functions::{AsyncFunction, AsyncResult};
 pub use crate::builtins::functions::Function;
 pub use crate::builtins::testers::Test;
-pub use crate::context::Context;
+pub use crate::context::{Context, LayeredContext};
 pub use crate::errors::{Error, ErrorKind, Result};
 // Template, dotted_pointer and get_json_pointer are meant to be used internally only but is exported for test/bench.
 
@@ -24,8 +30,11 @@ pub use crate::context::dotted_pointer;
 #[allow(deprecated)]
 pub use crate::context::get_json_pointer;
 
+pub use crate::parser::WhitespaceOptions;
 pub use crate::template::Template;
-pub use crate::lysine::Lysine;
+pub use crate::lysine::{Lysine, RenderLimits, UndefinedBehavior};
+pub use crate::object::Object;
+pub use crate::sandbox::{Access, Sandbox};
 pub use crate::utils::escape_html;
 // Re-export Value and other useful things from serde
 // so apps/tools can encode data in Lysine types
@@ -1,27 +1,92 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::iter;
+use std::mem;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use globwalk::glob_builder;
+use serde::Serialize;
+use serde_json::value::Value;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+#[cfg(feature = "async")]
+use crate::builtins::filters::AsyncFilter;
 use crate::builtins::filters::{array, common, number, object, string, Filter};
+#[cfg(feature = "async")]
+use crate::builtins::functions::AsyncFunction;
 use crate::builtins::functions::{self, Function};
 use crate::builtins::testers::{self, Test};
-use crate::context::Context;
+use crate::context::{Context, LayeredContext};
 use crate::errors::{Error, Result};
+use crate::parser::ast::Block;
+use crate::parser::WhitespaceOptions;
+#[cfg(feature = "async")]
+use crate::renderer::AsyncCalls;
 use crate::renderer::Renderer;
+use crate::sandbox::Sandbox;
 use crate::template::Template;
+#[cfg(feature = "async")]
+use crate::utils::buffer_to_string;
 use crate::utils::escape_html;
 
 // Default template name used for `Lysine::render_str` and `Lysine::one_off`.
 const ONE_OFF_TEMPLATE_NAME: &str = "__lysine_one_off";
 
+// Default number of iterations after which a `{% while %}` loop errors
+const DEFAULT_MAX_WHILE_ITERATIONS: usize = 10_000;
+
+// The functions implemented by the renderer itself, unless a function is registered with their name
+const RENDERER_FUNCTIONS: [&str; 2] = ["block", "include"];
+
 // The escape function type definition
 pub type EscapeFn = fn(&str) -> String;
 
+// What happens when a template uses a variable that isn't in the context
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UndefinedBehavior {
+    // Using an undefined variable is an error, unless it is negated or has a `default` filter
+    #[default]
+    Strict,
+    // An undefined variable renders as nothing and is falsy. Accessing an attribute of an
+    // undefined variable is still an error
+    Lenient,
+    // Like `Lenient` but accessing an attribute of an undefined variable, eg `a.b.c` when `a`
+    // is missing, is undefined as well
+    Chainable,
+    // Like `Chainable` but an undefined variable rendered in `{{ }}` writes a marker like
+    // `{{ missing: user.naem }}` so it can be spotted in the output
+    Debug,
+}
+
+// Limits on the resources a single render can use, eg when rendering templates written by users.
+// A render going over one of them errors with its own `ErrorKind`. Every limit is disabled by
+// default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderLimits {
+    // How many bytes can be written to the output
+    pub max_output_bytes: Option<usize>,
+    // How many bytes a single string or array built while rendering can use, like the output of
+    // macros and `include()`, concatenations, repeated strings or arrays added together. The
+    // size of an array is the size of its JSON
+    pub max_value_bytes: Option<usize>,
+    // How many iterations all the `{% for %}` and `{% while %}` loops can do in total. A
+    // `range()` or the items of an object longer than the iterations left error before being
+    // built
+    pub max_loop_iterations: Option<usize>,
+    // How deep macro calls and includes can be nested
+    pub max_call_depth: Option<usize>,
+    // How many nodes and expressions can be evaluated. An async render uses it for all its
+    // passes, see [`Lysine::render_async`]
+    pub max_fuel: Option<u64>,
+    // How long the render can take
+    pub timeout: Option<Duration>,
+}
+
 // Main point of inlysinection in this library.
 ///
 // The [`Lysine`] struct is the primary interface for working with the Lysine template engine. It contains parsed templates, registered filters (which can filter
@@ -70,12 +135,32 @@ pub struct Lysine {
     pub testers: HashMap<String, Arc<dyn Test>>,
     
     pub functions: HashMap<String, Arc<dyn Function>>,
+    // The filters and functions awaited by `Lysine::render_async`
+    #[cfg(feature = "async")]
+    pub async_filters: HashMap<String, Arc<dyn AsyncFilter>>,
+    #[cfg(feature = "async")]
+    pub async_functions: HashMap<String, Arc<dyn AsyncFunction>>,
     // Which extensions does Lysine automatically autoescape on.
     // Defaults to [".html", ".htm", ".xml"]
     
     pub autoescape_suffixes: Vec<&'static str>,
     
     escape_fn: EscapeFn,
+    // The whitespace rules applied when parsing templates
+    whitespace: WhitespaceOptions,
+    // How many iterations a `{% while %}` loop can do before the render errors
+    pub(crate) max_while_iterations: usize,
+    // Values visible to all templates, with a lower priority than the context of a render
+    pub(crate) globals: Context,
+    // What happens when a template uses a variable that isn't in the context
+    pub(crate) undefined_behavior: UndefinedBehavior,
+    // The resources a render can use
+    pub(crate) render_limits: RenderLimits,
+    // What the templates are allowed to use, checked when they are loaded and when rendering
+    pub(crate) sandbox: Option<Sandbox>,
+    // Whether math on non-integers uses arbitrary-precision decimals instead of floats
+    #[cfg(feature = "decimal")]
+    pub(crate) decimal_math: bool,
 }
 
 impl Lysine {
@@ -93,8 +178,20 @@ impl Lysine {
             filters: HashMap::new(),
             functions: HashMap::new(),
             testers: HashMap::new(),
+            #[cfg(feature = "async")]
+            async_filters: HashMap::new(),
+            #[cfg(feature = "async")]
+            async_functions: HashMap::new(),
             autoescape_suffixes: vec![".html", ".htm", ".xml"],
             escape_fn: escape_html,
+            whitespace: WhitespaceOptions::default(),
+            max_while_iterations: DEFAULT_MAX_WHILE_ITERATIONS,
+            globals: Context::new(),
+            undefined_behavior: UndefinedBehavior::Strict,
+            render_limits: RenderLimits::default(),
+            sandbox: None,
+            #[cfg(feature = "decimal")]
+            decimal_math: false,
         };
 
         lysine.load_from_glob()?;
@@ -245,10 +342,24 @@ impl Lysine {
         f.read_to_string(&mut input)
             .map_err(|e| Error::chain(format!("Failed to read template '{:?}'", path), e))?;
 
-        let tpl = Template::new(tpl_name, Some(path.to_str().unwrap().to_string()), &input)
-            .map_err(|e| Error::chain(format!("Failed to parse {:?}", path), e))?;
+        let tpl = Template::new_with_options(
+            tpl_name,
+            Some(path.to_str().unwrap().to_string()),
+            &input,
+            &self.whitespace,
+        )
+        .map_err(|e| Error::chain(format!("Failed to parse {:?}", path), e))?;
+
+        self.insert_template(tpl)
+    }
 
-        self.templates.insert(tpl_name.to_string(), tpl);
+    // Adds a parsed template, after checking it against the sandbox if there is one
+    fn insert_template(&mut self, tpl: Template) -> Result<()> {
+        if let Some(ref sandbox) = self.sandbox {
+            sandbox.check(&tpl)?;
+        }
+
+        self.templates.insert(tpl.name.clone(), tpl);
         Ok(())
     }
 
@@ -286,14 +397,9 @@ impl Lysine {
             }
         }
 
-        // TODO: if we can rewrite the 2 loops below to be only one loop, that'd be great
-        let mut tpl_parents = HashMap::new();
-        let mut tpl_block_definitions = HashMap::new();
-        for (name, template) in &self.templates {
-            if template.parent.is_none() && template.blocks.is_empty() {
-                continue;
-            }
-
+        // The parents and the definitions of the blocks of a template
+        type Chain = (Vec<String>, HashMap<String, Vec<(String, Block)>>);
+        let build_chain_and_blocks = |template: &Template| -> Result<Chain> {
             let parents = build_chain(&self.templates, template, template, vec![])?;
 
             let mut blocks_definitions = HashMap::new();
@@ -311,29 +417,91 @@ impl Lysine {
                 }
                 blocks_definitions.insert(block_name.clone(), definitions);
             }
-            tpl_parents.insert(name.clone(), parents);
-            tpl_block_definitions.insert(name.clone(), blocks_definitions);
+            Ok((parents, blocks_definitions))
+        };
+
+        // TODO: if we can rewrite the 2 loops below to be only one loop, that'd be great
+        // The chains of the hidden templates of the embeds are kept apart, keyed by the name of
+        // the template they belong to and their index, as their names aren't reserved
+        let mut tpl_chains = HashMap::new();
+        let mut embed_chains = HashMap::new();
+        for (name, template) in &self.templates {
+            for (index, embed) in template.embeds.iter().enumerate() {
+                embed_chains.insert((name.clone(), index), build_chain_and_blocks(embed)?);
+            }
+
+            if template.parent.is_none() && template.blocks.is_empty() {
+                continue;
+            }
+            tpl_chains.insert(name.clone(), build_chain_and_blocks(template)?);
+        }
+
+        // Templates at the bottom of a hierarchy need to override all the required blocks of
+        // their parents. The ones in the middle of it can leave that to their own children.
+        // Nothing extends the hidden templates of the embeds
+        let all_templates = || self.templates.values().flat_map(|t| iter::once(t).chain(&t.embeds));
+        let extended: HashSet<&String> =
+            all_templates().filter_map(|t| t.parent.as_ref()).collect();
+        let embeds = embed_chains
+            .iter()
+            .map(|((name, index), chain)| (&self.templates[name].embeds[*index], &chain.0));
+        let bottom_templates = tpl_chains
+            .iter()
+            .filter(|(name, _)| !extended.contains(name))
+            .map(|(name, chain)| (&self.templates[name], &chain.0))
+            .chain(embeds);
+        for (template, parents) in bottom_templates {
+            if parents.is_empty() {
+                continue;
+            }
+
+            let mut seen = HashSet::new();
+            let parents = parents.iter().map(|name| self.get_template(name));
+            for tpl in iter::once(Ok(template)).chain(parents) {
+                let tpl = tpl?;
+                for (block_name, block) in &tpl.blocks {
+                    if !seen.insert(block_name) {
+                        continue;
+                    }
+                    if block.required {
+                        return Err(Error::msg(format!(
+                            "Template `{}` needs to override the block `{}` marked as required in `{}`",
+                            template.name, block_name, tpl.name
+                        )));
+                    }
+                }
+            }
         }
 
         for template in self.templates.values_mut() {
+            for (index, embed) in template.embeds.iter_mut().enumerate() {
+                let (parents, blocks_definitions) =
+                    embed_chains.remove(&(template.name.clone(), index)).unwrap();
+                embed.parents = parents;
+                embed.blocks_definitions = blocks_definitions;
+            }
+
             // Simple template: no inheritance or blocks -> nothing to do
             if template.parent.is_none() && template.blocks.is_empty() {
                 continue;
             }
 
-            template.parents = match tpl_parents.remove(&template.name) {
-                Some(parents) => parents,
-                None => vec![],
-            };
-            template.blocks_definitions = match tpl_block_definitions.remove(&template.name) {
-                Some(blocks) => blocks,
-                None => HashMap::new(),
-            };
+            (template.parents, template.blocks_definitions) =
+                tpl_chains.remove(&template.name).unwrap_or_default();
         }
 
         Ok(())
     }
 
+    // Finds the hidden template of an embed, it belongs to the template the embed is in
+    pub(crate) fn get_embed(&self, embed_name: &str) -> Result<&Template> {
+        embed_name
+            .rsplit_once(":embed:")
+            .and_then(|(tpl_name, _)| self.templates.get(tpl_name))
+            .and_then(|tpl| tpl.embeds.iter().find(|embed| embed.name == embed_name))
+            .ok_or_else(|| Error::template_not_found(embed_name))
+    }
+
     // We keep track of macro files loaded in each Template so we can know whether one or them
     // is missing and error accordingly before the user tries to render a template.
     ///
@@ -348,6 +516,25 @@ impl Lysine {
                     )));
                 }
             }
+
+            for (_, tpl_name, macro_name, _) in &template.imported_macros {
+                match self.templates.get(tpl_name) {
+                    Some(macro_tpl) => {
+                        if !macro_tpl.macros.contains_key(macro_name) {
+                            return Err(Error::msg(format!(
+                                "Template `{}` imports the macro `{}` from `{}` but it isn't defined there",
+                                template.name, macro_name, tpl_name
+                            )));
+                        }
+                    }
+                    None => {
+                        return Err(Error::msg(format!(
+                            "Template `{}` loads macros from `{}` which isn't present in Lysine",
+                            template.name, tpl_name
+                        )));
+                    }
+                }
+            }
         }
 
         Ok(())
@@ -427,6 +614,240 @@ impl Lysine {
         renderer.render_to(write)
     }
 
+    // Renders a Lysine template given a [`LayeredContext`], which borrows its data so the same
+    // large data can be used for many renders without being cloned.
+    //
+    // # Examples
+    //
+    // ```
+    // # use lysine::{Context, LayeredContext, Lysine};
+    // let mut lysine = Lysine::default();
+    // lysine.add_raw_template("page", "{{ title }} - {{ site_name }}").unwrap();
+    //
+    // let mut site = Context::new();
+    // site.insert("site_name", "Lysine");
+    // site.insert("title", "Untitled");
+    // for title in ["Home", "About"] {
+    //     let mut context = LayeredContext::from(&site);
+    //     context.insert("title", title);
+    //     let output = lysine.render_layered("page", &context).unwrap();
+    //     assert_eq!(output, format!("{} - Lysine", title));
+    // }
+    // ```
+    pub fn render_layered(&self, template_name: &str, context: &LayeredContext) -> Result<String> {
+        let template = self.get_template(template_name)?;
+        let renderer = Renderer::new_layered(template, self, context);
+        renderer.render()
+    }
+
+    // Renders a Lysine template given a [`LayeredContext`] to something that implements
+    // [`Write`], see [`render_layered()`](Self::render_layered)
+    pub fn render_layered_to(
+        &self,
+        template_name: &str,
+        context: &LayeredContext,
+        write: impl Write,
+    ) -> Result<()> {
+        let template = self.get_template(template_name)?;
+        let renderer = Renderer::new_layered(template, self, context);
+        renderer.render_to(write)
+    }
+
+    // Renders a Lysine template given a [`Context`] to a [`Value`], to produce structured data
+    // without parsing the rendered string back.
+    //
+    // When the output of the template is a single `{{ }}`, the value of its expression is returned
+    // with its type kept. Whitespace around it and tags that don't output anything, like
+    // `{% set %}` or comments, are ignored. Any other template is rendered to a string.
+    //
+    // # Examples
+    //
+    // ```
+    // # use lysine::{Context, Lysine, Value};
+    // let mut lysine = Lysine::default();
+    // lysine.add_raw_templates(vec![
+    //     ("port", "{% set base = 8000 %}\n{{ base + offset }}\n"),
+    //     ("url", "http://localhost:{{ 8000 + offset }}"),
+    // ]).unwrap();
+    //
+    // let mut context = Context::new();
+    // context.insert("offset", &80);
+    // assert_eq!(lysine.render_value("port", &context).unwrap(), Value::from(8080));
+    // assert_eq!(
+    //     lysine.render_value("url", &context).unwrap(),
+    //     Value::from("http://localhost:8080")
+    // );
+    // ```
+    pub fn render_value(&self, template_name: &str, context: &Context) -> Result<Value> {
+        let template = self.get_template(template_name)?;
+        let renderer = Renderer::new(template, self, context);
+        renderer.render_value()
+    }
+
+    // Renders a Lysine template given a [`Context`] with another [`UndefinedBehavior`] than the
+    // one set with [`undefined_behavior()`](Self::undefined_behavior), eg to preview pages
+    // with an incomplete context.
+    //
+    // # Examples
+    //
+    // ```
+    // # use lysine::{Context, Lysine, UndefinedBehavior};
+    // let mut lysine = Lysine::default();
+    // lysine.add_raw_template("hello", "Hello {{ user.naem }}!").unwrap();
+    //
+    // let mut context = Context::new();
+    // context.insert("user", &serde_json::json!({"name": "Bob"}));
+    // let result = lysine.render_with_undefined_behavior("hello", &context, UndefinedBehavior::Debug);
+    // assert_eq!(result.unwrap(), "Hello {{ missing: user.naem }}!");
+    // assert!(lysine.render("hello", &context).is_err());
+    // ```
+    pub fn render_with_undefined_behavior(
+        &self,
+        template_name: &str,
+        context: &Context,
+        undefined_behavior: UndefinedBehavior,
+    ) -> Result<String> {
+        let template = self.get_template(template_name)?;
+        let renderer =
+            Renderer::new(template, self, context).with_undefined_behavior(undefined_behavior);
+        renderer.render()
+    }
+
+    // Renders a single block of a template given a [`Context`], eg for partial page updates.
+    //
+    // The block is rendered as it would be in the whole page: with the definition from the
+    // template at the bottom of the inheritance chain, `super()` and the macros of the template.
+    // Only what is outside of the block is skipped, including the `{% set %}` done there.
+    //
+    // # Examples
+    //
+    // ```
+    // # use lysine::{Context, Lysine};
+    // let mut lysine = Lysine::default();
+    // lysine.add_raw_templates(vec![
+    //     ("base.html", "<main>{% block results %}{% endblock results %}</main>"),
+    //     ("search.html", "{% extends "base.html" %}{% block results %}{{ count }} results{% endblock results %}"),
+    // ]).unwrap();
+    //
+    // let mut context = Context::new();
+    // context.insert("count", &3);
+    // let output = lysine.render_block("search.html", "results", &context).unwrap();
+    // assert_eq!(output, "3 results");
+    // ```
+    pub fn render_block(
+        &self,
+        template_name: &str,
+        block_name: &str,
+        context: &Context,
+    ) -> Result<String> {
+        let template = self.get_template(template_name)?;
+        let renderer = Renderer::new(template, self, context);
+        renderer.render_block(block_name)
+    }
+
+    // Renders a single block of a template given a [`Context`] to something that implements
+    // [`Write`], see [`render_block()`](Self::render_block)
+    pub fn render_block_to(
+        &self,
+        template_name: &str,
+        block_name: &str,
+        context: &Context,
+        write: impl Write,
+    ) -> Result<()> {
+        let template = self.get_template(template_name)?;
+        let renderer = Renderer::new(template, self, context);
+        renderer.render_block_to(block_name, write)
+    }
+
+    // Renders a macro defined in a template with the arguments given by name. Escaping is done depending on the name of the template, like for a render.
+    //
+    // The macro only sees its arguments, the same as when called from a template.
+    //
+    // # Examples
+    //
+    // ```
+    // # use std::collections::HashMap;
+    // # use lysine::{Lysine, Value};
+    // let mut lysine = Lysine::default();
+    // lysine.add_raw_template(
+    //     "forms.html",
+    //     "{% macro input(name, type=\"text\") %}<input name=\"{{ name }}\" type=\"{{ type }}\">{% endmacro input %}",
+    // ).unwrap();
+    //
+    // let mut args = HashMap::new();
+    // args.insert("name".to_string(), Value::from("email"));
+    // let output = lysine.call_macro("forms.html", "input", &args).unwrap();
+    // assert_eq!(output, "<input name=\"email\" type=\"text\">");
+    // ```
+    pub fn call_macro(
+        &self,
+        template_name: &str,
+        macro_name: &str,
+        args: &HashMap<String, Value>,
+    ) -> Result<String> {
+        let template = self.get_template(template_name)?;
+        let context = Context::new();
+        let renderer = Renderer::new(template, self, &context);
+        renderer.render_macro(macro_name, args)
+    }
+
+    // Renders a Lysine template given a [`Context`], awaiting the async functions and filters
+    // registered with [`register_async_function`](Self::register_async_function) and
+    // [`register_async_filter`](Self::register_async_filter). The sync ones work the same as
+    // with [`render()`](Self::render).
+    //
+    // The processor itself is synchronous: a pass of the render starts the async calls it meets
+    // that haven't been awaited yet, skipping what needs their results, and they are all awaited
+    // together before the next pass. The results are cached by name and arguments, errors
+    // included. A render does one pass more than its longest chain of async calls needing the
+    // result of the previous one, eg 2 for a loop calling a function for each item. The sync
+    // functions are called again on each pass. The fuel and the timeout of the [`RenderLimits`]
+    // are for all the passes together.
+    //
+    // # Examples
+    //
+    // ```ignore
+    // let mut lysine = Lysine::default();
+    // lysine.register_async_function("get_user", |args: HashMap<String, Value>| async move {
+    //     let user = cache.get(&args["id"]).await;
+    //     Ok(to_value(user).unwrap())
+    // });
+    // lysine.add_raw_template("profile", "{{ get_user(id=1).name }}").unwrap();
+    // let output = lysine.render_async("profile", &Context::new()).await.unwrap();
+    // ```
+    #[cfg(feature = "async")]
+    pub async fn render_async(&self, template_name: &str, context: &Context) -> Result<String> {
+        let mut output = Vec::with_capacity(2000);
+        self.render_to_async(template_name, context, &mut output).await?;
+        buffer_to_string(|| "converting rendered buffer to string".to_string(), output)
+    }
+
+    // Same as [`render_async()`](Self::render_async) but writes to an [`AsyncWrite`]. Nothing is
+    // written until the whole template is rendered.
+    #[cfg(feature = "async")]
+    pub async fn render_to_async(
+        &self,
+        template_name: &str,
+        context: &Context,
+        mut write: impl AsyncWrite + Unpin,
+    ) -> Result<()> {
+        let template = self.get_template(template_name)?;
+        let mut async_calls = AsyncCalls::default();
+
+        loop {
+            let mut output = Vec::with_capacity(2000);
+            let res = Renderer::new(template, self, context)
+                .render_to_with_async_calls(&mut output, &mut async_calls);
+
+            if !async_calls.await_pending().await {
+                res?;
+                write.write_all(&output).await?;
+                write.flush().await?;
+                return Ok(());
+            }
+        }
+    }
+
     // Renders a one off template (for example a template coming from a user
     // input) given a `Context` and an instance of Lysine. This allows you to
     // render templates using custom filters or functions.
@@ -520,9 +941,9 @@ impl Lysine {
     // lysine.add_raw_template("new.html", "Blabla").unwrap();
     // ```
     pub fn add_raw_template(&mut self, name: &str, content: &str) -> Result<()> {
-        let tpl = Template::new(name, None, content)
+        let tpl = Template::new_with_options(name, None, content, &self.whitespace)
             .map_err(|e| Error::chain(format!("Failed to parse '{}'", name), e))?;
-        self.templates.insert(name.to_string(), tpl);
+        self.insert_template(tpl)?;
         self.build_inheritance_chains()?;
         self.check_macro_files()?;
         Ok(())
@@ -547,9 +968,9 @@ impl Lysine {
     {
         for (name, content) in templates {
             let name = name.as_ref();
-            let tpl = Template::new(name, None, content.as_ref())
+            let tpl = Template::new_with_options(name, None, content.as_ref(), &self.whitespace)
                 .map_err(|e| Error::chain(format!("Failed to parse '{}'", name), e))?;
-            self.templates.insert(name.to_string(), tpl);
+            self.insert_template(tpl)?;
         }
         self.build_inheritance_chains()?;
         self.check_macro_files()?;
@@ -657,11 +1078,22 @@ impl Lysine {
         }
     }
 
+    // Whether calling `fn_name` runs a function of the renderer rather than a registered one
+    pub(crate) fn is_renderer_function(&self, fn_name: &str) -> bool {
+        #[cfg(feature = "async")]
+        if self.async_functions.contains_key(fn_name) {
+            return false;
+        }
+        RENDERER_FUNCTIONS.contains(&fn_name) && !self.functions.contains_key(fn_name)
+    }
+
     // Register a function with Lysine.
     ///
     // This registers an arbitrary function to make it callable from within a template. If a
     // function with that name already exists, it will be overwritten.
     ///
+    // A function named `block` or `include` shadows the one implemented by the renderer.
+    ///
     // ```no_compile
     // lysine.register_function("range", range);
     // ```
@@ -669,6 +1101,26 @@ impl Lysine {
         self.functions.insert(name.to_string(), Arc::new(function));
     }
 
+    // Register an async function with Lysine, it can only be used in templates rendered
+    // with [`Lysine::render_async`]. It takes precedence over a sync function with the same name.
+    //
+    // ```ignore
+    // lysine.register_async_function("get_user", |args: HashMap<String, Value>| async move {
+    //     load_user(&args["id"]).await
+    // });
+    // ```
+    #[cfg(feature = "async")]
+    pub fn register_async_function<F: AsyncFunction + 'static>(&mut self, name: &str, function: F) {
+        self.async_functions.insert(name.to_string(), Arc::new(function));
+    }
+
+    // Register an async filter with Lysine, it can only be used in templates rendered
+    // with [`Lysine::render_async`]. It takes precedence over a sync filter with the same name.
+    #[cfg(feature = "async")]
+    pub fn register_async_filter<F: AsyncFilter + 'static>(&mut self, name: &str, filter: F) {
+        self.async_filters.insert(name.to_string(), Arc::new(filter));
+    }
+
     fn register_lysine_filters(&mut self) {
         self.register_filter("upper", string::upper);
         self.register_filter("lower", string::lower);
@@ -706,7 +1158,7 @@ impl Lysine {
         self.register_filter("slice", array::slice);
         self.register_filter("group_by", array::group_by);
         self.register_filter("filter", array::filter);
-        self.register_filter("map", array::map);
+        self.register_filter("map", array::MapFilter);
         self.register_filter("concat", array::concat);
 
         self.register_filter("abs", number::abs);
@@ -743,7 +1195,7 @@ impl Lysine {
     }
 
     fn register_lysine_functions(&mut self) {
-        self.register_function("range", functions::range);
+        self.register_function("range", functions::Range);
         #[cfg(feature = "builtins")]
         self.register_function("now", functions::now);
         self.register_function("throw", functions::throw);
@@ -825,6 +1277,174 @@ impl Lysine {
         self.escape_fn = escape_html;
     }
 
+    // Remove the first newline after a `{% %}` tag or a comment. Disabled by default.
+    //
+    // Like the other whitespace settings, it only applies to the templates added after
+    // calling it or reloaded with [`Lysine::full_reload`]. A tag can opt out of it with `+%}`.
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Lysine, Context};
+    // let mut lysine = Lysine::default();
+    // lysine.trim_blocks(true);
+    // lysine.add_raw_template("list", "{% for i in [1, 2] %}\n{{ i }}\n{% endfor %}\n").unwrap();
+    // let result = lysine.render("list", &Context::new()).unwrap();
+    // assert_eq!(result, "1\n2\n");
+    // ```
+    pub fn trim_blocks(&mut self, trim_blocks: bool) {
+        self.whitespace.trim_blocks = trim_blocks;
+    }
+
+    // Remove the spaces and tabs from the start of a line up to a `{% %}` tag or a comment.
+    // Disabled by default. A tag can opt out of it with `{%+`.
+    pub fn lstrip_blocks(&mut self, lstrip_blocks: bool) {
+        self.whitespace.lstrip_blocks = lstrip_blocks;
+    }
+
+    // Whether to keep the newline at the very end of templates. Enabled by default.
+    pub fn keep_trailing_newline(&mut self, keep_trailing_newline: bool) {
+        self.whitespace.keep_trailing_newline = keep_trailing_newline;
+    }
+
+    // Set all the whitespace rules at once
+    pub fn set_whitespace_options(&mut self, options: WhitespaceOptions) {
+        self.whitespace = options;
+    }
+
+    // Add a variable visible to all templates, eg the name of the site, without inserting it in
+    // the [`Context`] of every render. A variable of the same name in the context of a render
+    // takes priority over it.
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Lysine, Context};
+    // let mut lysine = Lysine::default();
+    // lysine.add_global("site_name", "Lysine");
+    // lysine.add_raw_template("title", "{{ title }} - {{ site_name }}").unwrap();
+    //
+    // let mut context = Context::new();
+    // context.insert("title", "Home");
+    // let result = lysine.render("title", &context).unwrap();
+    // assert_eq!(result, "Home - Lysine");
+    // ```
+    pub fn add_global<T: Serialize + ?Sized, S: Into<String>>(&mut self, key: S, val: &T) {
+        self.globals.insert(key, val);
+    }
+
+    // Set how many iterations a `{% while %}` loop can do before the render errors, so a
+    // condition that never becomes false can't hang it. Defaults to 10000.
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Lysine, Context};
+    // let mut lysine = Lysine::default();
+    // lysine.max_while_iterations(3);
+    // lysine.add_raw_template("forever", "{% while true %}.{% endwhile %}").unwrap();
+    // assert!(lysine.render("forever", &Context::new()).is_err());
+    // ```
+    pub fn max_while_iterations(&mut self, max_while_iterations: usize) {
+        self.max_while_iterations = max_while_iterations;
+    }
+
+    // Set what happens when a template uses a variable that isn't in the context, see
+    // [`UndefinedBehavior`]. Defaults to [`UndefinedBehavior::Strict`], it can be changed for a
+    // single render with [`render_with_undefined_behavior()`](Self::render_with_undefined_behavior).
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Lysine, Context, UndefinedBehavior};
+    // let mut lysine = Lysine::default();
+    // lysine.undefined_behavior(UndefinedBehavior::Lenient);
+    // lysine.add_raw_template("hello", "Hello {{ name }}!").unwrap();
+    // let result = lysine.render("hello", &Context::new()).unwrap();
+    // assert_eq!(result, "Hello !");
+    // ```
+    pub fn undefined_behavior(&mut self, undefined_behavior: UndefinedBehavior) {
+        self.undefined_behavior = undefined_behavior;
+    }
+
+    // Set the limits on the resources a render can use, see [`RenderLimits`]. Nothing is limited
+    // by default.
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Lysine, Context, ErrorKind, RenderLimits};
+    // # use std::error::Error;
+    // let mut lysine = Lysine::default();
+    // lysine.render_limits(RenderLimits { max_loop_iterations: Some(100), ..Default::default() });
+    // let result = lysine.render_str("{% for i in range(end=1000) %}{{ i }}{% endfor %}", &Context::new());
+    // let err = result.unwrap_err();
+    // let source = err.source().unwrap().downcast_ref::<lysine::Error>().unwrap();
+    // assert!(matches!(source.kind, ErrorKind::LoopIterationLimitExceeded(100)));
+    // ```
+    pub fn render_limits(&mut self, render_limits: RenderLimits) {
+        self.render_limits = render_limits;
+    }
+
+    // Restrict the filters, tests, functions and templates the templates can use, see
+    // [`Sandbox`]. The templates already loaded are checked right away and the ones added
+    // afterwards when they are loaded, erroring if they use something that isn't allowed.
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Access, Lysine, Sandbox};
+    // let mut lysine = Lysine::default();
+    // lysine
+    //     .sandbox(Sandbox { functions: Access::allowlist(["range"]), ..Default::default() })
+    //     .unwrap();
+    // assert!(lysine.add_raw_template("ok", "{% for i in range(end=3) %}{{ i }}{% endfor %}").is_ok());
+    // assert!(lysine.add_raw_template("env", "{{ get_env(name=\"HOME\") }}").is_err());
+    // ```
+    pub fn sandbox(&mut self, sandbox: Sandbox) -> Result<()> {
+        for template in self.templates.values() {
+            sandbox.check(template)?;
+        }
+        self.sandbox = Some(sandbox);
+        Ok(())
+    }
+
+    // Do the math involving non-integers with arbitrary-precision decimals instead of floats,
+    // eg for prices. Disabled by default.
+    //
+    // The results keep all their digits, the `decimal` feature enables serde_json's
+    // `arbitrary_precision` for that. Divisions that don't give an exact result are rounded
+    // to 28 significant digits.
+    //
+    // # Examples
+    //
+    // Basic usage:
+    //
+    // ```
+    // # use lysine::{Lysine, Context};
+    // let mut lysine = Lysine::default();
+    // lysine.decimal_math(true);
+    // lysine.add_raw_template("total", "{{ 0.1 + 0.2 }}").unwrap();
+    // let result = lysine.render("total", &Context::new()).unwrap();
+    // assert_eq!(result, "0.3");
+    // ```
+    #[cfg(feature = "decimal")]
+    pub fn decimal_math(&mut self, decimal_math: bool) {
+        self.decimal_math = decimal_math;
+    }
+
     // Re-parse all templates found in the glob given to Lysine.
     ///
     // Use this when you are watching a directory and want to reload everything,
@@ -843,11 +1463,11 @@ impl Lysine {
         self.check_macro_files()
     }
 
-    // Extend this [`Lysine`] instance with the templates, filters, testers and functions defined in
-    // another instance.
+    // Extend this [`Lysine`] instance with the templates, filters, testers, functions and globals
+    // defined in another instance.
     ///
-    // Use that method when you want to add a given Lysine instance templates/filters/testers/functions
-    // to your own. If a template/filter/tester/function with the same name already exists in your instance,
+    // Use that method when you want to add a given Lysine instance templates/filters/testers/functions/globals
+    // to your own. If a template/filter/tester/function/global with the same name already exists in your instance,
     // it will not be overwritten.
     ///
     ///```no_compile
@@ -858,6 +1478,9 @@ impl Lysine {
     pub fn extend(&mut self, other: &Lysine) -> Result<()> {
         for (name, template) in &other.templates {
             if !self.templates.contains_key(name) {
+                if let Some(ref sandbox) = self.sandbox {
+                    sandbox.check(template)?;
+                }
                 let mut tpl = template.clone();
                 tpl.from_extend = true;
                 self.templates.insert(name.to_string(), tpl);
@@ -882,6 +1505,25 @@ impl Lysine {
             }
         }
 
+        #[cfg(feature = "async")]
+        for (name, filter) in &other.async_filters {
+            if !self.async_filters.contains_key(name) {
+                self.async_filters.insert(name.to_string(), filter.clone());
+            }
+        }
+
+        #[cfg(feature = "async")]
+        for (name, function) in &other.async_functions {
+            if !self.async_functions.contains_key(name) {
+                self.async_functions.insert(name.to_string(), function.clone());
+            }
+        }
+
+        // The globals already set are kept over the ones of `other`
+        let mut globals = other.globals.clone();
+        globals.extend(mem::take(&mut self.globals));
+        self.globals = globals;
+
         self.build_inheritance_chains()?;
         self.check_macro_files()
     }
@@ -895,8 +1537,20 @@ impl Default for Lysine {
             filters: HashMap::new(),
             testers: HashMap::new(),
             functions: HashMap::new(),
+            #[cfg(feature = "async")]
+            async_filters: HashMap::new(),
+            #[cfg(feature = "async")]
+            async_functions: HashMap::new(),
             autoescape_suffixes: vec![".html", ".htm", ".xml"],
             escape_fn: escape_html,
+            whitespace: WhitespaceOptions::default(),
+            max_while_iterations: DEFAULT_MAX_WHILE_ITERATIONS,
+            globals: Context::new(),
+            undefined_behavior: UndefinedBehavior::Strict,
+            render_limits: RenderLimits::default(),
+            sandbox: None,
+            #[cfg(feature = "decimal")]
+            decimal_math: false,
         };
 
         lysine.register_lysine_filters();
@@ -938,10 +1592,13 @@ mod tests {
     use tempfile::tempdir;
 
     use std::collections::HashMap;
+    use std::error::Error;
     use std::fs::File;
 
     use super::Lysine;
     use crate::context::Context;
+    use crate::sandbox::{Access, Sandbox};
+    use crate::template::Template;
     use serde_json::{json, Value as JsonValue};
 
     #[test]
@@ -1200,6 +1857,139 @@ mod tests {
         assert!(my_lysine.testers.contains_key("hello"));
     }
 
+    #[test]
+    fn test_extend_globals_without_overwriting() {
+        let mut my_lysine = Lysine::default();
+        my_lysine.add_global("site", "mine");
+        let mut framework_lysine = Lysine::default();
+        framework_lysine.add_global("site", "framework");
+        framework_lysine.add_global("version", "1.0");
+        my_lysine.extend(&framework_lysine).unwrap();
+        assert_eq!(my_lysine.globals.get("site"), Some(&json!("mine")));
+        assert_eq!(my_lysine.globals.get("version"), Some(&json!("1.0")));
+    }
+
+    #[test]
+    fn test_sandbox_rejects_templates_when_loading() {
+        let mut lysine = Lysine::default();
+        lysine
+            .sandbox(Sandbox {
+                filters: Access::denylist(["json_encode"]),
+                tests: Access::allowlist(["defined"]),
+                functions: Access::allowlist(["range", "include"]),
+                templates: Access::allowlist(["base", "macros", "footer"]),
+            })
+            .unwrap();
+        lysine
+            .add_raw_templates(vec![
+                ("base", "{% block content %}{% endblock content %}"),
+                ("macros", "{% macro hello() %}Hello{% endmacro hello %}"),
+                ("footer", "Footer"),
+            ])
+            .unwrap();
+
+        let allowed = vec![
+            "{% for i in range(end=3) %}{{ i | upper }}{% endfor %}",
+            "{% if name is defined %}{{ name }}{% endif %}",
+            "{% extends \"base\" %}{% block content %}{% include \"footer\" %}{% endblock content %}",
+            "{% import \"macros\" as macros %}{{ macros::hello() }}",
+            "{% from \"macros\" import hello %}{{ hello() }}",
+            "{{ include(name=[\"footer\"], ignore_missing=true) }}",
+        ];
+        for input in allowed {
+            lysine.add_raw_template("tpl", input).unwrap();
+        }
+
+        let denied = vec![
+            ("{{ get_env(name=\"HOME\") }}", "the function `get_env`"),
+            ("{{ range(end=now()) }}", "the function `now`"),
+            ("{{ data | json_encode }}", "the filter `json_encode`"),
+            ("{% filter json_encode %}{% endfilter %}", "the filter `json_encode`"),
+            ("{{ [1] | map(attribute=get_random()) }}", "the function `get_random`"),
+            ("{% if name is string %}{% endif %}", "the test `string`"),
+            ("{% include \"secret\" %}", "the template `secret`"),
+            ("{% extends \"secret\" %}", "the template `secret`"),
+            ("{% import \"secret\" as secret %}", "the template `secret`"),
+            ("{% from \"secret\" import hello %}", "the template `secret`"),
+            ("{% embed \"secret\" %}{% endembed %}", "the template `secret`"),
+            ("{{ include(name=\"secret\") }}", "the template `secret`"),
+            ("{% macro m(a=get_env(name=\"HOME\")) %}{% endmacro m %}", "the function `get_env`"),
+        ];
+        for (input, used) in denied {
+            let err = lysine.add_raw_template("denied", input).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Template 'denied' uses {}, which isn't allowed by the sandbox", used)
+            );
+        }
+        assert!(lysine.get_template("denied").is_err());
+
+        let err = lysine.add_raw_template("denied", "{{ include(name=page) }}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Template 'denied' calls `include()` with a template name that isn't a string, which isn't allowed by the sandbox"
+        );
+    }
+
+    #[test]
+    fn test_sandbox_checks_loaded_templates() {
+        let mut lysine = Lysine::default();
+        lysine.add_raw_template("env", "{{ get_env(name=\"HOME\") }}").unwrap();
+
+        let err = lysine.sandbox(Sandbox::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Template 'env' uses the function `get_env`, which isn't allowed by the sandbox"
+        );
+
+        let mut sandboxed = Lysine::default();
+        sandboxed.sandbox(Sandbox::default()).unwrap();
+        assert!(sandboxed.extend(&lysine).is_err());
+        assert!(sandboxed.add_raw_template("now", "{{ now() }}").is_ok());
+    }
+
+    #[test]
+    fn test_sandbox_checks_templates_when_rendering() {
+        let mut lysine = Lysine::default();
+        lysine
+            .sandbox(Sandbox {
+                filters: Access::denylist(["json_encode"]),
+                tests: Access::allowlist(["defined"]),
+                functions: Access::allowlist(["include"]),
+                templates: Access::allowlist(["macros"]),
+            })
+            .unwrap();
+        lysine.add_raw_template("macros", "{% macro hello() %}Hello{% endmacro hello %}").unwrap();
+
+        // Templates skipping the checks done when loading are still stopped when rendering
+        let secret = Template::new("secret", None, "{% macro hello() %}Secret{% endmacro hello %}");
+        lysine.templates.insert("secret".to_string(), secret.unwrap());
+        let bypassing = vec![
+            ("function", "{{ now() }}", "the function `now`"),
+            ("filter", "{{ 1 | json_encode }}", "the filter `json_encode`"),
+            ("test", "{% if x is odd %}{% endif %}", "the test `odd`"),
+            ("include", "{% include \"secret\" %}", "the template `secret`"),
+            ("include_fn", "{{ include(name=\"secret\") }}", "the template `secret`"),
+            (
+                "import",
+                "{% import \"secret\" as secret %}{{ secret::hello() }}",
+                "the template `secret`",
+            ),
+        ];
+        for (name, input, _) in &bypassing {
+            let tpl = Template::new(name, None, input).unwrap();
+            lysine.templates.insert(name.to_string(), tpl);
+        }
+
+        for (name, _, used) in bypassing {
+            let err = lysine.render(name, &Context::new()).unwrap_err();
+            assert_eq!(
+                err.source().unwrap().to_string(),
+                format!("Template '{}' uses {}, which isn't allowed by the sandbox", name, used)
+            );
+        }
+    }
+
     #[test]
     fn can_load_from_glob() {
         let lysine = Lysine::new("examples/basic/templates/**/*").unwrap();
@@ -1234,6 +2024,20 @@ mod tests {
         assert!(lysine.get_template("one").is_ok());
     }
 
+    #[test]
+    fn registered_functions_shadow_renderer_functions() {
+        let mut lysine = Lysine::default();
+        lysine
+            .add_raw_templates(vec![("hello", "Hello"), ("tpl", "{{ include(name=\"hello\") }}")])
+            .unwrap();
+        assert_eq!(lysine.render("tpl", &Context::new()).unwrap(), "Hello");
+
+        lysine.register_function("include", |args: &HashMap<String, JsonValue>| {
+            Ok(JsonValue::String(format!("custom {}", args["name"].as_str().unwrap())))
+        });
+        assert_eq!(lysine.render("tpl", &Context::new()).unwrap(), "custom hello");
+    }
+
     #[should_panic]
     #[test]
     fn test_can_only_parse_templates() {
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+
+// A value of the context that is resolved when a template uses it instead of being serialized
+// into a `Value` up front, eg a collection backed by a database.
+//
+// Accessing an attribute like `users.first.name` only calls `get("first")`, a `{% for %}` loop
+// uses `iter`, the `length` filter uses `len` and `users.page(number=2)` calls a method. The
+// whole object is only converted with `to_value` when it is rendered or given to something else,
+// like a filter or a function.
+pub trait Object: Sync + Send {
+    // The value of an attribute, `None` if the object doesn't have it
+    fn get(&self, key: &str) -> Option<Value>;
+
+    // The whole object as a value
+    fn to_value(&self) -> Value;
+
+    // The items a `{% for %}` loop goes through, `None` if the object can't be iterated on
+    fn iter(&self) -> Option<Box<dyn Iterator<Item = Value> + '_>> {
+        None
+    }
+
+    // The number of items used by the `length` filter, `None` to use the length of `to_value`
+    fn len(&self) -> Option<usize> {
+        None
+    }
+
+    // Whether the object has no items, `None` if `len` is `None`
+    fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    // Calls a method with its keyword arguments
+    fn call_method(&self, name: &str, _args: &HashMap<String, Value>) -> Result<Value> {
+        Err(Error::msg(format!("The object doesn't have a method `{}`", name)))
+    }
+}
+
+// An object stored in a context, cloning it only clones the pointer
+#[derive(Clone)]
+pub(crate) struct ObjectRef(pub(crate) Arc<dyn Object>);
+
+impl fmt::Debug for ObjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Object")
+    }
+}
+
+// 2 objects are only equal if they are the same one
+impl PartialEq for ObjectRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::fmt;
 
+use serde_json::Value;
+
 // Whether to remove the whitespace of a `{% %}` tag
 #[derive(Clone, Copy, Debug, PartialEq, Default)]
 pub struct WS {
@@ -8,6 +10,24 @@ pub struct WS {
     pub left: bool,
     // `true` if the tag is `-%}`
     pub right: bool,
+    // `true` if the tag is `{%+`, ignoring `lstrip_blocks` for that tag
+    pub keep_left: bool,
+    // `true` if the tag is `+%}`, ignoring `trim_blocks` for that tag
+    pub keep_right: bool,
+}
+
+impl WS {
+    // Sets the left side from the start delimiter of a tag, eg `{%-`
+    pub fn set_left(&mut self, start: &str) {
+        self.left = start.ends_with('-');
+        self.keep_left = start.ends_with('+');
+    }
+
+    // Sets the right side from the end delimiter of a tag, eg `-%}`
+    pub fn set_right(&mut self, end: &str) {
+        self.right = end.starts_with('-');
+        self.keep_right = end.starts_with('+');
+    }
 }
 
 // All math operators
@@ -86,10 +106,21 @@ impl fmt::Display for LogicOperator {
 pub struct FunctionCall {
     // The name of the function
     pub name: String,
+    // The args given without a name, in order. Only macros accept those
+    pub positional_args: Vec<Expr>,
     // The args of the function: key -> value
     pub args: HashMap<String, Expr>,
 }
 
+// A call to a method of an object of the context, like `users.page(number=2)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MethodCall {
+    // The name of the object in the context
+    pub object: String,
+    // The method name and its arguments
+    pub call: FunctionCall,
+}
+
 // A mathematical expression
 #[derive(Clone, Debug, PartialEq)]
 pub struct MathExpr {
@@ -112,18 +143,18 @@ pub struct LogicExpr {
     pub operator: LogicOperator,
 }
 
-// Can only be a combination of string + ident or ident + ident
+// A `~` concatenation of values into a string
 #[derive(Clone, Debug, PartialEq)]
 pub struct StringConcat {
     // All the values we're concatening into a string
-    pub values: Vec<ExprVal>,
+    pub values: Vec<Expr>,
 }
 
 impl StringConcat {
     pub(crate) fn to_template_string(&self) -> String {
         let mut res = Vec::new();
         for value in &self.values {
-            match value {
+            match value.val {
                 ExprVal::String(ref s) => res.push(format!("'{}'", s)),
                 ExprVal::Ident(ref s) => res.push(s.to_string()),
                 _ => res.push("unknown".to_string()),
@@ -145,6 +176,34 @@ pub struct In {
     pub negated: bool,
 }
 
+// What is accessed on the result of an expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessKey {
+    // `.name`
+    Attr(String),
+    // `[expression]`, an index for arrays or a key for objects
+    Index(Expr),
+}
+
+// Attributes and indices accessed on an expression that isn't a plain variable,
+// like `(items | first).name` or `get_user().email`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Access {
+    // The expression we are accessing things on
+    pub target: Box<Expr>,
+    // What to access, in order, and whether it was accessed with `?.`
+    pub keys: Vec<(AccessKey, bool)>,
+}
+
+// `lhs ?? rhs`, the right side is only evaluated if the left side is missing or null
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coalesce {
+    // The value to use if it exists
+    pub lhs: Box<Expr>,
+    // The fallback
+    pub rhs: Box<Expr>,
+}
+
 // An expression is the node found in variable block, kwargs and conditions.
 #[derive(Clone, Debug, PartialEq)]
 #[allow(missing_docs)]
@@ -158,12 +217,19 @@ pub enum ExprVal {
     Logic(LogicExpr),
     Test(Test),
     MacroCall(MacroCall),
+    MacroRef(MacroRef),
     FunctionCall(FunctionCall),
+    MethodCall(MethodCall),
     // A vec of Expr, not ExprVal since filters are allowed
     // on values inside arrays
     Array(Vec<Expr>),
     StringConcat(StringConcat),
     In(In),
+    Access(Access),
+    Coalesce(Coalesce),
+    // A parenthesised expression that needs to be kept as is, eg when it is negated
+    // and then filtered: `(!! a) | string`
+    Group(Box<Expr>),
 }
 
 // An expression is a value that can be negated and followed by
@@ -216,8 +282,8 @@ impl Expr {
 // A test node `if my_var is odd`
 #[derive(Clone, Debug, PartialEq)]
 pub struct Test {
-    // Which variable is evaluated
-    pub ident: String,
+    // The expression being tested
+    pub expr: Box<Expr>,
     // Is it using `not`?
     pub negated: bool,
     // Name of the test
@@ -247,6 +313,46 @@ pub struct Set {
     pub global: bool,
 }
 
+// The types a variable declared with `{% requires %}` can be restricted to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RequiredType {
+    String,
+    // Any number, integer or not
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl fmt::Display for RequiredType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                RequiredType::String => "a string",
+                RequiredType::Number => "a number",
+                RequiredType::Integer => "an integer",
+                RequiredType::Boolean => "a boolean",
+                RequiredType::Array => "an array",
+                RequiredType::Object => "an object",
+            }
+        )
+    }
+}
+
+// A variable of the context declared by `{% requires user, cart.items: array, locale = "en" %}`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Requirement {
+    // The name of the variable, can be a dotted path like `cart.items`
+    pub name: String,
+    // The type the variable needs to have, if any
+    pub kind: Option<RequiredType>,
+    // The value used when the variable isn't in the context, which makes it optional
+    pub default: Option<Value>,
+}
+
 // A call to a namespaced macro `macros::my_macro()`
 #[derive(Clone, Debug, PartialEq)]
 pub struct MacroCall {
@@ -254,17 +360,34 @@ pub struct MacroCall {
     pub namespace: String,
     // The macro name
     pub name: String,
+    // The args given without a name, matched against the macro args in declaration order
+    pub positional_args: Vec<Expr>,
     // The args for that macro: name -> value
     pub args: HashMap<String, Expr>,
 }
 
+// A reference to a namespaced macro `macros::my_macro` without calling it, so it can be
+// passed around as a value and called later
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroRef {
+    // The namespace we're looking for that macro in
+    pub namespace: String,
+    // The macro name
+    pub name: String,
+}
+
 // A Macro definition
 #[derive(Clone, Debug, PartialEq)]
 pub struct MacroDefinition {
     // The macro name
     pub name: String,
-    // The args for that macro: name -> optional default value
-    pub args: HashMap<String, Option<Expr>>,
+    // The args for that macro in declaration order: name -> optional default value
+    // The defaults are evaluated at call time and can refer to the args before them
+    pub args: Vec<(String, Option<Expr>)>,
+    // The name of the `*args` parameter collecting extra positional args in an array
+    pub varargs: Option<String>,
+    // The name of the `**kwargs` parameter collecting unknown keyword args in an object
+    pub kwargs: Option<String>,
     // The macro content
     pub body: Vec<Node>,
 }
@@ -274,10 +397,26 @@ pub struct MacroDefinition {
 pub struct Block {
     // The block name
     pub name: String,
+    // Whether the block was marked `scoped`, which is needed to use it inside a for loop
+    pub scoped: bool,
+    // Whether the block was marked `required`, a child template then needs to override it
+    pub required: bool,
     // The block content
     pub body: Vec<Node>,
 }
 
+// A `{% embed "panel.html" %}...{% endembed %}`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Embed {
+    // The name of the template to embed
+    pub template: String,
+    // The name of the hidden template extending the embedded one with the blocks below,
+    // filled when the template containing the embed is loaded
+    pub name: String,
+    // The blocks overriding the ones of the embedded template, and the comments around them
+    pub body: Vec<Node>,
+}
+
 // A forloop: can be over values or key/values
 #[derive(Clone, Debug, PartialEq)]
 pub struct Forloop {
@@ -293,6 +432,15 @@ pub struct Forloop {
     pub empty_body: Option<Vec<Node>>,
 }
 
+// A while loop, stopped by the condition becoming falsy or by a `{% break %}`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Whileloop {
+    // The condition checked before every iteration
+    pub condition: Expr,
+    // What's in the while loop itself
+    pub body: Vec<Node>,
+}
+
 // An if/elif/else condition with their respective body
 #[derive(Clone, Debug, PartialEq)]
 pub struct If {
@@ -317,10 +465,16 @@ pub enum Node {
 
     // The `{% extends "blabla.html" %}` node, contains the template name
     Extends(WS, String),
+    // The `{% requires user, locale = "en" %}` node, declaring the variables the template needs
+    Requires(WS, Vec<Requirement>),
     // The `{% include "blabla.html" %}` node, contains the template name
     Include(WS, Vec<String>, bool),
+    // The `{% embed "blabla.html" %}...{% endembed %}` node
+    Embed(WS, Embed, WS),
     // The `{% import "macros.html" as macros %}`
     ImportMacro(WS, String, String),
+    // The `{% from "macros.html" import hello, world as earth %}`, as (macro name, bound name) pairs
+    FromImport(WS, String, Vec<(String, String)>),
     // The `{% set val = something %}` tag
     Set(WS, Set),
 
@@ -333,6 +487,8 @@ pub enum Node {
     Block(WS, Block, WS),
     // A `{% for i in items %}...{% endfor %}`
     Forloop(WS, Forloop, WS),
+    // A `{% while condition %}...{% endwhile %}`
+    Whileloop(WS, Whileloop, WS),
 
     // A if/elif/else block, WS for the if/elif/else is directly in the struct
     If(If, WS),
@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
 use lazy_static::lazy_static;
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest::Parser;
 use pest_derive::Parser;
+use serde_json::{Number, Value};
 
 use crate::errors::{Error, Result as LysineResult};
 
@@ -24,22 +25,28 @@ mod whitespace;
 mod tests;
 
 use self::ast::*;
-pub use self::whitespace::remove_whitespace;
+pub use self::whitespace::{remove_whitespace, WhitespaceOptions};
 
 lazy_static! {
-    static ref MATH_PARSER: PrattParser<Rule> = PrattParser::new()
+    static ref EXPR_PARSER: PrattParser<Rule> = PrattParser::new()
+        .op(Op::infix(Rule::op_or, Assoc::Left)) // ||
+        .op(Op::infix(Rule::op_and, Assoc::Left)) // &&
+        .op(Op::prefix(Rule::op_not)) // !!
+        .op(Op::infix(Rule::op_lt, Assoc::Left) | Op::infix(Rule::op_lte, Assoc::Left) | Op::infix(Rule::op_gt, Assoc::Left)
+            | Op::infix(Rule::op_gte, Assoc::Left)
+            | Op::infix(Rule::op_eq, Assoc::Left) | Op::infix(Rule::op_ineq, Assoc::Left)
+            | Op::infix(Rule::op_in, Assoc::Left)) // <, <=, >, >=, ==, !=, in
+        .op(Op::infix(Rule::op_coalesce, Assoc::Right)) // ??
+        .op(Op::infix(Rule::filtered_concat, Assoc::Left)) // ~ after a filter
+        .op(Op::infix(Rule::filtered_sum, Assoc::Left)) // +, - after a filter
+        .op(Op::infix(Rule::filtered_product, Assoc::Left)) // *, /, % after a filter
+        .op(Op::postfix(Rule::filter) | Op::postfix(Rule::test)) // | filter, is test
+        .op(Op::infix(Rule::op_concat, Assoc::Left)) // ~
         .op(Op::infix(Rule::op_add, Assoc::Left) | Op::infix(Rule::op_minus, Assoc::Left)) // +, -
         .op(Op::infix(Rule::op_mult, Assoc::Left)
             | Op::infix(Rule::op_div, Assoc::Left)
-            | Op::infix(Rule::op_modulo, Assoc::Left)); // *, /, %
-
-    static ref COMPARISON_EXPR_PARSER: PrattParser<Rule> = PrattParser::new()
-        .op(Op::infix(Rule::op_lt, Assoc::Left) | Op::infix(Rule::op_lte, Assoc::Left) | Op::infix(Rule::op_gt, Assoc::Left)
-            | Op::infix(Rule::op_gte, Assoc::Left)
-            | Op::infix(Rule::op_eq, Assoc::Left)| Op::infix(Rule::op_ineq, Assoc::Left)); // <, <=, >, >=, ==, !=
-
-    static ref LOGIC_EXPR_PARSER: PrattParser<Rule> = PrattParser::new()
-        .op(Op::infix(Rule::op_or, Assoc::Left)).op(Op::infix(Rule::op_and, Assoc::Left));
+            | Op::infix(Rule::op_modulo, Assoc::Left)) // *, /, %
+        .op(Op::prefix(Rule::op_neg)); // -
 }
 
 // Strings are delimited by double quotes, single quotes and backticks
@@ -61,7 +68,6 @@ fn parse_kwarg(pair: Pair<Rule>) -> LysineResult<(String, Expr)> {
         match p.as_rule() {
             Rule::ident => name = Some(p.as_span().as_str().to_string()),
             Rule::logic_expr => val = Some(parse_logic_expr(p)?),
-            Rule::array_filter => val = Some(parse_array_with_filters(p)?),
             _ => unreachable!("{:?} not supposed to get there (parse_kwarg)!", p.as_rule()),
         };
     }
@@ -69,22 +75,81 @@ fn parse_kwarg(pair: Pair<Rule>) -> LysineResult<(String, Expr)> {
     Ok((name.unwrap(), val.unwrap()))
 }
 
+fn parse_positional_arg(pair: Pair<Rule>) -> LysineResult<Expr> {
+    let p = pair.into_inner().next().unwrap();
+    match p.as_rule() {
+        Rule::logic_expr => parse_logic_expr(p),
+        _ => unreachable!("{:?} not supposed to get there (parse_positional_arg)!", p.as_rule()),
+    }
+}
+
+// Parses the args of a function or macro call, positional ones need to come before the
+// keyword ones like in Python
+fn parse_call_arg(
+    pair: Pair<Rule>,
+    call_name: &str,
+    positional_args: &mut Vec<Expr>,
+    args: &mut HashMap<String, Expr>,
+) -> LysineResult<()> {
+    match pair.as_rule() {
+        Rule::kwarg => {
+            let (name, val) = parse_kwarg(pair)?;
+            if args.contains_key(&name) {
+                return Err(Error::msg(format!(
+                    "Argument `{}` was passed more than once in the call to `{}`",
+                    name, call_name
+                )));
+            }
+            args.insert(name, val);
+        }
+        Rule::positional_arg => {
+            if !args.is_empty() {
+                return Err(Error::msg(format!(
+                    "Positional argument `{}` follows keyword arguments in the call to `{}`",
+                    pair.as_str().trim(),
+                    call_name
+                )));
+            }
+            positional_args.push(parse_positional_arg(pair)?);
+        }
+        _ => unreachable!("{:?} not supposed to get there (parse_call_arg)!", pair.as_rule()),
+    };
+
+    Ok(())
+}
+
 fn parse_fn_call(pair: Pair<Rule>) -> LysineResult<FunctionCall> {
-    let mut name = None;
+    let mut name = String::new();
+    let mut positional_args = vec![];
     let mut args = HashMap::new();
 
     for p in pair.into_inner() {
         match p.as_rule() {
-            Rule::ident => name = Some(p.as_span().as_str().to_string()),
-            Rule::kwarg => {
-                let (name, val) = parse_kwarg(p)?;
-                args.insert(name, val);
+            Rule::ident => name = p.as_span().as_str().to_string(),
+            _ => parse_call_arg(p, &name, &mut positional_args, &mut args)?,
+        };
+    }
+
+    Ok(FunctionCall { name, positional_args, args })
+}
+
+fn parse_method_call(pair: Pair<Rule>) -> LysineResult<MethodCall> {
+    let mut object = String::new();
+    let mut call =
+        FunctionCall { name: String::new(), positional_args: vec![], args: HashMap::new() };
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::method_name => {
+                let mut idents = p.into_inner();
+                object = idents.next().unwrap().as_str().to_string();
+                call.name = idents.next().unwrap().as_str().to_string();
             }
-            _ => unreachable!("{:?} not supposed to get there (parse_fn_call)!", p.as_rule()),
+            _ => parse_call_arg(p, &call.name, &mut call.positional_args, &mut call.args)?,
         };
     }
 
-    Ok(FunctionCall { name: name.unwrap(), args })
+    Ok(MethodCall { object, call })
 }
 
 fn parse_filter(pair: Pair<Rule>) -> LysineResult<FunctionCall> {
@@ -104,7 +169,7 @@ fn parse_filter(pair: Pair<Rule>) -> LysineResult<FunctionCall> {
         };
     }
 
-    Ok(FunctionCall { name: name.unwrap(), args })
+    Ok(FunctionCall { name: name.unwrap(), positional_args: vec![], args })
 }
 
 fn parse_test_call(pair: Pair<Rule>) -> LysineResult<(String, Vec<Expr>)> {
@@ -114,21 +179,7 @@ fn parse_test_call(pair: Pair<Rule>) -> LysineResult<(String, Vec<Expr>)> {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::ident => name = Some(p.as_span().as_str().to_string()),
-            Rule::test_arg =>
-            // iterate on the test_arg rule
-            {
-                for p2 in p.into_inner() {
-                    match p2.as_rule() {
-                        Rule::logic_expr => {
-                            args.push(parse_logic_expr(p2)?);
-                        }
-                        Rule::array => {
-                            args.push(Expr::new(parse_array(p2)?));
-                        }
-                        _ => unreachable!("Invalid arg type for test {:?}", p2.as_rule()),
-                    }
-                }
-            }
+            Rule::test_arg => args.push(parse_expr(p.into_inner())?),
             _ => unreachable!("{:?} not supposed to get there (parse_test_call)!", p.as_rule()),
         };
     }
@@ -136,105 +187,71 @@ fn parse_test_call(pair: Pair<Rule>) -> LysineResult<(String, Vec<Expr>)> {
     Ok((name.unwrap(), args))
 }
 
-fn parse_test(pair: Pair<Rule>) -> LysineResult<Test> {
-    let mut ident = None;
-    let mut name = None;
-    let mut args = vec![];
+// `expr is test_name(args)` or `expr is not test_name(args)`
+fn parse_test(expr: Expr, pair: Pair<Rule>) -> LysineResult<Test> {
+    let mut negated = false;
+    let mut test = None;
 
     for p in pair.into_inner() {
         match p.as_rule() {
-            Rule::dotted_square_bracket_ident => ident = Some(p.as_str().to_string()),
-            Rule::test_call => {
-                let (_name, _args) = parse_test_call(p)?;
-                name = Some(_name);
-                args = _args;
-            }
-            _ => unreachable!("{:?} not supposed to get there (parse_ident)!", p.as_rule()),
+            Rule::test_not => negated = true,
+            Rule::test_call => test = Some(parse_test_call(p)?),
+            _ => unreachable!("{:?} not supposed to get there (parse_test)!", p.as_rule()),
         };
     }
 
-    Ok(Test { ident: ident.unwrap(), negated: false, name: name.unwrap(), args })
+    let (name, args) = test.unwrap();
+    Ok(Test { expr: Box::new(expr), negated, name, args })
 }
 
-fn parse_string_concat(pair: Pair<Rule>) -> LysineResult<ExprVal> {
-    let mut values = vec![];
-    let mut current_str = String::new();
+// Adds filters to an expression, keeping the grouping of a negated one since the negation
+// happens after the filters
+fn add_filters(expr: Expr, filters: Vec<FunctionCall>) -> Expr {
+    if filters.is_empty() {
+        return expr;
+    }
 
-    // Can we fold it into a simple string?
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            Rule::string => {
-                current_str.push_str(&replace_string_markers(p.as_str()));
-            }
-            Rule::int => {
-                if !current_str.is_empty() {
-                    values.push(ExprVal::String(current_str));
-                    current_str = String::new();
-                }
-                values.push(ExprVal::Int(p.as_str().parse().map_err(|_| {
-                    Error::msg(format!("Integer out of bounds: `{}`", p.as_str()))
-                })?));
-            }
-            Rule::float => {
-                if !current_str.is_empty() {
-                    values.push(ExprVal::String(current_str));
-                    current_str = String::new();
-                }
-                values.push(ExprVal::Float(
-                    p.as_str().parse().map_err(|_| {
-                        Error::msg(format!("Float out of bounds: `{}`", p.as_str()))
-                    })?,
-                ));
-            }
-            Rule::dotted_square_bracket_ident => {
-                if !current_str.is_empty() {
-                    values.push(ExprVal::String(current_str));
-                    current_str = String::new();
-                }
-                values.push(ExprVal::Ident(p.as_str().to_string()))
-            }
-            Rule::fn_call => {
-                if !current_str.is_empty() {
-                    values.push(ExprVal::String(current_str));
-                    current_str = String::new();
-                }
-                values.push(ExprVal::FunctionCall(parse_fn_call(p)?))
+    let mut expr = if expr.negated { Expr::new(ExprVal::Group(Box::new(expr))) } else { expr };
+    expr.filters.extend(filters);
+    expr
+}
+
+// Concatenates 2 expressions, merging them into the same `StringConcat` when possible
+// and folding the strings next to each other into a single one
+fn concat_exprs(lhs: Expr, rhs: Expr) -> Expr {
+    let mut values: Vec<Expr> = vec![];
+    for e in [lhs, rhs] {
+        let exprs = match e {
+            Expr { val: ExprVal::StringConcat(concat), negated: false, ref filters }
+                if filters.is_empty() =>
+            {
+                concat.values
             }
-            _ => unreachable!("Got {:?} in parse_string_concat", p),
+            _ => vec![e],
         };
-    }
 
-    if values.is_empty() {
-        // we only got a string
-        return Ok(ExprVal::String(current_str));
+        for e in exprs {
+            match (values.last_mut(), e) {
+                (
+                    Some(Expr { val: ExprVal::String(ref mut current), negated: false, filters }),
+                    Expr { val: ExprVal::String(s), negated: false, filters: ref f },
+                ) if filters.is_empty() && f.is_empty() => current.push_str(&s),
+                (_, e) => values.push(e),
+            }
+        }
     }
 
-    if !current_str.is_empty() {
-        values.push(ExprVal::String(current_str));
+    if let [Expr { val: ExprVal::String(_), negated: false, filters }] = &values[..] {
+        if filters.is_empty() {
+            return values.pop().unwrap();
+        }
     }
 
-    Ok(ExprVal::StringConcat(StringConcat { values }))
+    Expr::new(ExprVal::StringConcat(StringConcat { values }))
 }
 
-fn parse_basic_expression(pair: Pair<Rule>) -> LysineResult<ExprVal> {
-    let primary = parse_basic_expression;
-
-    let infix = |lhs: LysineResult<ExprVal>, op: Pair<Rule>, rhs: LysineResult<ExprVal>| {
-        Ok(ExprVal::Math(MathExpr {
-            lhs: Box::new(Expr::new(lhs?)),
-            operator: match op.as_rule() {
-                Rule::op_add => MathOperator::Add,
-                Rule::op_minus => MathOperator::Sub,
-                Rule::op_mult => MathOperator::Mul,
-                Rule::op_div => MathOperator::Div,
-                Rule::op_modulo => MathOperator::Modulo,
-                _ => unreachable!(),
-            },
-            rhs: Box::new(Expr::new(rhs?)),
-        }))
-    };
-
-    let expr = match pair.as_rule() {
+fn parse_basic_val(pair: Pair<Rule>) -> LysineResult<Expr> {
+    let val = match pair.as_rule() {
         Rule::int => ExprVal::Int(
             pair.as_str()
                 .parse()
@@ -252,219 +269,145 @@ fn parse_basic_expression(pair: Pair<Rule>) -> LysineResult<ExprVal> {
             "False" => ExprVal::Bool(false),
             _ => unreachable!(),
         },
-        Rule::test => ExprVal::Test(parse_test(pair)?),
-        Rule::test_not => {
-            let mut test = parse_test(pair)?;
-            test.negated = true;
-            ExprVal::Test(test)
-        }
+        Rule::string => ExprVal::String(replace_string_markers(pair.as_str())),
+        Rule::array => parse_array(pair)?,
         Rule::fn_call => ExprVal::FunctionCall(parse_fn_call(pair)?),
+        Rule::method_call => ExprVal::MethodCall(parse_method_call(pair)?),
         Rule::macro_call => ExprVal::MacroCall(parse_macro_call(pair)?),
+        Rule::macro_ref => ExprVal::MacroRef(parse_macro_ref(pair)),
         Rule::dotted_square_bracket_ident => ExprVal::Ident(pair.as_str().to_string()),
-        Rule::basic_expr => {
-            MATH_PARSER.map_primary(primary).map_infix(infix).parse(pair.into_inner())?
-        }
-        _ => unreachable!("Got {:?} in parse_basic_expression: {}", pair.as_rule(), pair.as_str()),
+        Rule::paren_expr => return parse_logic_expr(pair.into_inner().next().unwrap()),
+        _ => unreachable!("Got {:?} in parse_basic_val: {}", pair.as_rule(), pair.as_str()),
     };
-    Ok(expr)
+
+    Ok(Expr::new(val))
 }
 
-// A basic expression with optional filters
-fn parse_basic_expr_with_filters(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let mut expr_val = None;
-    let mut filters = vec![];
+// A value followed by the attributes and indices accessed on it
+fn parse_access_val(pair: Pair<Rule>) -> LysineResult<Expr> {
+    let mut pairs = pair.into_inner();
+    let target = parse_basic_val(pairs.next().unwrap())?;
 
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            Rule::basic_expr => expr_val = Some(parse_basic_expression(p)?),
-            Rule::filter => filters.push(parse_filter(p)?),
-            _ => unreachable!("Got {:?}", p),
-        };
+    let mut keys = vec![];
+    for p in pairs {
+        let rule = p.as_rule();
+        let mut optional = false;
+        let mut key = None;
+        for p2 in p.into_inner() {
+            match p2.as_rule() {
+                Rule::op_safe_nav => optional = true,
+                Rule::ident => key = Some(AccessKey::Attr(p2.as_str().to_string())),
+                Rule::logic_expr => key = Some(AccessKey::Index(parse_logic_expr(p2)?)),
+                _ => unreachable!("Got {:?} in parse_access_val for {:?}", p2.as_rule(), rule),
+            }
+        }
+        keys.push((key.unwrap(), optional));
     }
 
-    Ok(Expr { val: expr_val.unwrap(), negated: false, filters })
-}
-
-// A string expression with optional filters
-fn parse_string_expr_with_filters(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let mut expr_val = None;
-    let mut filters = vec![];
-
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            Rule::string => expr_val = Some(ExprVal::String(replace_string_markers(p.as_str()))),
-            Rule::string_concat => expr_val = Some(parse_string_concat(p)?),
-            Rule::filter => filters.push(parse_filter(p)?),
-            _ => unreachable!("Got {:?}", p),
-        };
+    if keys.is_empty() {
+        return Ok(target);
     }
 
-    Ok(Expr { val: expr_val.unwrap(), negated: false, filters })
+    Ok(Expr::new(ExprVal::Access(Access { target: Box::new(target), keys })))
 }
 
-// An array with optional filters
-fn parse_array_with_filters(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let mut array = None;
-    let mut filters = vec![];
-
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            Rule::array => array = Some(parse_array(p)?),
-            Rule::filter => filters.push(parse_filter(p)?),
-            _ => unreachable!("Got {:?}", p),
-        };
-    }
-
-    Ok(Expr { val: array.unwrap(), negated: false, filters })
+// `!! expr`, a negated expression already negated keeps its own negation: `!! (!! a)`
+fn negate_expr(expr: Expr) -> Expr {
+    let mut expr = if expr.negated { Expr::new(ExprVal::Group(Box::new(expr))) } else { expr };
+    expr.negated = true;
+    expr
 }
 
-fn parse_in_condition_container(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let mut expr = None;
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            Rule::array_filter => expr = Some(parse_array_with_filters(p)?),
-            Rule::dotted_square_bracket_ident => {
-                expr = Some(Expr::new(ExprVal::Ident(p.as_str().to_string())))
-            }
-            Rule::string_expr_filter => expr = Some(parse_string_expr_with_filters(p)?),
-            _ => unreachable!("Got {:?} in parse_in_condition_container", p),
-        };
+// `-expr`, numbers are negated directly and anything else is subtracted from 0
+fn neg_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr { val: ExprVal::Int(n), negated: false, ref filters }
+            if filters.is_empty() && n.checked_neg().is_some() =>
+        {
+            Expr::new(ExprVal::Int(-n))
+        }
+        Expr { val: ExprVal::Float(f), negated: false, ref filters } if filters.is_empty() => {
+            Expr::new(ExprVal::Float(-f))
+        }
+        _ => Expr::new(ExprVal::Math(MathExpr {
+            lhs: Box::new(Expr::new(ExprVal::Int(0))),
+            operator: MathOperator::Sub,
+            rhs: Box::new(expr),
+        })),
     }
-    Ok(expr.unwrap())
 }
 
-fn parse_in_condition(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let mut lhs = None;
-    let mut rhs = None;
-    let mut negated = false;
-
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            // lhs
-            Rule::string_expr_filter => lhs = Some(parse_string_expr_with_filters(p)?),
-            Rule::basic_expr_filter => lhs = Some(parse_basic_expr_with_filters(p)?),
-            // rhs
-            Rule::in_cond_container => rhs = Some(parse_in_condition_container(p)?),
-            Rule::op_not => negated = true,
-            _ => unreachable!("Got {:?} in parse_in_condition", p),
-        };
+fn math_operator(op: &Pair<Rule>) -> MathOperator {
+    match op.as_rule() {
+        Rule::op_add => MathOperator::Add,
+        Rule::op_minus => MathOperator::Sub,
+        Rule::op_mult => MathOperator::Mul,
+        Rule::op_div => MathOperator::Div,
+        Rule::op_modulo => MathOperator::Modulo,
+        _ => unreachable!("{:?} is not a math operator", op.as_rule()),
     }
-
-    Ok(Expr::new(ExprVal::In(In {
-        lhs: Box::new(lhs.unwrap()),
-        rhs: Box::new(rhs.unwrap()),
-        negated,
-    })))
 }
 
-// A basic expression with optional filters with prece
-fn parse_comparison_val(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let primary = parse_comparison_val;
-
-    let infix = |lhs: LysineResult<Expr>, op: Pair<Rule>, rhs: LysineResult<Expr>| {
-        Ok(Expr::new(ExprVal::Math(MathExpr {
-            lhs: Box::new(lhs?),
-            operator: match op.as_rule() {
-                Rule::op_add => MathOperator::Add,
-                Rule::op_minus => MathOperator::Sub,
-                Rule::op_mult => MathOperator::Mul,
-                Rule::op_div => MathOperator::Div,
-                Rule::op_modulo => MathOperator::Modulo,
-                _ => unreachable!(),
-            },
-            rhs: Box::new(rhs?),
-        })))
+// Parses the operands and operators of an expression with the precedence of `EXPR_PARSER`
+fn parse_expr(pairs: Pairs<Rule>) -> LysineResult<Expr> {
+    // The operands grouped after an operator are only there for the error messages
+    let pairs = pairs.flat_map(|p| match p.as_rule() {
+        Rule::operand | Rule::math_operand => p.into_inner().collect(),
+        _ => vec![p],
+    });
+
+    let prefix = |op: Pair<Rule>, rhs: LysineResult<Expr>| match op.as_rule() {
+        Rule::op_not => Ok(negate_expr(rhs?)),
+        Rule::op_neg => Ok(neg_expr(rhs?)),
+        _ => unreachable!("{:?} not supposed to get there (prefix of expression)!", op.as_rule()),
     };
 
-    let expr = match pair.as_rule() {
-        Rule::basic_expr_filter => parse_basic_expr_with_filters(pair)?,
-        Rule::comparison_val => {
-            MATH_PARSER.map_primary(primary).map_infix(infix).parse(pair.into_inner())?
-        }
-        _ => unreachable!("Got {:?} in parse_comparison_val", pair.as_rule()),
+    let postfix = |lhs: LysineResult<Expr>, op: Pair<Rule>| match op.as_rule() {
+        Rule::filter => Ok(add_filters(lhs?, vec![parse_filter(op)?])),
+        Rule::test => Ok(Expr::new(ExprVal::Test(parse_test(lhs?, op)?))),
+        _ => unreachable!("{:?} not supposed to get there (postfix of expression)!", op.as_rule()),
     };
-    Ok(expr)
-}
-
-fn parse_comparison_expression(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let primary = parse_comparison_expression;
 
     let infix = |lhs: LysineResult<Expr>, op: Pair<Rule>, rhs: LysineResult<Expr>| {
-        Ok(Expr::new(ExprVal::Logic(LogicExpr {
-            lhs: Box::new(lhs?),
-            operator: match op.as_rule() {
-                Rule::op_lt => LogicOperator::Lt,
-                Rule::op_lte => LogicOperator::Lte,
-                Rule::op_gt => LogicOperator::Gt,
-                Rule::op_gte => LogicOperator::Gte,
-                Rule::op_ineq => LogicOperator::NotEq,
-                Rule::op_eq => LogicOperator::Eq,
-                _ => unreachable!(),
-            },
-            rhs: Box::new(rhs?),
-        })))
-    };
+        let (lhs, rhs) = (Box::new(lhs?), Box::new(rhs?));
+        let operator = match op.as_rule() {
+            Rule::op_or => LogicOperator::Or,
+            Rule::op_and => LogicOperator::And,
+            Rule::op_lt => LogicOperator::Lt,
+            Rule::op_lte => LogicOperator::Lte,
+            Rule::op_gt => LogicOperator::Gt,
+            Rule::op_gte => LogicOperator::Gte,
+            Rule::op_eq => LogicOperator::Eq,
+            Rule::op_ineq => LogicOperator::NotEq,
+            Rule::op_in => {
+                let negated = op.into_inner().next().is_some();
+                return Ok(Expr::new(ExprVal::In(In { lhs, rhs, negated })));
+            }
+            Rule::op_coalesce => return Ok(Expr::new(ExprVal::Coalesce(Coalesce { lhs, rhs }))),
+            Rule::op_concat | Rule::filtered_concat => return Ok(concat_exprs(*lhs, *rhs)),
+            Rule::filtered_sum | Rule::filtered_product => {
+                let operator = math_operator(&op.into_inner().next().unwrap());
+                return Ok(Expr::new(ExprVal::Math(MathExpr { lhs, rhs, operator })));
+            }
+            _ => {
+                let operator = math_operator(&op);
+                return Ok(Expr::new(ExprVal::Math(MathExpr { lhs, rhs, operator })));
+            }
+        };
 
-    let expr = match pair.as_rule() {
-        Rule::comparison_val => parse_comparison_val(pair)?,
-        Rule::string_expr_filter => parse_string_expr_with_filters(pair)?,
-        Rule::comparison_expr => {
-            COMPARISON_EXPR_PARSER.map_primary(primary).map_infix(infix).parse(pair.into_inner())?
-        }
-        _ => unreachable!("Got {:?} in parse_comparison_expression", pair.as_rule()),
+        Ok(Expr::new(ExprVal::Logic(LogicExpr { lhs, rhs, operator })))
     };
-    Ok(expr)
-}
-
-// An expression that can be negated
-fn parse_logic_val(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let mut negated = false;
-    let mut expr = None;
-
-    for p in pair.into_inner() {
-        match p.as_rule() {
-            Rule::op_not => negated = true,
-            Rule::in_cond => expr = Some(parse_in_condition(p)?),
-            Rule::comparison_expr => expr = Some(parse_comparison_expression(p)?),
-            Rule::string_expr_filter => expr = Some(parse_string_expr_with_filters(p)?),
-            Rule::logic_expr => expr = Some(parse_logic_expr(p)?),
-            _ => unreachable!(),
-        };
-    }
 
-    let mut e = expr.unwrap();
-    e.negated = negated;
-    Ok(e)
+    EXPR_PARSER
+        .map_primary(parse_access_val)
+        .map_prefix(prefix)
+        .map_postfix(postfix)
+        .map_infix(infix)
+        .parse(pairs)
 }
 
 fn parse_logic_expr(pair: Pair<Rule>) -> LysineResult<Expr> {
-    let primary = parse_logic_expr;
-
-    let infix = |lhs: LysineResult<Expr>, op: Pair<Rule>, rhs: LysineResult<Expr>| match op.as_rule() {
-        Rule::op_or => Ok(Expr::new(ExprVal::Logic(LogicExpr {
-            lhs: Box::new(lhs?),
-            operator: LogicOperator::Or,
-            rhs: Box::new(rhs?),
-        }))),
-        Rule::op_and => Ok(Expr::new(ExprVal::Logic(LogicExpr {
-            lhs: Box::new(lhs?),
-            operator: LogicOperator::And,
-            rhs: Box::new(rhs?),
-        }))),
-        _ => unreachable!(
-            "{:?} not supposed to get there (infix of logic_expression)!",
-            op.as_rule()
-        ),
-    };
-
-    let expr = match pair.as_rule() {
-        Rule::logic_val => parse_logic_val(pair)?,
-        Rule::logic_expr => {
-            LOGIC_EXPR_PARSER.map_primary(primary).map_infix(infix).parse(pair.into_inner())?
-        }
-        _ => unreachable!("Got {:?} in parse_logic_expr", pair.as_rule()),
-    };
-    Ok(expr)
+    parse_expr(pair.into_inner())
 }
 
 fn parse_array(pair: Pair<Rule>) -> LysineResult<ExprVal> {
@@ -472,8 +415,8 @@ fn parse_array(pair: Pair<Rule>) -> LysineResult<ExprVal> {
 
     for p in pair.into_inner() {
         match p.as_rule() {
-            Rule::logic_val => {
-                vals.push(parse_logic_val(p)?);
+            Rule::logic_expr => {
+                vals.push(parse_logic_expr(p)?);
             }
             _ => unreachable!("Got {:?} in parse_array", p.as_rule()),
         }
@@ -500,6 +443,7 @@ fn parse_string_array(pair: Pair<Rule>) -> Vec<String> {
 fn parse_macro_call(pair: Pair<Rule>) -> LysineResult<MacroCall> {
     let mut namespace = None;
     let mut name = None;
+    let mut positional_args = vec![];
     let mut args = HashMap::new();
 
     for p in pair.into_inner() {
@@ -512,15 +456,24 @@ fn parse_macro_call(pair: Pair<Rule>) -> LysineResult<MacroCall> {
                     name = Some(p.as_span().as_str().to_string());
                 }
             }
-            Rule::kwarg => {
-                let (key, val) = parse_kwarg(p)?;
-                args.insert(key, val);
+            _ => {
+                let call_name =
+                    format!("{}::{}", namespace.as_ref().unwrap(), name.as_ref().unwrap());
+                parse_call_arg(p, &call_name, &mut positional_args, &mut args)?;
             }
-            _ => unreachable!("Got {:?} in parse_macro_call", p.as_rule()),
         }
     }
 
-    Ok(MacroCall { namespace: namespace.unwrap(), name: name.unwrap(), args })
+    Ok(MacroCall { namespace: namespace.unwrap(), name: name.unwrap(), positional_args, args })
+}
+
+fn parse_macro_ref(pair: Pair<Rule>) -> MacroRef {
+    let mut idents = pair.into_inner().map(|p| p.as_str().to_string());
+    // namespace comes first
+    let namespace = idents.next().unwrap();
+    let name = idents.next().unwrap();
+
+    MacroRef { namespace, name }
 }
 
 fn parse_variable_tag(pair: Pair<Rule>) -> LysineResult<Node> {
@@ -530,13 +483,12 @@ fn parse_variable_tag(pair: Pair<Rule>) -> LysineResult<Node> {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::var_start => {
-                ws.left = p.as_span().as_str() == "{{-";
+                ws.set_left(p.as_str());
             }
             Rule::var_end => {
-                ws.right = p.as_span().as_str() == "-}}";
+                ws.set_right(p.as_str());
             }
             Rule::logic_expr => expr = Some(parse_logic_expr(p)?),
-            Rule::array_filter => expr = Some(parse_array_with_filters(p)?),
             _ => unreachable!("unexpected {:?} rule in parse_variable_tag", p.as_rule()),
         }
     }
@@ -551,12 +503,12 @@ fn parse_import_macro(pair: Pair<Rule>) -> Node {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::tag_start => {
-                ws.left = p.as_span().as_str() == "{%-";
+                ws.set_left(p.as_str());
             }
             Rule::string => file = Some(replace_string_markers(p.as_span().as_str())),
             Rule::ident => ident = Some(p.as_span().as_str().to_string()),
             Rule::tag_end => {
-                ws.right = p.as_span().as_str() == "-%}";
+                ws.set_right(p.as_str());
             }
             _ => unreachable!(),
         };
@@ -565,6 +517,33 @@ fn parse_import_macro(pair: Pair<Rule>) -> Node {
     Node::ImportMacro(ws, file.unwrap(), ident.unwrap())
 }
 
+fn parse_from_import(pair: Pair<Rule>) -> Node {
+    let mut ws = WS::default();
+    let mut file = None;
+    let mut names = vec![];
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::tag_start => {
+                ws.set_left(p.as_str());
+            }
+            Rule::string => file = Some(replace_string_markers(p.as_span().as_str())),
+            Rule::from_import_name => {
+                let idents: Vec<_> = p.into_inner().map(|p2| p2.as_str().to_string()).collect();
+                // Without `as`, the macro is bound under its own name
+                let alias = idents.last().unwrap().clone();
+                names.push((idents[0].clone(), alias));
+            }
+            Rule::tag_end => {
+                ws.set_right(p.as_str());
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    Node::FromImport(ws, file.unwrap(), names)
+}
+
 fn parse_extends(pair: Pair<Rule>) -> Node {
     let mut ws = WS::default();
     let mut file = None;
@@ -572,11 +551,11 @@ fn parse_extends(pair: Pair<Rule>) -> Node {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::tag_start => {
-                ws.left = p.as_span().as_str() == "{%-";
+                ws.set_left(p.as_str());
             }
             Rule::string => file = Some(replace_string_markers(p.as_span().as_str())),
             Rule::tag_end => {
-                ws.right = p.as_span().as_str() == "-%}";
+                ws.set_right(p.as_str());
             }
             _ => unreachable!(),
         };
@@ -585,6 +564,80 @@ fn parse_extends(pair: Pair<Rule>) -> Node {
     Node::Extends(ws, file.unwrap())
 }
 
+// The default values of `{% requires %}` are only literals so they are known without rendering
+fn literal_to_value(expr: &Expr) -> Option<Value> {
+    if !expr.filters.is_empty() || expr.negated {
+        return None;
+    }
+
+    match expr.val {
+        ExprVal::String(ref s) => Some(Value::String(s.clone())),
+        ExprVal::Int(i) => Some(Value::from(i)),
+        ExprVal::Float(f) => Number::from_f64(f).map(Value::Number),
+        ExprVal::Bool(b) => Some(Value::Bool(b)),
+        ExprVal::Array(ref vals) => vals.iter().map(literal_to_value).collect(),
+        _ => None,
+    }
+}
+
+fn parse_requirement(pair: Pair<Rule>) -> LysineResult<Requirement> {
+    let mut name = None;
+    let mut kind = None;
+    let mut default = None;
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::dotted_ident => name = Some(p.as_str().to_string()),
+            Rule::requirement_type => {
+                kind = Some(match p.as_str() {
+                    "string" => RequiredType::String,
+                    "number" => RequiredType::Number,
+                    "integer" => RequiredType::Integer,
+                    "boolean" => RequiredType::Boolean,
+                    "array" => RequiredType::Array,
+                    "object" => RequiredType::Object,
+                    _ => unreachable!(),
+                });
+            }
+            _ => {
+                let text = p.as_str().to_string();
+                match literal_to_value(&parse_basic_val(p)?) {
+                    Some(val) => default = Some(val),
+                    None => {
+                        return Err(Error::msg(format!(
+                            "The default value of `{}` in `{{% requires %}}` can only contain literals, found `{}`",
+                            name.unwrap(),
+                            text
+                        )));
+                    }
+                }
+            }
+        };
+    }
+
+    Ok(Requirement { name: name.unwrap(), kind, default })
+}
+
+fn parse_requires(pair: Pair<Rule>) -> LysineResult<Node> {
+    let mut ws = WS::default();
+    let mut requirements = vec![];
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::tag_start => {
+                ws.set_left(p.as_str());
+            }
+            Rule::requirement => requirements.push(parse_requirement(p)?),
+            Rule::tag_end => {
+                ws.set_right(p.as_str());
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    Ok(Node::Requires(ws, requirements))
+}
+
 fn parse_include(pair: Pair<Rule>) -> Node {
     let mut ws = WS::default();
     let mut files = vec![];
@@ -593,7 +646,7 @@ fn parse_include(pair: Pair<Rule>) -> Node {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::tag_start => {
-                ws.left = p.as_span().as_str() == "{%-";
+                ws.set_left(p.as_str());
             }
             Rule::string => {
                 files.push(replace_string_markers(p.as_span().as_str()));
@@ -601,7 +654,7 @@ fn parse_include(pair: Pair<Rule>) -> Node {
             Rule::string_array => files.extend(parse_string_array(p)),
             Rule::ignore_missing => ignore_missing = true,
             Rule::tag_end => {
-                ws.right = p.as_span().as_str() == "-%}";
+                ws.set_right(p.as_str());
             }
             _ => unreachable!(),
         };
@@ -610,6 +663,57 @@ fn parse_include(pair: Pair<Rule>) -> Node {
     Node::Include(ws, files, ignore_missing)
 }
 
+fn parse_embed(pair: Pair<Rule>) -> LysineResult<Node> {
+    let mut start_ws = WS::default();
+    let mut end_ws = WS::default();
+    let mut template = None;
+    let mut body = vec![];
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::embed_tag => {
+                for p2 in p.into_inner() {
+                    match p2.as_rule() {
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
+                        Rule::string => template = Some(replace_string_markers(p2.as_str())),
+                        _ => unreachable!(),
+                    };
+                }
+            }
+            Rule::embed_content => {
+                for node in parse_content(p)? {
+                    if let Node::Text(ref s) = node {
+                        if !s.trim().is_empty() {
+                            return Err(Error::msg(format!(
+                                "Only blocks and comments can be used inside `{{% embed %}}`, found the text `{}`",
+                                s.trim()
+                            )));
+                        }
+                    }
+                    body.push(node);
+                }
+            }
+            Rule::endembed_tag => {
+                for p2 in p.into_inner() {
+                    match p2.as_rule() {
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
+                        _ => unreachable!(),
+                    };
+                }
+            }
+            _ => unreachable!("unexpected {:?} rule in parse_embed", p.as_rule()),
+        };
+    }
+
+    Ok(Node::Embed(
+        start_ws,
+        Embed { template: template.unwrap(), name: String::new(), body },
+        end_ws,
+    ))
+}
+
 fn parse_set_tag(pair: Pair<Rule>, global: bool) -> LysineResult<Node> {
     let mut ws = WS::default();
     let mut key = None;
@@ -618,14 +722,13 @@ fn parse_set_tag(pair: Pair<Rule>, global: bool) -> LysineResult<Node> {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::tag_start => {
-                ws.left = p.as_span().as_str() == "{%-";
+                ws.set_left(p.as_str());
             }
             Rule::tag_end => {
-                ws.right = p.as_span().as_str() == "-%}";
+                ws.set_right(p.as_str());
             }
             Rule::ident => key = Some(p.as_str().to_string()),
             Rule::logic_expr => expr = Some(parse_logic_expr(p)?),
-            Rule::array_filter => expr = Some(parse_array_with_filters(p)?),
             _ => unreachable!("unexpected {:?} rule in parse_set_tag", p.as_rule()),
         }
     }
@@ -643,8 +746,8 @@ fn parse_raw_tag(pair: Pair<Rule>) -> Node {
             Rule::raw_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => start_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => start_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
                         _ => unreachable!(),
                     }
                 }
@@ -653,8 +756,8 @@ fn parse_raw_tag(pair: Pair<Rule>) -> Node {
             Rule::endraw_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => end_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => end_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
                         _ => unreachable!(),
                     }
                 }
@@ -677,12 +780,13 @@ fn parse_filter_section(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::filter_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => start_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => start_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
                         Rule::fn_call => filter = Some(parse_fn_call(p2)?),
                         Rule::ident => {
                             filter = Some(FunctionCall {
                                 name: p2.as_str().to_string(),
+                                positional_args: vec![],
                                 args: HashMap::new(),
                             });
                         }
@@ -700,8 +804,8 @@ fn parse_filter_section(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::endfilter_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => end_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => end_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
                         _ => unreachable!(),
                     }
                 }
@@ -716,6 +820,8 @@ fn parse_block(pair: Pair<Rule>) -> LysineResult<Node> {
     let mut start_ws = WS::default();
     let mut end_ws = WS::default();
     let mut name = None;
+    let mut scoped = false;
+    let mut required = false;
     let mut body = vec![];
 
     for p in pair.into_inner() {
@@ -723,9 +829,14 @@ fn parse_block(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::block_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => start_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => start_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
                         Rule::ident => name = Some(p2.as_span().as_str().to_string()),
+                        Rule::block_modifier => match p2.as_str() {
+                            "scoped" => scoped = true,
+                            "required" => required = true,
+                            _ => unreachable!(),
+                        },
                         _ => unreachable!(),
                     };
                 }
@@ -734,8 +845,8 @@ fn parse_block(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::endblock_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => end_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => end_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
                         Rule::ident => (),
                         _ => unreachable!(),
                     };
@@ -745,65 +856,86 @@ fn parse_block(pair: Pair<Rule>) -> LysineResult<Node> {
         };
     }
 
-    Ok(Node::Block(start_ws, Block { name: name.unwrap(), body }, end_ws))
+    Ok(Node::Block(start_ws, Block { name: name.unwrap(), scoped, required, body }, end_ws))
 }
 
-fn parse_macro_arg(p: Pair<Rule>) -> LysineResult<ExprVal> {
-    let val = match p.as_rule() {
-        Rule::int => Some(ExprVal::Int(
-            p.as_str()
-                .parse()
-                .map_err(|_| Error::msg(format!("Integer out of bounds: `{}`", p.as_str())))?,
-        )),
-        Rule::float => Some(ExprVal::Float(
-            p.as_str()
-                .parse()
-                .map_err(|_| Error::msg(format!("Float out of bounds: `{}`", p.as_str())))?,
-        )),
-        Rule::boolean => match p.as_str() {
-            "true" => Some(ExprVal::Bool(true)),
-            "True" => Some(ExprVal::Bool(true)),
-            "false" => Some(ExprVal::Bool(false)),
-            "False" => Some(ExprVal::Bool(false)),
-            _ => unreachable!(),
-        },
-        Rule::string => Some(ExprVal::String(replace_string_markers(p.as_str()))),
-        _ => unreachable!("Got {:?} in parse_macro_arg: {}", p.as_rule(), p.as_str()),
-    };
-
-    Ok(val.unwrap())
-}
+// The arguments of a macro definition, in declaration order, and the optional names of
+// the `*args` and `**kwargs` parameters
+type MacroSignature = (Vec<(String, Option<Expr>)>, Option<String>, Option<String>);
 
-fn parse_macro_fn(pair: Pair<Rule>) -> LysineResult<(String, HashMap<String, Option<Expr>>)> {
+fn parse_macro_fn(pair: Pair<Rule>) -> LysineResult<(String, MacroSignature)> {
     let mut name = String::new();
-    let mut args = HashMap::new();
+    let mut args: Vec<(String, Option<Expr>)> = vec![];
+    let mut varargs = None;
+    let mut kwargs = None;
 
     for p2 in pair.into_inner() {
-        match p2.as_rule() {
-            Rule::ident => name = p2.as_str().to_string(),
+        let rule = p2.as_rule();
+        if rule == Rule::ident {
+            name = p2.as_str().to_string();
+            continue;
+        }
+
+        let mut arg_name = String::new();
+        let mut default_val = None;
+        for p3 in p2.into_inner() {
+            match p3.as_rule() {
+                Rule::ident => arg_name = p3.as_str().to_string(),
+                Rule::logic_expr => default_val = Some(parse_logic_expr(p3)?),
+                _ => unreachable!("Got {:?} in parse_macro_fn", p3.as_rule()),
+            };
+        }
+
+        if args.iter().any(|(n, _)| n == &arg_name)
+            || varargs.as_ref() == Some(&arg_name)
+            || kwargs.as_ref() == Some(&arg_name)
+        {
+            return Err(Error::msg(format!(
+                "Macro `{}` has the argument `{}` defined more than once",
+                name, arg_name
+            )));
+        }
+        if kwargs.is_some() {
+            return Err(Error::msg(format!(
+                "Macro `{}` has arguments after its `**` argument, it needs to be the last one",
+                name
+            )));
+        }
+
+        match rule {
             Rule::macro_def_arg => {
-                let mut arg_name = None;
-                let mut default_val = None;
-                for p3 in p2.into_inner() {
-                    match p3.as_rule() {
-                        Rule::ident => arg_name = Some(p3.as_str().to_string()),
-                        _ => default_val = Some(Expr::new(parse_macro_arg(p3)?)),
-                    };
+                if varargs.is_some() {
+                    return Err(Error::msg(format!(
+                        "Macro `{}` has the argument `{}` after its `*` argument",
+                        name, arg_name
+                    )));
+                }
+                args.push((arg_name, default_val));
+            }
+            Rule::macro_def_varargs => {
+                if varargs.is_some() {
+                    return Err(Error::msg(format!(
+                        "Macro `{}` can only have a single `*` argument",
+                        name
+                    )));
                 }
-                args.insert(arg_name.unwrap(), default_val);
+                varargs = Some(arg_name);
             }
-            _ => continue,
+            Rule::macro_def_kwargs => kwargs = Some(arg_name),
+            _ => unreachable!("Got {:?} in parse_macro_fn", rule),
         }
     }
 
-    Ok((name, args))
+    Ok((name, (args, varargs, kwargs)))
 }
 
 fn parse_macro_definition(pair: Pair<Rule>) -> LysineResult<Node> {
     let mut start_ws = WS::default();
     let mut end_ws = WS::default();
     let mut name = String::new();
-    let mut args = HashMap::new();
+    let mut args = vec![];
+    let mut varargs = None;
+    let mut kwargs = None;
     let mut body = vec![];
 
     for p in pair.into_inner() {
@@ -811,12 +943,12 @@ fn parse_macro_definition(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::macro_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => start_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => start_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
                         Rule::macro_fn_wrapper => {
-                            let macro_fn = parse_macro_fn(p2)?;
-                            name = macro_fn.0;
-                            args = macro_fn.1;
+                            let (macro_name, signature) = parse_macro_fn(p2)?;
+                            name = macro_name;
+                            (args, varargs, kwargs) = signature;
                         }
                         _ => continue,
                     };
@@ -826,8 +958,8 @@ fn parse_macro_definition(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::endmacro_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => end_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => end_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
                         Rule::ident => (),
                         _ => unreachable!(),
                     };
@@ -837,7 +969,11 @@ fn parse_macro_definition(pair: Pair<Rule>) -> LysineResult<Node> {
         }
     }
 
-    Ok(Node::MacroDefinition(start_ws, MacroDefinition { name, args, body }, end_ws))
+    Ok(Node::MacroDefinition(
+        start_ws,
+        MacroDefinition { name, args, varargs, kwargs, body },
+        end_ws,
+    ))
 }
 
 fn parse_forloop(pair: Pair<Rule>) -> LysineResult<Node> {
@@ -856,13 +992,10 @@ fn parse_forloop(pair: Pair<Rule>) -> LysineResult<Node> {
                 let mut idents = vec![];
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => start_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => start_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
                         Rule::ident => idents.push(p2.as_str().to_string()),
-                        Rule::basic_expr_filter => {
-                            container = Some(parse_basic_expr_with_filters(p2)?);
-                        }
-                        Rule::array_filter => container = Some(parse_array_with_filters(p2)?),
+                        Rule::for_container => container = Some(parse_expr(p2.into_inner())?),
                         _ => unreachable!(),
                     };
                 }
@@ -890,8 +1023,8 @@ fn parse_forloop(pair: Pair<Rule>) -> LysineResult<Node> {
             Rule::endfor_tag => {
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => end_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => end_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
                         Rule::ident => (),
                         _ => unreachable!(),
                     };
@@ -908,16 +1041,52 @@ fn parse_forloop(pair: Pair<Rule>) -> LysineResult<Node> {
     ))
 }
 
+fn parse_whileloop(pair: Pair<Rule>) -> LysineResult<Node> {
+    let mut start_ws = WS::default();
+    let mut end_ws = WS::default();
+
+    let mut condition = None;
+    let mut body = vec![];
+
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::while_tag => {
+                for p2 in p.into_inner() {
+                    match p2.as_rule() {
+                        Rule::tag_start => start_ws.set_left(p2.as_str()),
+                        Rule::tag_end => start_ws.set_right(p2.as_str()),
+                        Rule::logic_expr => condition = Some(parse_logic_expr(p2)?),
+                        _ => unreachable!(),
+                    };
+                }
+            }
+            Rule::for_content => body.extend(parse_content(p)?),
+            Rule::endwhile_tag => {
+                for p2 in p.into_inner() {
+                    match p2.as_rule() {
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
+                        _ => unreachable!(),
+                    };
+                }
+            }
+            _ => unreachable!("unexpected {:?} rule in parse_whileloop", p.as_rule()),
+        };
+    }
+
+    Ok(Node::Whileloop(start_ws, Whileloop { condition: condition.unwrap(), body }, end_ws))
+}
+
 fn parse_break_tag(pair: Pair<Rule>) -> Node {
     let mut ws = WS::default();
 
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::tag_start => {
-                ws.left = p.as_span().as_str() == "{%-";
+                ws.set_left(p.as_str());
             }
             Rule::tag_end => {
-                ws.right = p.as_span().as_str() == "-%}";
+                ws.set_right(p.as_str());
             }
             _ => unreachable!(),
         };
@@ -932,10 +1101,10 @@ fn parse_continue_tag(pair: Pair<Rule>) -> Node {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::tag_start => {
-                ws.left = p.as_span().as_str() == "{%-";
+                ws.set_left(p.as_str());
             }
             Rule::tag_end => {
-                ws.right = p.as_span().as_str() == "-%}";
+                ws.set_right(p.as_str());
             }
             _ => unreachable!(),
         };
@@ -951,10 +1120,10 @@ fn parse_comment_tag(pair: Pair<Rule>) -> Node {
     for p in pair.into_inner() {
         match p.as_rule() {
             Rule::com_start => {
-                ws.left = p.as_span().as_str() == "{#-";
+                ws.set_left(p.as_str());
             }
             Rule::com_end => {
-                ws.right = p.as_span().as_str() == "-#}";
+                ws.set_right(p.as_str());
             }
             Rule::com_text => {
                 content = p.as_str().to_owned();
@@ -991,8 +1160,8 @@ fn parse_if(pair: Pair<Rule>) -> LysineResult<Node> {
 
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => current_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => current_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => current_ws.set_left(p2.as_str()),
+                        Rule::tag_end => current_ws.set_right(p2.as_str()),
                         Rule::logic_expr => expr = Some(parse_logic_expr(p2)?),
                         _ => unreachable!(),
                     };
@@ -1014,8 +1183,8 @@ fn parse_if(pair: Pair<Rule>) -> LysineResult<Node> {
                 in_else = true;
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => current_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => current_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => current_ws.set_left(p2.as_str()),
+                        Rule::tag_end => current_ws.set_right(p2.as_str()),
                         _ => unreachable!(),
                     };
                 }
@@ -1030,8 +1199,8 @@ fn parse_if(pair: Pair<Rule>) -> LysineResult<Node> {
 
                 for p2 in p.into_inner() {
                     match p2.as_rule() {
-                        Rule::tag_start => end_ws.left = p2.as_span().as_str() == "{%-",
-                        Rule::tag_end => end_ws.right = p2.as_span().as_str() == "-%}",
+                        Rule::tag_start => end_ws.set_left(p2.as_str()),
+                        Rule::tag_end => end_ws.set_right(p2.as_str()),
                         _ => unreachable!(),
                     };
                 }
@@ -1051,6 +1220,8 @@ fn parse_content(pair: Pair<Rule>) -> LysineResult<Vec<Node>> {
     for p in pairs {
         match p.as_rule() {
             Rule::include_tag => nodes.push(parse_include(p)),
+            Rule::embed => nodes.push(parse_embed(p)?),
+            Rule::from_import_tag => nodes.push(parse_from_import(p)),
             Rule::com_tag => nodes.push(parse_comment_tag(p)),
             Rule::super_tag => nodes.push(Node::Super),
             Rule::set_tag => nodes.push(parse_set_tag(p, false)?),
@@ -1058,6 +1229,7 @@ fn parse_content(pair: Pair<Rule>) -> LysineResult<Vec<Node>> {
             Rule::raw => nodes.push(parse_raw_tag(p)),
             Rule::var_tag => nodes.push(parse_variable_tag(p)?),
             Rule::forloop => nodes.push(parse_forloop(p)?),
+            Rule::whileloop => nodes.push(parse_whileloop(p)?),
             Rule::break_tag => nodes.push(parse_break_tag(p)),
             Rule::continue_tag => nodes.push(parse_continue_tag(p)),
             Rule::content_if
@@ -1089,13 +1261,14 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
                     | Rule::single_quoted_string => {
                         "a string".to_string()
                     }
-                    Rule::string_concat => "a concatenation of strings".to_string(),
-                    Rule::string_expr_filter => "a string or a concatenation of strings".to_string(),
                     Rule::all_chars => "a character".to_string(),
                     Rule::array => "an array of values".to_string(),
-                    Rule::array_filter => "an array of values with an optional filter".to_string(),
                     Rule::string_array => "an array of strings".to_string(),
                     Rule::basic_val => "a value".to_string(),
+                    Rule::paren_expr => "an expression inside parentheses".to_string(),
+                    Rule::access_val => "a value with optional attribute or index accesses".to_string(),
+                    Rule::attr_access => "an attribute access: `.name` or `?.name`".to_string(),
+                    Rule::index_access => "an index access: `[expression]` or `?.[expression]`".to_string(),
                     Rule::basic_op => "a mathematical operator".to_string(),
                     Rule::comparison_op => "a comparison operator".to_string(),
                     Rule::boolean => "`true` or `false`".to_string(),
@@ -1103,14 +1276,17 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
                     Rule::dotted_ident => "a dotted identifier (identifiers separated by `.`)".to_string(),
                     Rule::dotted_square_bracket_ident => "a square bracketed identifier (identifiers separated by `.` or `[]`s)".to_string(),
                     Rule::square_brackets => "an identifier, string or integer inside `[]`s".to_string(),
-                    Rule::basic_expr_filter => "an expression with an optional filter".to_string(),
-                    Rule::comparison_val => "a comparison value".to_string(),
-                    Rule::basic_expr | Rule::comparison_expr => "an expression".to_string(),
-                    Rule::logic_val => "a value that can be negated".to_string(),
-                    Rule::logic_expr => "any expressions".to_string(),
+                    Rule::math_operand => "an integer, a float, `true` or `false`, an identifier (must start with a-z), a square bracketed identifier (identifiers separated by `.` or `[]`s), or an expression".to_string(),
+                    Rule::operand | Rule::operand_val | Rule::expr | Rule::expr_tail => "an expression".to_string(),
+                    Rule::logic_expr => "a value that can be negated or an array of values".to_string(),
+                    Rule::for_container => "an expression or an array of values".to_string(),
                     Rule::fn_call => "a function call".to_string(),
+                    Rule::method_name => "a method name: `object.method`".to_string(),
+                    Rule::method_call => "a method call".to_string(),
                     Rule::kwarg => "a keyword argument: `key=value` where `value` can be any expressions".to_string(),
                     Rule::kwargs => "a list of keyword arguments: `key=value` where `value` can be any expressions and separated by `,`".to_string(),
+                    Rule::positional_arg => "a positional argument (any expressions including arrays)".to_string(),
+                    Rule::call_args => "a list of arguments, positional ones first, separated by `,`".to_string(),
                     Rule::op_or => "`or`".to_string(),
                     Rule::op_and => "`and`".to_string(),
                     Rule::op_not => "`not`".to_string(),
@@ -1125,19 +1301,31 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
                     Rule::op_mult => "`*`".to_string(),
                     Rule::op_div => "`/`".to_string(),
                     Rule::op_modulo => "`%`".to_string(),
+                    Rule::op_concat | Rule::filtered_concat => "`~`".to_string(),
+                    Rule::filtered_sum => "`+` or `-`".to_string(),
+                    Rule::filtered_product => "`*`, `/` or `%`".to_string(),
+                    Rule::filtered_op => "an operator".to_string(),
+                    Rule::infix_op => "an operator".to_string(),
+                    Rule::op_neg => "`-`".to_string(),
+                    Rule::op_in => "`in`".to_string(),
+                    Rule::op_coalesce => "`??`".to_string(),
+                    Rule::op_safe_nav => "`?.`".to_string(),
                     Rule::filter => "a filter".to_string(),
                     Rule::test => "a test".to_string(),
-                    Rule::test_not => "a negated test".to_string(),
+                    Rule::test_not => "`not`".to_string(),
                     Rule::test_call => "a test call".to_string(),
                     Rule::test_arg => "a test argument (any expressions including arrays)".to_string(),
                     Rule::test_args => "a list of test arguments (any expression including arrays)".to_string(),
                     Rule::macro_fn | Rule::macro_fn_wrapper => "a macro function".to_string(),
                     Rule::macro_call => "a macro function call".to_string(),
+                    Rule::macro_ref => "a macro reference: `namespace::macro_name`".to_string(),
                     Rule::macro_def_arg => {
-                        "an argument name with an optional default lilysinel value: `id`, `key=1`".to_string()
+                        "an argument name with an optional default value: `id`, `key=1`".to_string()
                     }
-                    Rule::macro_def_args => {
-                        "a list of argument names with an optional default lilysinel value: `id`, `key=1`".to_string()
+                    Rule::macro_def_varargs => "an argument collecting extra positional arguments: `*args`".to_string(),
+                    Rule::macro_def_kwargs => "an argument collecting extra keyword arguments: `**kwargs`".to_string(),
+                    Rule::macro_def_param | Rule::macro_def_args => {
+                        "a list of argument names with an optional default value: `id`, `key=1`".to_string()
                     }
                     Rule::endmacro_tag => "`{% endmacro %}`".to_string(),
                     Rule::macro_content => "the macro content".to_string(),
@@ -1160,6 +1348,9 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
                     Rule::endraw_tag => "`{% endraw %}`".to_string(),
                     Rule::ignore_missing => "ignore missing mark for include tag".to_string(),
                     Rule::include_tag => r#"an include tag (`{% include "..." %}`)"#.to_string(),
+                    Rule::embed | Rule::embed_tag => r#"an embed tag (`{% embed "..." %}`)"#.to_string(),
+                    Rule::endembed_tag => "an endembed tag (`{% endembed %}`)".to_string(),
+                    Rule::embed_content => "the blocks of an embed".to_string(),
                     Rule::com_tag => "a comment tag (`{#...#}`)".to_string(),
                     Rule::com_text => "the context of a comment (`{# ... #}`)".to_string(),
                     Rule::var_tag => "a variable tag (`{{ ... }}`)".to_string(),
@@ -1171,6 +1362,10 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
                     },
                     Rule::endfilter_tag => "an endfilter tag (`{% endfilter %}`)".to_string(),
                     Rule::endfor_tag => "an endfor tag (`{% endfor %}`)".to_string(),
+                    Rule::while_tag | Rule::whileloop => {
+                        "a while loop (`{% while condition %}...{% endwhile %}`)".to_string()
+                    }
+                    Rule::endwhile_tag => "an endwhile tag (`{% endwhile %}`)".to_string(),
                     Rule::if_tag
                     | Rule::content_if
                     | Rule::block_if
@@ -1189,17 +1384,21 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
                     Rule::com_end => "a comment end (`#}`)".to_string(),
                     Rule::block_start => "`{{`, `{%` or `{#`".to_string(),
                     Rule::import_macro_tag => r#"an import macro tag (`{% import "filename" as namespace %}`"#.to_string(),
+                    Rule::from_import_tag => r#"a from import tag (`{% from "filename" import name, other as alias %}`"#.to_string(),
+                    Rule::from_import_name => "a macro name with an optional alias: `name`, `name as alias`".to_string(),
                     Rule::block | Rule::block_tag => r#"a block tag (`{% block block_name %}`"#.to_string(),
+                    Rule::block_modifier => "`scoped` or `required`".to_string(),
                     Rule::endblock_tag => r#"an endblock tag (`{% endblock block_name %}`"#.to_string(),
                     Rule::macro_definition
                     | Rule::macro_tag => r#"a macro definition tag (`{% macro my_macro() %}`"#.to_string(),
                     Rule::extends_tag => r#"an extends tag (`{% extends "myfile" %}`"#.to_string(),
+                    Rule::requires_tag => r#"a requires tag (`{% requires user, locale = "en" %}`)"#.to_string(),
+                    Rule::requirement => r#"a required variable with an optional type and default: `user`, `locale: string = "en"`"#.to_string(),
+                    Rule::requirement_type => "a type (`string`, `number`, `integer`, `boolean`, `array` or `object`)".to_string(),
                     Rule::template => "a template".to_string(),
                     Rule::break_tag => "a break tag".to_string(),
                     Rule::continue_tag => "a continue tag".to_string(),
                     Rule::top_imports => "top imports".to_string(),
-                    Rule::in_cond => "a `in` condition".to_string(),
-                    Rule::in_cond_container => "a `in` condition container: a string, an array or an ident".to_string(),
                 }
             });
             return Err(Error::msg(fancy_e));
@@ -1212,7 +1411,9 @@ pub fn parse(input: &str) -> LysineResult<Vec<Node>> {
     for p in pairs.next().unwrap().into_inner() {
         match p.as_rule() {
             Rule::extends_tag => nodes.push(parse_extends(p)),
+            Rule::requires_tag => nodes.push(parse_requires(p)?),
             Rule::import_macro_tag => nodes.push(parse_import_macro(p)),
+            Rule::from_import_tag => nodes.push(parse_from_import(p)),
             Rule::content => nodes.extend(parse_content(p)?),
             Rule::macro_definition => nodes.push(parse_macro_definition(p)?),
             Rule::com_tag => (),
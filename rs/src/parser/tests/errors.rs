@@ -118,10 +118,34 @@ fn invalid_macro_not_toplevel() {
 fn invalid_macro_default_arg_value() {
     assert_err_msg(
         r#"
-{% macro input(label=something) %}
+{% macro input(label=) %}
 {% endmacro input %}
     "#,
-        &["2:22", "expected an integer, a float, a string, or `true` or `false`"],
+        &["2:22", "expected a value that can be negated"],
+    );
+}
+
+#[test]
+fn invalid_macro_arg_after_varargs() {
+    assert_err_msg(
+        r#"{% macro input(*rest, label) %}{% endmacro input %}"#,
+        &["Macro `input` has the argument `label` after its `*` argument"],
+    );
+}
+
+#[test]
+fn invalid_macro_duplicate_arg() {
+    assert_err_msg(
+        r#"{% macro input(label, label=1) %}{% endmacro input %}"#,
+        &["Macro `input` has the argument `label` defined more than once"],
+    );
+}
+
+#[test]
+fn invalid_macro_call_positional_after_keyword() {
+    assert_err_msg(
+        "{{ my::macro(label=1, 2) }}",
+        &["Positional argument `2` follows keyword arguments in the call to `my::macro`"],
     );
 }
 
@@ -240,6 +264,25 @@ fn invalid_include_no_string() {
     assert_err_msg("{% include 1 %}", &["1:12", "expected a string"]);
 }
 
+#[test]
+fn invalid_requires_type() {
+    assert_err_msg(
+        "{% requires user: person %}",
+        &[
+            "1:19",
+            "expected a type (`string`, `number`, `integer`, `boolean`, `array` or `object`)",
+        ],
+    );
+}
+
+#[test]
+fn invalid_requires_default() {
+    assert_err_msg(
+        "{% requires tags = [name] %}",
+        &["The default value of `tags` in `{% requires %}` can only contain literals, found `[name]`"],
+    );
+}
+
 #[test]
 fn unterminated_extends() {
     assert_err_msg("{% extends %}", &["1:12", "expected a string"]);
@@ -282,7 +325,7 @@ fn invalid_test_argument() {
         r#"{% if a is odd(key=1) %}"#,
         &[
             "1:19",
-            "expected `or`, `and`, `not`, `<=`, `>=`, `<`, `>`, `==`, `!=`, `+`, `-`, `*`, `/`, `%`, or a filter"
+            "expected `or`, `and`, `not`, `<=`, `>=`, `<`, `>`, `==`, `!=`, `+`, `-`, `*`, `/`, `%`, `~`, `??`, `?.`, a filter, or a test"
         ],
     );
 }
@@ -130,7 +130,7 @@ fn lex_string_concat() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::string_concat, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -168,7 +168,7 @@ fn lex_basic_expr() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::basic_expr, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -190,7 +190,7 @@ fn lex_basic_expr_with_filter() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::basic_expr_filter, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -207,7 +207,7 @@ fn lex_string_expr_with_filter() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::string_expr_filter, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -240,7 +240,7 @@ fn lex_comparison_val() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::comparison_val, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -263,7 +263,7 @@ fn lex_in_cond() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::in_cond, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -288,7 +288,7 @@ fn lex_comparison_expr() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::comparison_expr, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -322,7 +322,7 @@ fn lex_logic_val() {
     ];
 
     for i in inputs {
-        assert_lex_rule!(Rule::logic_val, i);
+        assert_lex_rule!(Rule::logic_expr, i);
     }
 }
 
@@ -418,11 +418,18 @@ fn lex_macro_definition() {
 
 #[test]
 fn lex_test() {
-    let inputs =
-        vec!["a is defined", "a is defined()", "a is divisibleby(2)", "a is in([1, 2, something])"];
+    let inputs = vec![
+        "a is defined",
+        "a is defined()",
+        "a is divisibleby(2)",
+        "a is in([1, 2, something])",
+        "a is not odd",
+        "(items | first) is odd",
+        "get_user().age is odd",
+    ];
     for i in inputs {
         // The () are not counted as tokens for some reasons so can't use the macro
-        assert!(LysineParser::parse(Rule::test, i).is_ok());
+        assert!(LysineParser::parse(Rule::logic_expr, i).is_ok());
     }
 }
 
@@ -546,6 +553,19 @@ fn lex_for_tag() {
     }
 }
 
+#[test]
+fn lex_while_tag() {
+    let inputs = vec![
+        "{%- while true %}",
+        "{% while i < 10 -%}",
+        "{% while pages[page] | length > 0 && !! done %}",
+    ];
+
+    for i in inputs {
+        assert_lex_rule!(Rule::while_tag, i);
+    }
+}
+
 #[test]
 fn lex_break_tag() {
     assert!(LysineParser::parse(Rule::break_tag, "{% break %}").is_ok());
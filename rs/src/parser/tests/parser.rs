@@ -26,14 +26,18 @@ fn parse_include_tag() {
     let ast = parse("{% include \"index.html\" -%}").unwrap();
     assert_eq!(
         ast[0],
-        Node::Include(WS { left: false, right: true }, vec!["index.html".to_string()], false,),
+        Node::Include(
+            WS { left: false, right: true, ..Default::default() },
+            vec!["index.html".to_string()],
+            false,
+        ),
     );
     let ast =
         parse("{% include [\"custom/index.html\", \"index.html\"] ignore missing %}").unwrap();
     assert_eq!(
         ast[0],
         Node::Include(
-            WS { left: false, right: false },
+            WS { left: false, right: false, ..Default::default() },
             vec!["custom/index.html".to_string(), "index.html".to_string()],
             true,
         ),
@@ -43,13 +47,57 @@ fn parse_include_tag() {
 #[test]
 fn parse_extends() {
     let ast = parse("{% extends \"index.html\" -%}").unwrap();
-    assert_eq!(ast[0], Node::Extends(WS { left: false, right: true }, "index.html".to_string(),),);
+    assert_eq!(
+        ast[0],
+        Node::Extends(
+            WS { left: false, right: true, ..Default::default() },
+            "index.html".to_string(),
+        ),
+    );
+}
+
+#[test]
+fn parse_requires() {
+    let ast = parse(
+        "{% requires user: object, cart.items = [], locale: string = \"en\" %}\n{% extends \"base.html\" %}",
+    )
+    .unwrap();
+    assert_eq!(
+        ast[0],
+        Node::Requires(
+            WS::default(),
+            vec![
+                Requirement {
+                    name: "user".to_string(),
+                    kind: Some(RequiredType::Object),
+                    default: None
+                },
+                Requirement {
+                    name: "cart.items".to_string(),
+                    kind: None,
+                    default: Some(serde_json::json!([]))
+                },
+                Requirement {
+                    name: "locale".to_string(),
+                    kind: Some(RequiredType::String),
+                    default: Some(serde_json::json!("en"))
+                },
+            ],
+        ),
+    );
+    assert_eq!(ast[1], Node::Extends(WS::default(), "base.html".to_string()));
 }
 
 #[test]
 fn parse_comments_before_extends() {
     let ast = parse("{# A comment #}{% extends \"index.html\" -%}").unwrap();
-    assert_eq!(ast[0], Node::Extends(WS { left: false, right: true }, "index.html".to_string(),),);
+    assert_eq!(
+        ast[0],
+        Node::Extends(
+            WS { left: false, right: true, ..Default::default() },
+            "index.html".to_string(),
+        ),
+    );
 }
 
 #[test]
@@ -58,20 +106,36 @@ fn parse_import_macro() {
     assert_eq!(
         ast[0],
         Node::ImportMacro(
-            WS { left: false, right: true },
+            WS { left: false, right: true, ..Default::default() },
             "macros.html".to_string(),
             "macros".to_string(),
         ),
     );
 }
 
+#[test]
+fn parse_from_import() {
+    let ast = parse("{% from \"forms.html\" import input, select as dropdown %}").unwrap();
+    assert_eq!(
+        ast[0],
+        Node::FromImport(
+            WS::default(),
+            "forms.html".to_string(),
+            vec![
+                ("input".to_string(), "input".to_string()),
+                ("select".to_string(), "dropdown".to_string()),
+            ],
+        ),
+    );
+}
+
 #[test]
 fn parse_variable_with_whitespace_trimming() {
     let ast = parse("{{- id }}").unwrap();
     assert_eq!(
         ast[0],
         Node::VariableBlock(
-            WS { left: true, right: false },
+            WS { left: true, right: false, ..Default::default() },
             Expr::new(ExprVal::Ident("id".to_string()))
         ),
     );
@@ -99,8 +163,16 @@ fn parse_variable_tag_ident_with_simple_filters() {
             Expr::with_filters(
                 ExprVal::Ident("arr".to_string()),
                 vec![
-                    FunctionCall { name: "first".to_string(), args: HashMap::new() },
-                    FunctionCall { name: "join".to_string(), args: join_args },
+                    FunctionCall {
+                        name: "first".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },
+                    FunctionCall {
+                        name: "join".to_string(),
+                        positional_args: vec![],
+                        args: join_args
+                    },
                 ],
             )
         )
@@ -154,7 +226,11 @@ fn parse_variable_tag_array_lit_with_filter() {
                     Expr::new(ExprVal::Int(2)),
                     Expr::new(ExprVal::Int(3))
                 ]),
-                vec![FunctionCall { name: "length".to_string(), args: HashMap::new() },],
+                vec![FunctionCall {
+                    name: "length".to_string(),
+                    positional_args: vec![],
+                    args: HashMap::new()
+                },],
             )
         )
     );
@@ -218,7 +294,11 @@ fn parse_variable_tag_lit_math_expression_with_parentheses_and_filter() {
                     operator: MathOperator::Mul,
                     rhs: Box::new(Expr::new(ExprVal::Float(2.5))),
                 },),
-                vec![FunctionCall { name: "round".to_string(), args: HashMap::new() },],
+                vec![FunctionCall {
+                    name: "round".to_string(),
+                    positional_args: vec![],
+                    args: HashMap::new()
+                },],
             )
         )
     );
@@ -234,7 +314,11 @@ fn parse_variable_math_on_filter() {
             Expr::new(ExprVal::Math(MathExpr {
                 lhs: Box::new(Expr::with_filters(
                     ExprVal::Ident("a".to_string()),
-                    vec![FunctionCall { name: "length".to_string(), args: HashMap::new() },],
+                    vec![FunctionCall {
+                        name: "length".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },],
                 )),
                 operator: MathOperator::Sub,
                 rhs: Box::new(Expr::new(ExprVal::Int(1))),
@@ -301,7 +385,11 @@ fn parse_variable_tag_math_with_filters_and_logic_expression() {
                             rhs: Box::new(Expr::new(ExprVal::Float(2.5))),
                         },))),
                     },),
-                    vec![FunctionCall { name: "round".to_string(), args: HashMap::new() },],
+                    vec![FunctionCall {
+                        name: "round".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },],
                 )),
                 operator: LogicOperator::And,
                 rhs: Box::new(Expr::new(ExprVal::Ident("admin".to_string()))),
@@ -327,7 +415,7 @@ fn parse_test() {
         Node::VariableBlock(
             WS::default(),
             Expr::new(ExprVal::Test(Test {
-                ident: "a".to_string(),
+                expr: Box::new(Expr::new(ExprVal::Ident("a".to_string()))),
                 negated: false,
                 name: "divisibleby".to_string(),
                 args: vec![Expr::new(ExprVal::Int(2))]
@@ -392,7 +480,7 @@ fn parse_variable_tag_simple_test() {
         Node::VariableBlock(
             WS::default(),
             Expr::new(ExprVal::Test(Test {
-                ident: "id".to_string(),
+                expr: Box::new(Expr::new(ExprVal::Ident("id".to_string()))),
                 negated: false,
                 name: "defined".to_string(),
                 args: vec![],
@@ -409,7 +497,7 @@ fn parse_variable_tag_simple_negated_test() {
         Node::VariableBlock(
             WS::default(),
             Expr::new(ExprVal::Test(Test {
-                ident: "id".to_string(),
+                expr: Box::new(Expr::new(ExprVal::Ident("id".to_string()))),
                 negated: true,
                 name: "defined".to_string(),
                 args: vec![],
@@ -427,7 +515,7 @@ fn parse_variable_tag_test_as_expression() {
             WS::default(),
             Expr::new(ExprVal::Logic(LogicExpr {
                 lhs: Box::new(Expr::new(ExprVal::Test(Test {
-                    ident: "user".to_string(),
+                    expr: Box::new(Expr::new(ExprVal::Ident("user".to_string()))),
                     negated: false,
                     name: "defined".to_string(),
                     args: vec![],
@@ -452,6 +540,7 @@ fn parse_variable_tag_macro_call() {
             Expr::new(ExprVal::MacroCall(MacroCall {
                 namespace: "macros".to_string(),
                 name: "get_time".to_string(),
+                positional_args: vec![],
                 args,
             },)),
         )
@@ -468,11 +557,17 @@ fn parse_allow_block_in_filter_section() {
         Node::FilterSection(
             WS::default(),
             FilterSection {
-                filter: FunctionCall { name: "upper".to_owned(), args: HashMap::default() },
+                filter: FunctionCall {
+                    name: "upper".to_owned(),
+                    positional_args: vec![],
+                    args: HashMap::default()
+                },
                 body: vec![Node::Block(
                     WS::default(),
                     Block {
                         name: "content".to_owned(),
+                        scoped: false,
+                        required: false,
                         body: vec![Node::Text("Hello".to_owned())]
                     },
                     WS::default(),
@@ -500,6 +595,7 @@ fn parse_variable_tag_macro_call_with_array() {
             Expr::new(ExprVal::MacroCall(MacroCall {
                 namespace: "macros".to_string(),
                 name: "get_time".to_string(),
+                positional_args: vec![],
                 args,
             },))
         )
@@ -515,7 +611,11 @@ fn parse_variable_tag_macro_call_with_array_with_filters() {
         "some".to_string(),
         Expr::with_filters(
             ExprVal::Array(vec![Expr::new(ExprVal::Int(1)), Expr::new(ExprVal::Int(2))]),
-            vec![FunctionCall { name: "reverse".to_string(), args: HashMap::new() }],
+            vec![FunctionCall {
+                name: "reverse".to_string(),
+                positional_args: vec![],
+                args: HashMap::new(),
+            }],
         ),
     );
 
@@ -526,6 +626,7 @@ fn parse_variable_tag_macro_call_with_array_with_filters() {
             Expr::new(ExprVal::MacroCall(MacroCall {
                 namespace: "macros".to_string(),
                 name: "get_time".to_string(),
+                positional_args: vec![],
                 args,
             },))
         )
@@ -546,9 +647,14 @@ fn parse_variable_tag_macro_call_with_filter() {
                 ExprVal::MacroCall(MacroCall {
                     namespace: "macros".to_string(),
                     name: "get_time".to_string(),
+                    positional_args: vec![],
                     args,
                 },),
-                vec![FunctionCall { name: "round".to_string(), args: HashMap::new() },],
+                vec![FunctionCall {
+                    name: "round".to_string(),
+                    positional_args: vec![],
+                    args: HashMap::new()
+                },],
             )
         )
     );
@@ -564,7 +670,11 @@ fn parse_variable_tag_global_function() {
         ast[0],
         Node::VariableBlock(
             WS::default(),
-            Expr::new(ExprVal::FunctionCall(FunctionCall { name: "get_time".to_string(), args },))
+            Expr::new(ExprVal::FunctionCall(FunctionCall {
+                name: "get_time".to_string(),
+                positional_args: vec![],
+                args
+            },))
         )
     );
 }
@@ -618,10 +728,22 @@ fn parse_variable_tag_global_function_with_filter() {
         Node::VariableBlock(
             WS::default(),
             Expr::with_filters(
-                ExprVal::FunctionCall(FunctionCall { name: "get_time".to_string(), args },),
+                ExprVal::FunctionCall(FunctionCall {
+                    name: "get_time".to_string(),
+                    positional_args: vec![],
+                    args
+                },),
                 vec![
-                    FunctionCall { name: "round".to_string(), args: HashMap::new() },
-                    FunctionCall { name: "upper".to_string(), args: HashMap::new() },
+                    FunctionCall {
+                        name: "round".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },
+                    FunctionCall {
+                        name: "upper".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },
                 ],
             )
         )
@@ -662,6 +784,7 @@ fn parse_set_tag_macro_call() {
                 value: Expr::new(ExprVal::MacroCall(MacroCall {
                     namespace: "macros".to_string(),
                     name: "something".to_string(),
+                    positional_args: vec![],
                     args: HashMap::new(),
                 },)),
                 global: false,
@@ -681,6 +804,7 @@ fn parse_set_tag_fn_call() {
                 key: "hello".to_string(),
                 value: Expr::new(ExprVal::FunctionCall(FunctionCall {
                     name: "utcnow".to_string(),
+                    positional_args: vec![],
                     args: HashMap::new(),
                 },)),
                 global: false,
@@ -689,6 +813,27 @@ fn parse_set_tag_fn_call() {
     );
 }
 
+#[test]
+fn parse_set_tag_method_call() {
+    let ast = parse("{% set hello = users.page(number=2) %}").unwrap();
+    let mut args = HashMap::new();
+    args.insert("number".to_string(), Expr::new(ExprVal::Int(2)));
+    assert_eq!(
+        ast[0],
+        Node::Set(
+            WS::default(),
+            Set {
+                key: "hello".to_string(),
+                value: Expr::new(ExprVal::MethodCall(MethodCall {
+                    object: "users".to_string(),
+                    call: FunctionCall { name: "page".to_string(), positional_args: vec![], args },
+                })),
+                global: false,
+            },
+        )
+    );
+}
+
 #[test]
 fn parse_set_array() {
     let ast = parse("{% set hello = [1, true, 'hello'] %}").unwrap();
@@ -724,7 +869,11 @@ fn parse_set_array_with_filter() {
                         Expr::new(ExprVal::Bool(true)),
                         Expr::new(ExprVal::String("hello".to_string())),
                     ]),
-                    vec![FunctionCall { name: "length".to_string(), args: HashMap::new() },],
+                    vec![FunctionCall {
+                        name: "length".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },],
                 ),
                 global: false,
             },
@@ -743,6 +892,7 @@ fn parse_set_global_tag() {
                 key: "hello".to_string(),
                 value: Expr::new(ExprVal::FunctionCall(FunctionCall {
                     name: "utcnow".to_string(),
+                    positional_args: vec![],
                     args: HashMap::new(),
                 },)),
                 global: true,
@@ -782,7 +932,11 @@ fn parse_filter_section_without_args() {
         Node::FilterSection(
             start_ws,
             FilterSection {
-                filter: FunctionCall { name: "upper".to_string(), args: HashMap::new() },
+                filter: FunctionCall {
+                    name: "upper".to_string(),
+                    positional_args: vec![],
+                    args: HashMap::new()
+                },
                 body: vec![Node::Text("A".to_string())],
             },
             end_ws,
@@ -804,7 +958,7 @@ fn parse_filter_section_with_args() {
         Node::FilterSection(
             start_ws,
             FilterSection {
-                filter: FunctionCall { name: "upper".to_string(), args },
+                filter: FunctionCall { name: "upper".to_string(), positional_args: vec![], args },
                 body: vec![Node::Text("A".to_string())],
             },
             end_ws,
@@ -821,7 +975,11 @@ fn parse_filter_section_preserves_ws() {
         Node::FilterSection(
             WS::default(),
             FilterSection {
-                filter: FunctionCall { name: "upper".to_string(), args: HashMap::new() },
+                filter: FunctionCall {
+                    name: "upper".to_string(),
+                    positional_args: vec![],
+                    args: HashMap::new()
+                },
                 body: vec![
                     Node::Text("  ".to_string()),
                     Node::VariableBlock(WS::default(), Expr::new(ExprVal::Ident("a".to_string()))),
@@ -845,6 +1003,8 @@ fn parse_block() {
             start_ws,
             Block {
                 name: "hello".to_string(),
+                scoped: false,
+                required: false,
                 body: vec![Node::Super, Node::Text(" hey".to_string())],
             },
             end_ws,
@@ -852,13 +1012,104 @@ fn parse_block() {
     );
 }
 
+#[test]
+fn parse_embed() {
+    let ast = parse("{% embed \"panel.html\" -%} {% block body %}Hi{% endblock %} {% endembed %}")
+        .unwrap();
+
+    assert_eq!(
+        ast[0],
+        Node::Embed(
+            WS { right: true, ..Default::default() },
+            Embed {
+                template: "panel.html".to_string(),
+                name: String::new(),
+                body: vec![
+                    Node::Text(" ".to_string()),
+                    Node::Block(
+                        WS::default(),
+                        Block {
+                            name: "body".to_string(),
+                            scoped: false,
+                            required: false,
+                            body: vec![Node::Text("Hi".to_string())],
+                        },
+                        WS::default(),
+                    ),
+                    Node::Text(" ".to_string()),
+                ],
+            },
+            WS::default(),
+        )
+    );
+}
+
+#[test]
+fn parse_macro_definition_with_varargs_and_kwargs() {
+    let ast =
+        parse("{% macro tag(name, size=width * 2, *children, **attrs) %}{% endmacro %}").unwrap();
+    let args = vec![
+        ("name".to_string(), None),
+        (
+            "size".to_string(),
+            Some(Expr::new(ExprVal::Math(MathExpr {
+                lhs: Box::new(Expr::new(ExprVal::Ident("width".to_string()))),
+                operator: MathOperator::Mul,
+                rhs: Box::new(Expr::new(ExprVal::Int(2))),
+            }))),
+        ),
+    ];
+
+    assert_eq!(
+        ast[0],
+        Node::MacroDefinition(
+            WS::default(),
+            MacroDefinition {
+                name: "tag".to_string(),
+                args,
+                varargs: Some("children".to_string()),
+                kwargs: Some("attrs".to_string()),
+                body: vec![],
+            },
+            WS::default(),
+        )
+    );
+}
+
+#[test]
+fn parse_macro_call_with_positional_args() {
+    let ast = parse("{{ forms::input(\"name\", [1, 2], type=\"text\") }}").unwrap();
+    let mut args = HashMap::new();
+    args.insert("type".to_string(), Expr::new(ExprVal::String("text".to_string())));
+
+    assert_eq!(
+        ast[0],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::MacroCall(MacroCall {
+                namespace: "forms".to_string(),
+                name: "input".to_string(),
+                positional_args: vec![
+                    Expr::new(ExprVal::String("name".to_string())),
+                    Expr::new(ExprVal::Array(vec![
+                        Expr::new(ExprVal::Int(1)),
+                        Expr::new(ExprVal::Int(2)),
+                    ])),
+                ],
+                args,
+            })),
+        )
+    );
+}
+
 #[test]
 fn parse_simple_macro_definition() {
     let ast = parse("{% macro hello(a=1, b='hello', c) %}A: {{a}}{% endmacro %}").unwrap();
-    let mut args = HashMap::new();
-    args.insert("a".to_string(), Some(Expr::new(ExprVal::Int(1))));
-    args.insert("b".to_string(), Some(Expr::new(ExprVal::String("hello".to_string()))));
-    args.insert("c".to_string(), None);
+    let args = vec![
+        ("a".to_string(), Some(Expr::new(ExprVal::Int(1)))),
+        ("b".to_string(), Some(Expr::new(ExprVal::String("hello".to_string())))),
+        ("c".to_string(), None),
+    ];
 
     assert_eq!(
         ast[0],
@@ -867,6 +1118,8 @@ fn parse_simple_macro_definition() {
             MacroDefinition {
                 name: "hello".to_string(),
                 args,
+                varargs: None,
+                kwargs: None,
                 body: vec![
                     Node::Text("A: ".to_string()),
                     Node::VariableBlock(WS::default(), Expr::new(ExprVal::Ident("a".to_string()))),
@@ -892,7 +1145,11 @@ fn parse_value_forloop() {
                 value: "item".to_string(),
                 container: Expr::with_filters(
                     ExprVal::Ident("items".to_string()),
-                    vec![FunctionCall { name: "reverse".to_string(), args: HashMap::new() },],
+                    vec![FunctionCall {
+                        name: "reverse".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },],
                 ),
                 body: vec![Node::Text("A".to_string())],
                 empty_body: None,
@@ -917,6 +1174,7 @@ fn parse_key_value_forloop() {
                 value: "item".to_string(),
                 container: Expr::new(ExprVal::FunctionCall(FunctionCall {
                     name: "get_map".to_string(),
+                    positional_args: vec![],
                     args: HashMap::new(),
                 },)),
                 body: vec![Node::Text("A".to_string())],
@@ -967,7 +1225,11 @@ fn parse_value_forloop_array_with_filter() {
                 value: "item".to_string(),
                 container: Expr::with_filters(
                     ExprVal::Array(vec![Expr::new(ExprVal::Int(1)), Expr::new(ExprVal::Int(2)),]),
-                    vec![FunctionCall { name: "reverse".to_string(), args: HashMap::new() },],
+                    vec![FunctionCall {
+                        name: "reverse".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new()
+                    },],
                 ),
                 body: vec![Node::Text("A".to_string())],
                 empty_body: None,
@@ -977,6 +1239,28 @@ fn parse_value_forloop_array_with_filter() {
     );
 }
 
+#[test]
+fn parse_whileloop() {
+    let ast = parse("{% while i < 3 -%}{% break %}{% endwhile %}").unwrap();
+    let start_ws = WS { right: true, ..Default::default() };
+
+    assert_eq!(
+        ast[0],
+        Node::Whileloop(
+            start_ws,
+            Whileloop {
+                condition: Expr::new(ExprVal::Logic(LogicExpr {
+                    lhs: Box::new(Expr::new(ExprVal::Ident("i".to_string()))),
+                    operator: LogicOperator::Lt,
+                    rhs: Box::new(Expr::new(ExprVal::Int(3))),
+                })),
+                body: vec![Node::Break(WS::default())],
+            },
+            WS::default(),
+        )
+    );
+}
+
 #[test]
 fn parse_value_forloop_empty() {
     let ast = parse("{% for item in [1,2,] %}A{% else %}B{%- endfor %}").unwrap();
@@ -1051,7 +1335,7 @@ fn parse_break() {
                 key: None,
                 value: "item".to_string(),
                 container: Expr::new(ExprVal::Ident("items".to_string())),
-                body: vec![Node::Break(WS { left: false, right: true }),],
+                body: vec![Node::Break(WS { left: false, right: true, ..Default::default() }),],
                 empty_body: None,
             },
             for_ws,
@@ -1071,7 +1355,7 @@ fn parse_continue() {
                 key: None,
                 value: "item".to_string(),
                 container: Expr::new(ExprVal::Ident("items".to_string())),
-                body: vec![Node::Continue(WS { left: false, right: true }),],
+                body: vec![Node::Continue(WS { left: false, right: true, ..Default::default() }),],
                 empty_body: None,
             },
             for_ws,
@@ -1096,8 +1380,8 @@ fn parse_string_concat() {
             WS::default(),
             Expr::new(ExprVal::StringConcat(StringConcat {
                 values: vec![
-                    ExprVal::String("hello".to_string()),
-                    ExprVal::Ident("ident".to_string()),
+                    Expr::new(ExprVal::String("hello".to_string())),
+                    Expr::new(ExprVal::Ident("ident".to_string())),
                 ]
             }))
         ),
@@ -1113,11 +1397,191 @@ fn parse_string_concat_multiple() {
             WS::default(),
             Expr::new(ExprVal::StringConcat(StringConcat {
                 values: vec![
-                    ExprVal::String("hello".to_string()),
-                    ExprVal::Ident("ident".to_string()),
-                    ExprVal::String("ho".to_string()),
+                    Expr::new(ExprVal::String("hello".to_string())),
+                    Expr::new(ExprVal::Ident("ident".to_string())),
+                    Expr::new(ExprVal::String("ho".to_string())),
                 ]
             }))
         ),
     );
 }
+
+#[test]
+fn parse_string_concat_with_expressions() {
+    let ast = parse("{{ 'total: ' ~ (a + 1) ~ get_unit() }}").unwrap();
+    assert_eq!(
+        ast[0],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::StringConcat(StringConcat {
+                values: vec![
+                    Expr::new(ExprVal::String("total: ".to_string())),
+                    Expr::new(ExprVal::Math(MathExpr {
+                        lhs: Box::new(Expr::new(ExprVal::Ident("a".to_string()))),
+                        operator: MathOperator::Add,
+                        rhs: Box::new(Expr::new(ExprVal::Int(1))),
+                    })),
+                    Expr::new(ExprVal::FunctionCall(FunctionCall {
+                        name: "get_unit".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new(),
+                    })),
+                ]
+            }))
+        ),
+    );
+}
+
+#[test]
+fn parse_access_on_expression() {
+    let ast = parse("{{ (items | first).name }}{{ get_user()['emails'][i + 1] }}").unwrap();
+    assert_eq!(
+        ast[0],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::Access(Access {
+                target: Box::new(Expr::with_filters(
+                    ExprVal::Ident("items".to_string()),
+                    vec![FunctionCall {
+                        name: "first".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new(),
+                    }],
+                )),
+                keys: vec![(AccessKey::Attr("name".to_string()), false)],
+            }))
+        ),
+    );
+    assert_eq!(
+        ast[1],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::Access(Access {
+                target: Box::new(Expr::new(ExprVal::FunctionCall(FunctionCall {
+                    name: "get_user".to_string(),
+                    positional_args: vec![],
+                    args: HashMap::new(),
+                }))),
+                keys: vec![
+                    (AccessKey::Index(Expr::new(ExprVal::String("emails".to_string()))), false),
+                    (
+                        AccessKey::Index(Expr::new(ExprVal::Math(MathExpr {
+                            lhs: Box::new(Expr::new(ExprVal::Ident("i".to_string()))),
+                            operator: MathOperator::Add,
+                            rhs: Box::new(Expr::new(ExprVal::Int(1))),
+                        }))),
+                        false,
+                    ),
+                ],
+            }))
+        ),
+    );
+}
+
+#[test]
+fn parse_test_and_negation_on_expression() {
+    let ast = parse("{{ (items | first) is not odd }}{{ -(1 + 2) }}{{ items[-1] }}").unwrap();
+    assert_eq!(
+        ast[0],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::Test(Test {
+                expr: Box::new(Expr::with_filters(
+                    ExprVal::Ident("items".to_string()),
+                    vec![FunctionCall {
+                        name: "first".to_string(),
+                        positional_args: vec![],
+                        args: HashMap::new(),
+                    }],
+                )),
+                negated: true,
+                name: "odd".to_string(),
+                args: vec![],
+            }))
+        ),
+    );
+    assert_eq!(
+        ast[1],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::Math(MathExpr {
+                lhs: Box::new(Expr::new(ExprVal::Int(0))),
+                operator: MathOperator::Sub,
+                rhs: Box::new(Expr::new(ExprVal::Math(MathExpr {
+                    lhs: Box::new(Expr::new(ExprVal::Int(1))),
+                    operator: MathOperator::Add,
+                    rhs: Box::new(Expr::new(ExprVal::Int(2))),
+                }))),
+            }))
+        ),
+    );
+    assert_eq!(
+        ast[2],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::Access(Access {
+                target: Box::new(Expr::new(ExprVal::Ident("items".to_string()))),
+                keys: vec![(AccessKey::Index(Expr::new(ExprVal::Int(-1))), false)],
+            }))
+        ),
+    );
+}
+
+#[test]
+fn parse_null_coalescing_and_safe_navigation() {
+    let ast = parse("{{ user?.address?.[0].city ?? default ?? 'unknown' }}").unwrap();
+    assert_eq!(
+        ast[0],
+        Node::VariableBlock(
+            WS::default(),
+            Expr::new(ExprVal::Coalesce(Coalesce {
+                lhs: Box::new(Expr::new(ExprVal::Access(Access {
+                    target: Box::new(Expr::new(ExprVal::Ident("user".to_string()))),
+                    keys: vec![
+                        (AccessKey::Attr("address".to_string()), true),
+                        (AccessKey::Index(Expr::new(ExprVal::Int(0))), true),
+                        (AccessKey::Attr("city".to_string()), false),
+                    ],
+                }))),
+                rhs: Box::new(Expr::new(ExprVal::Coalesce(Coalesce {
+                    lhs: Box::new(Expr::new(ExprVal::Ident("default".to_string()))),
+                    rhs: Box::new(Expr::new(ExprVal::String("unknown".to_string()))),
+                }))),
+            }))
+        ),
+    );
+}
+
+#[test]
+fn parse_macro_ref() {
+    let ast = parse("{% set cell = macros::bold %}").unwrap();
+
+    assert_eq!(
+        ast[0],
+        Node::Set(
+            WS::default(),
+            Set {
+                key: "cell".to_string(),
+                value: Expr::new(ExprVal::MacroRef(MacroRef {
+                    namespace: "macros".to_string(),
+                    name: "bold".to_string(),
+                })),
+                global: false,
+            },
+        )
+    );
+}
+
+#[test]
+fn parse_block_with_modifiers() {
+    let ast = parse("{% block hello scoped required %}{% endblock hello %}").unwrap();
+
+    assert_eq!(
+        ast[0],
+        Node::Block(
+            WS::default(),
+            Block { name: "hello".to_string(), scoped: true, required: true, body: vec![] },
+            WS::default(),
+        )
+    );
+}
@@ -1,23 +1,22 @@
 use crate::parser::ast::*;
-use crate::parser::remove_whitespace;
-use std::collections::HashMap;
+use crate::parser::{remove_whitespace, WhitespaceOptions};
 
 #[test]
 fn do_nothing_if_unneeded() {
     let ast = vec![Node::Text("hey ".to_string())];
-    assert_eq!(remove_whitespace(ast.clone(), None), ast);
+    assert_eq!(remove_whitespace(ast.clone(), &WhitespaceOptions::default()), ast);
 }
 
 #[test]
 fn remove_previous_ws_if_single_opening_tag_requires_it() {
-    let ws = WS { left: true, right: false };
+    let ws = WS { left: true, right: false, ..Default::default() };
     let ast = vec![
         Node::Text("hey ".to_string()),
         Node::ImportMacro(ws, "hey ".to_string(), "ho".to_string()),
     ];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![
             Node::Text("hey".to_string()), // it removed the trailing space
             Node::ImportMacro(ws, "hey ".to_string(), "ho".to_string()),
@@ -27,14 +26,14 @@ fn remove_previous_ws_if_single_opening_tag_requires_it() {
 
 #[test]
 fn remove_next_ws_if_single_opening_tag_requires_it() {
-    let ws = WS { left: true, right: true };
+    let ws = WS { left: true, right: true, ..Default::default() };
     let ast = vec![
         Node::ImportMacro(ws, "hey ".to_string(), "ho".to_string()),
         Node::Text("  hey".to_string()),
     ];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![
             Node::ImportMacro(ws, "hey ".to_string(), "ho".to_string()),
             Node::Text("hey".to_string()), // it removed the leading space
@@ -44,13 +43,13 @@ fn remove_next_ws_if_single_opening_tag_requires_it() {
 
 #[test]
 fn handle_ws_both_sides_for_raw_tag() {
-    let start_ws = WS { left: true, right: false };
-    let end_ws = WS { left: true, right: true };
+    let start_ws = WS { left: true, right: false, ..Default::default() };
+    let end_ws = WS { left: true, right: true, ..Default::default() };
     let ast =
         vec![Node::Raw(start_ws, "  hey ".to_string(), end_ws), Node::Text("  hey".to_string())];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![
             // it removed only the space at the end
             Node::Raw(start_ws, "  hey".to_string(), end_ws),
@@ -61,13 +60,15 @@ fn handle_ws_both_sides_for_raw_tag() {
 
 #[test]
 fn handle_ws_both_sides_for_macro_definitions() {
-    let start_ws = WS { left: true, right: true };
-    let end_ws = WS { left: true, right: true };
+    let start_ws = WS { left: true, right: true, ..Default::default() };
+    let end_ws = WS { left: true, right: true, ..Default::default() };
     let ast = vec![Node::MacroDefinition(
         start_ws,
         MacroDefinition {
             name: "something".to_string(),
-            args: HashMap::new(),
+            args: vec![],
+            varargs: None,
+            kwargs: None,
             body: vec![
                 Node::Text("\n  ".to_string()),
                 Node::Text("hey".to_string()),
@@ -78,12 +79,14 @@ fn handle_ws_both_sides_for_macro_definitions() {
     )];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![Node::MacroDefinition(
             start_ws,
             MacroDefinition {
                 name: "something".to_string(),
-                args: HashMap::new(),
+                args: vec![],
+                varargs: None,
+                kwargs: None,
                 body: vec![Node::Text("hey".to_string())],
             },
             end_ws,
@@ -93,8 +96,8 @@ fn handle_ws_both_sides_for_macro_definitions() {
 
 #[test]
 fn handle_ws_both_sides_for_forloop_tag_and_remove_empty_node() {
-    let start_ws = WS { left: true, right: true };
-    let end_ws = WS { left: true, right: true };
+    let start_ws = WS { left: true, right: true, ..Default::default() };
+    let end_ws = WS { left: true, right: true, ..Default::default() };
     let ast = vec![
         Node::Forloop(
             start_ws,
@@ -112,7 +115,7 @@ fn handle_ws_both_sides_for_forloop_tag_and_remove_empty_node() {
     ];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![
             Node::Forloop(
                 start_ws,
@@ -133,24 +136,24 @@ fn handle_ws_both_sides_for_forloop_tag_and_remove_empty_node() {
 
 #[test]
 fn handle_ws_for_if_nodes() {
-    let end_ws = WS { left: false, right: true };
+    let end_ws = WS { left: false, right: true, ..Default::default() };
     let ast = vec![
         Node::Text("C ".to_string()),
         Node::If(
             If {
                 conditions: vec![
                     (
-                        WS { left: true, right: true },
+                        WS { left: true, right: true, ..Default::default() },
                         Expr::new(ExprVal::Int(1)),
                         vec![Node::Text(" a ".to_string())],
                     ),
                     (
-                        WS { left: true, right: false },
+                        WS { left: true, right: false, ..Default::default() },
                         Expr::new(ExprVal::Int(1)),
                         vec![Node::Text(" a ".to_string())],
                     ),
                     (
-                        WS { left: true, right: true },
+                        WS { left: true, right: true, ..Default::default() },
                         Expr::new(ExprVal::Int(1)),
                         vec![Node::Text(" a ".to_string())],
                     ),
@@ -163,24 +166,24 @@ fn handle_ws_for_if_nodes() {
     ];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![
             Node::Text("C".to_string()),
             Node::If(
                 If {
                     conditions: vec![
                         (
-                            WS { left: true, right: true },
+                            WS { left: true, right: true, ..Default::default() },
                             Expr::new(ExprVal::Int(1)),
                             vec![Node::Text("a".to_string())],
                         ),
                         (
-                            WS { left: true, right: false },
+                            WS { left: true, right: false, ..Default::default() },
                             Expr::new(ExprVal::Int(1)),
                             vec![Node::Text(" a".to_string())],
                         ),
                         (
-                            WS { left: true, right: true },
+                            WS { left: true, right: true, ..Default::default() },
                             Expr::new(ExprVal::Int(1)),
                             vec![Node::Text("a ".to_string())],
                         ),
@@ -196,30 +199,30 @@ fn handle_ws_for_if_nodes() {
 
 #[test]
 fn handle_ws_for_if_nodes_with_else() {
-    let end_ws = WS { left: true, right: true };
+    let end_ws = WS { left: true, right: true, ..Default::default() };
     let ast = vec![
         Node::Text("C ".to_string()),
         Node::If(
             If {
                 conditions: vec![
                     (
-                        WS { left: true, right: true },
+                        WS { left: true, right: true, ..Default::default() },
                         Expr::new(ExprVal::Int(1)),
                         vec![Node::Text(" a ".to_string())],
                     ),
                     (
-                        WS { left: true, right: false },
+                        WS { left: true, right: false, ..Default::default() },
                         Expr::new(ExprVal::Int(1)),
                         vec![Node::Text(" a ".to_string())],
                     ),
                     (
-                        WS { left: true, right: true },
+                        WS { left: true, right: true, ..Default::default() },
                         Expr::new(ExprVal::Int(1)),
                         vec![Node::Text(" a ".to_string())],
                     ),
                 ],
                 otherwise: Some((
-                    WS { left: true, right: true },
+                    WS { left: true, right: true, ..Default::default() },
                     vec![Node::Text(" a ".to_string())],
                 )),
             },
@@ -229,30 +232,30 @@ fn handle_ws_for_if_nodes_with_else() {
     ];
 
     assert_eq!(
-        remove_whitespace(ast, None),
+        remove_whitespace(ast, &WhitespaceOptions::default()),
         vec![
             Node::Text("C".to_string()),
             Node::If(
                 If {
                     conditions: vec![
                         (
-                            WS { left: true, right: true },
+                            WS { left: true, right: true, ..Default::default() },
                             Expr::new(ExprVal::Int(1)),
                             vec![Node::Text("a".to_string())],
                         ),
                         (
-                            WS { left: true, right: false },
+                            WS { left: true, right: false, ..Default::default() },
                             Expr::new(ExprVal::Int(1)),
                             vec![Node::Text(" a".to_string())],
                         ),
                         (
-                            WS { left: true, right: true },
+                            WS { left: true, right: true, ..Default::default() },
                             Expr::new(ExprVal::Int(1)),
                             vec![Node::Text("a".to_string())],
                         ),
                     ],
                     otherwise: Some((
-                        WS { left: true, right: true },
+                        WS { left: true, right: true, ..Default::default() },
                         vec![Node::Text("a".to_string())],
                     )),
                 },
@@ -1,113 +1,223 @@
 use crate::parser::ast::*;
 
-macro_rules! trim_right_previous {
-    ($vec: expr) => {
-        if let Some(last) = $vec.pop() {
-            if let Node::Text(mut s) = last {
-                s = s.trim_end().to_string();
-                if !s.is_empty() {
-                    $vec.push(Node::Text(s));
-                }
+// The whitespace rules applied to all the templates of a Lysine instance, on top of
+// the `{%-` and `-%}` defined in the template.
+// A tag can opt out of them with `{%+` and `+%}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WhitespaceOptions {
+    // Remove the first newline after a `{% %}` tag or a comment
+    pub trim_blocks: bool,
+    // Remove the spaces and tabs between the start of a line and a `{% %}` tag or a comment
+    pub lstrip_blocks: bool,
+    // Keep the newline at the very end of a template
+    pub keep_trailing_newline: bool,
+}
+
+impl Default for WhitespaceOptions {
+    fn default() -> WhitespaceOptions {
+        WhitespaceOptions { trim_blocks: false, lstrip_blocks: false, keep_trailing_newline: true }
+    }
+}
+
+// How to trim the text node next to a tag
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Trim {
+    // Leave it alone
+    Nothing,
+    // Remove all the whitespace, from `{%-` and `-%}`
+    All,
+    // Only remove the whitespace on the line of the tag: the indentation before it
+    // for `lstrip_blocks` or the newline after it for `trim_blocks`
+    Line,
+}
+
+// Which trimming to do on the text before a tag
+fn trim_before(ws: WS, is_block_tag: bool, options: &WhitespaceOptions) -> Trim {
+    if ws.left {
+        Trim::All
+    } else if is_block_tag && options.lstrip_blocks && !ws.keep_left {
+        Trim::Line
+    } else {
+        Trim::Nothing
+    }
+}
+
+// Which trimming to do on the text after a tag
+fn trim_after(ws: WS, is_block_tag: bool, options: &WhitespaceOptions) -> Trim {
+    if ws.right {
+        Trim::All
+    } else if is_block_tag && options.trim_blocks && !ws.keep_right {
+        Trim::Line
+    } else {
+        Trim::Nothing
+    }
+}
+
+fn trim_text_start(s: &str, trim: Trim) -> &str {
+    match trim {
+        Trim::Nothing => s,
+        Trim::All => s.trim_start(),
+        Trim::Line => s.strip_prefix("\r\n").or_else(|| s.strip_prefix('\n')).unwrap_or(s),
+    }
+}
+
+// `line_start` is whether the text starts at the beginning of a line, in which case
+// `lstrip_blocks` can remove it entirely if it's only indentation
+fn trim_text_end(s: &str, trim: Trim, line_start: bool) -> &str {
+    match trim {
+        Trim::Nothing => s,
+        Trim::All => s.trim_end(),
+        Trim::Line => {
+            let stripped = s.trim_end_matches([' ', '\t']);
+            if stripped.ends_with('\n') || (stripped.is_empty() && line_start) {
+                stripped
             } else {
-                $vec.push(last);
+                s
             }
         }
-    };
-    ($cond: expr, $vec: expr) => {
-        if $cond {
-            trim_right_previous!($vec);
+    }
+}
+
+// Trims the end of the last node if it is a text node. Empty text nodes are discarded
+fn trim_previous(nodes: &mut Vec<Node>, trim: Trim, line_start: bool) {
+    if trim == Trim::Nothing {
+        return;
+    }
+
+    if let Some(last) = nodes.pop() {
+        if let Node::Text(s) = last {
+            let s = trim_text_end(&s, trim, line_start);
+            if !s.is_empty() {
+                nodes.push(Node::Text(s.to_string()));
+            }
+        } else {
+            nodes.push(last);
         }
-    };
+    }
 }
 
-// Removes whitespace from the AST nodes according to the `{%-` and `-%}` defined in the template.
+// Removes whitespace from the AST nodes according to the `{%-` and `-%}` defined in the template
+// and to the `trim_blocks`/`lstrip_blocks` options.
 // Empty string nodes will be discarded.
-///
-// The `ws` param is used when recursing through nested bodies to know whether to know
-// how to handle the whitespace for that whole body:
-// - set the initial `trim_left_next` to `ws.left`
-// - trim last node if it is a text node if `ws.right == true`
-pub fn remove_whitespace(nodes: Vec<Node>, body_ws: Option<WS>) -> Vec<Node> {
+pub fn remove_whitespace(nodes: Vec<Node>, options: &WhitespaceOptions) -> Vec<Node> {
+    trim_body(nodes, Trim::Nothing, Trim::Nothing, true, options)
+}
+
+// `start` and `end` are how to trim the first and last text nodes of that body, according to
+// the tags around it
+fn trim_body(
+    nodes: Vec<Node>,
+    start: Trim,
+    end: Trim,
+    line_start: bool,
+    options: &WhitespaceOptions,
+) -> Vec<Node> {
     let mut res = Vec::with_capacity(nodes.len());
 
     // Whether the node we just added to res is a Text node
     let mut previous_was_text = false;
-    // Whether the previous block ended wth `-%}` and we need to trim left the next text node
-    let mut trim_left_next = body_ws.map_or(false, |ws| ws.left);
+    // Whether that text node starts at the beginning of a line
+    let mut text_line_start = false;
+    // How the previous tag wants the next text node to be trimmed
+    let mut trim_next = start;
 
     for n in nodes {
         match n {
             Node::Text(s) => {
                 previous_was_text = true;
+                text_line_start = (line_start && res.is_empty())
+                    || (trim_next == Trim::Line && (s.starts_with('\n') || s.starts_with("\r\n")));
 
-                if !trim_left_next {
+                if trim_next == Trim::Nothing {
                     res.push(Node::Text(s));
                     continue;
                 }
-                trim_left_next = false;
 
-                let new_val = s.trim_start();
+                let new_val = trim_text_start(&s, trim_next);
+                trim_next = Trim::Nothing;
                 if !new_val.is_empty() {
                     res.push(Node::Text(new_val.to_string()));
                 }
                 // empty text nodes will be skipped
                 continue;
             }
-            Node::VariableBlock(ws, _)
-            | Node::ImportMacro(ws, _, _)
+            Node::VariableBlock(ws, _) => {
+                if previous_was_text {
+                    trim_previous(&mut res, trim_before(ws, false, options), text_line_start);
+                }
+                trim_next = trim_after(ws, false, options);
+            }
+            Node::ImportMacro(ws, _, _)
+            | Node::FromImport(ws, _, _)
             | Node::Extends(ws, _)
+            | Node::Requires(ws, _)
             | Node::Include(ws, _, _)
             | Node::Set(ws, _)
             | Node::Break(ws)
             | Node::Comment(ws, _)
             | Node::Continue(ws) => {
-                trim_right_previous!(previous_was_text && ws.left, res);
-                trim_left_next = ws.right;
+                if previous_was_text {
+                    trim_previous(&mut res, trim_before(ws, true, options), text_line_start);
+                }
+                trim_next = trim_after(ws, true, options);
             }
             Node::Raw(start_ws, ref s, end_ws) => {
-                trim_right_previous!(previous_was_text && start_ws.left, res);
-                previous_was_text = false;
-                trim_left_next = end_ws.right;
-
-                if start_ws.right || end_ws.left {
-                    let val = if start_ws.right && end_ws.left {
-                        s.trim()
-                    } else if start_ws.right {
-                        s.trim_start()
-                    } else {
-                        s.trim_end()
-                    };
-
-                    res.push(Node::Raw(start_ws, val.to_string(), end_ws));
-                    continue;
+                if previous_was_text {
+                    trim_previous(&mut res, trim_before(start_ws, true, options), text_line_start);
                 }
+                previous_was_text = false;
+                trim_next = trim_after(end_ws, true, options);
+
+                let start_trim = trim_after(start_ws, true, options);
+                let raw_line_start = start_trim == Trim::Line && s.starts_with('\n');
+                let val = trim_text_start(s, start_trim);
+                let val = trim_text_end(val, trim_before(end_ws, true, options), raw_line_start);
+                res.push(Node::Raw(start_ws, val.to_string(), end_ws));
+                continue;
             }
             // Those nodes have a body surrounded by 2 tags
             Node::Forloop(start_ws, _, end_ws)
+            | Node::Whileloop(start_ws, _, end_ws)
             | Node::MacroDefinition(start_ws, _, end_ws)
             | Node::FilterSection(start_ws, _, end_ws)
+            | Node::Embed(start_ws, _, end_ws)
             | Node::Block(start_ws, _, end_ws) => {
-                trim_right_previous!(previous_was_text && start_ws.left, res);
+                if previous_was_text {
+                    trim_previous(&mut res, trim_before(start_ws, true, options), text_line_start);
+                }
                 previous_was_text = false;
-                trim_left_next = end_ws.right;
+                trim_next = trim_after(end_ws, true, options);
 
                 // let's remove ws from the bodies now and append the cleaned up node
-                let body_ws = WS { left: start_ws.right, right: end_ws.left };
+                let body_start = trim_after(start_ws, true, options);
+                let body_end = trim_before(end_ws, true, options);
                 match n {
                     Node::Forloop(_, mut forloop, _) => {
-                        forloop.body = remove_whitespace(forloop.body, Some(body_ws));
+                        forloop.body =
+                            trim_body(forloop.body, body_start, body_end, false, options);
                         res.push(Node::Forloop(start_ws, forloop, end_ws));
                     }
+                    Node::Whileloop(_, mut whileloop, _) => {
+                        whileloop.body =
+                            trim_body(whileloop.body, body_start, body_end, false, options);
+                        res.push(Node::Whileloop(start_ws, whileloop, end_ws));
+                    }
                     Node::MacroDefinition(_, mut macro_def, _) => {
-                        macro_def.body = remove_whitespace(macro_def.body, Some(body_ws));
+                        macro_def.body =
+                            trim_body(macro_def.body, body_start, body_end, false, options);
                         res.push(Node::MacroDefinition(start_ws, macro_def, end_ws));
                     }
                     Node::FilterSection(_, mut filter_section, _) => {
-                        filter_section.body = remove_whitespace(filter_section.body, Some(body_ws));
+                        filter_section.body =
+                            trim_body(filter_section.body, body_start, body_end, false, options);
                         res.push(Node::FilterSection(start_ws, filter_section, end_ws));
                     }
+                    Node::Embed(_, mut embed, _) => {
+                        embed.body = trim_body(embed.body, body_start, body_end, false, options);
+                        res.push(Node::Embed(start_ws, embed, end_ws));
+                    }
                     Node::Block(_, mut block, _) => {
-                        block.body = remove_whitespace(block.body, Some(body_ws));
+                        block.body = trim_body(block.body, body_start, body_end, false, options);
                         res.push(Node::Block(start_ws, block, end_ws));
                     }
                     _ => unreachable!(),
@@ -116,58 +226,44 @@ pub fn remove_whitespace(nodes: Vec<Node>, body_ws: Option<WS>) -> Vec<Node> {
             }
             // The ugly one
             Node::If(If { conditions, otherwise }, end_ws) => {
-                trim_left_next = end_ws.right;
-                let mut new_conditions: Vec<(_, _, Vec<_>)> = Vec::with_capacity(conditions.len());
-
-                for mut condition in conditions {
-                    if condition.0.left {
-                        // We need to trim the text node before the if tag
-                        if new_conditions.is_empty() && previous_was_text {
-                            trim_right_previous!(res);
-                        } else if let Some(&mut (_, _, ref mut body)) = new_conditions.last_mut() {
-                            trim_right_previous!(body);
-                        }
+                if previous_was_text {
+                    if let Some(&(ws, _, _)) = conditions.first() {
+                        trim_previous(&mut res, trim_before(ws, true, options), text_line_start);
                     }
-
-                    // we can't peek at the next one to know whether we need to trim right since
-                    // are consuming conditions. We'll find out at the next ilysinetion.
-                    condition.2 = remove_whitespace(
-                        condition.2,
-                        Some(WS { left: condition.0.right, right: false }),
-                    );
-                    new_conditions.push(condition);
                 }
-
                 previous_was_text = false;
+                trim_next = trim_after(end_ws, true, options);
 
-                // We now need to look for the last potential `{%-` bit for if/elif
+                // Each body ends with the next elif/else/endif tag
+                let mut next_tags: Vec<WS> = conditions.iter().skip(1).map(|c| c.0).collect();
+                next_tags.push(otherwise.as_ref().map(|o| o.0).unwrap_or(end_ws));
 
-                // That can be a `{%- else`
-                if let Some((else_ws, body)) = otherwise {
-                    if else_ws.left {
-                        if let Some(&mut (_, _, ref mut body)) = new_conditions.last_mut() {
-                            trim_right_previous!(body);
-                        }
-                    }
-                    let mut else_body =
-                        remove_whitespace(body, Some(WS { left: else_ws.right, right: false }));
-                    // if we have an `else`, the `endif` will affect the else node so we need to check
-                    if end_ws.left {
-                        trim_right_previous!(else_body);
-                    }
-                    res.push(Node::If(
-                        If { conditions: new_conditions, otherwise: Some((else_ws, else_body)) },
-                        end_ws,
-                    ));
-                    continue;
-                }
+                let new_conditions = conditions
+                    .into_iter()
+                    .zip(next_tags)
+                    .map(|((ws, expr, body), next_ws)| {
+                        let body = trim_body(
+                            body,
+                            trim_after(ws, true, options),
+                            trim_before(next_ws, true, options),
+                            false,
+                            options,
+                        );
+                        (ws, expr, body)
+                    })
+                    .collect();
 
-                // Or `{%- endif`
-                if end_ws.left {
-                    if let Some(&mut (_, _, ref mut body)) = new_conditions.last_mut() {
-                        trim_right_previous!(true, body);
-                    }
-                }
+                // if we have an `else`, the `endif` will affect the else node
+                let otherwise = otherwise.map(|(else_ws, body)| {
+                    let body = trim_body(
+                        body,
+                        trim_after(else_ws, true, options),
+                        trim_before(end_ws, true, options),
+                        false,
+                        options,
+                    );
+                    (else_ws, body)
+                });
 
                 res.push(Node::If(If { conditions: new_conditions, otherwise }, end_ws));
                 continue;
@@ -180,8 +276,8 @@ pub fn remove_whitespace(nodes: Vec<Node>, body_ws: Option<WS>) -> Vec<Node> {
         res.push(n);
     }
 
-    if let Some(whitespace) = body_ws {
-        trim_right_previous!(whitespace.right, res);
+    if previous_was_text {
+        trim_previous(&mut res, end, text_line_start);
     }
 
     res
@@ -0,0 +1,94 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::poll_fn;
+use std::sync::Arc;
+use std::task::Poll;
+
+use serde_json::{to_string, Value};
+
+use crate::builtins::functions::AsyncResult;
+use crate::errors::{Error, Result};
+use crate::renderer::limits::Spent;
+
+// The results of the async functions and filters called during an async render.
+//
+// The processor is synchronous: when it meets a call that hasn't been awaited yet, it keeps its
+// future here and goes on with the render to find the other calls, skipping what needs the
+// result. Once all the futures are done, the render starts again from the beginning and gets the
+// results of the calls from the cache.
+#[derive(Default)]
+pub struct AsyncCalls {
+    // Call key -> what the future returned, errors are kept as well so every use gets them
+    results: HashMap<String, std::result::Result<Value, Arc<Error>>>,
+    // The calls met during the pass that weren't awaited yet and their futures
+    pending: Vec<(String, AsyncResult)>,
+    // What the previous passes used of the render limits, the fuel and the timeout are for the
+    // whole render
+    pub spent: Option<Spent>,
+    // The key of the macro references, which has to stay the same for the calls given one to
+    // be found again
+    pub macro_ref_key: Option<String>,
+}
+
+impl AsyncCalls {
+    // Identifies a call by its name and arguments, and the value for filters
+    pub fn key(name: &str, value: Option<&Value>, args: &HashMap<String, Value>) -> String {
+        let args: BTreeMap<_, _> = args.iter().collect();
+        let args = to_string(&args).unwrap();
+        match value {
+            Some(value) => format!("{}|{}{}", value, name, args),
+            None => format!("{}{}", name, args),
+        }
+    }
+
+    // Returns the result of a call if it was already awaited, otherwise starts it and returns an
+    // error to stop the expression using it
+    pub fn call(&mut self, key: String, start: impl FnOnce() -> AsyncResult) -> Result<Value> {
+        match self.results.get(&key) {
+            Some(Ok(val)) => Ok(val.clone()),
+            Some(Err(e)) => Err(Error::copy_shared(e)),
+            None => {
+                if !self.pending.iter().any(|(pending_key, _)| *pending_key == key) {
+                    self.pending.push((key, start()));
+                }
+                Err(Error::async_call_pending())
+            }
+        }
+    }
+
+    // Whether the pass met calls that weren't awaited yet, its output is then thrown away
+    pub fn is_waiting(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Awaits all the calls met during the pass together, returns `false` if there wasn't any
+    pub async fn await_pending(&mut self) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+
+        let mut pending: Vec<_> =
+            self.pending.drain(..).map(|(key, future)| (key, Some(future))).collect();
+        let results = &mut self.results;
+        poll_fn(|cx| {
+            let mut done = true;
+            for (key, slot) in &mut pending {
+                if let Some(future) = slot {
+                    match future.as_mut().poll(cx) {
+                        Poll::Ready(res) => {
+                            results.insert(key.clone(), res.map_err(Arc::new));
+                            *slot = None;
+                        }
+                        Poll::Pending => done = false,
+                    }
+                }
+            }
+            if done {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        true
+    }
+}
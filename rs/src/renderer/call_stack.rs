@@ -1,10 +1,11 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 
-use serde_json::{to_value, Value};
+use serde_json::{to_value, Map, Value};
 
-use crate::context::dotted_pointer;
+use crate::context::{dotted_pointer, object_pointer, LayeredContext};
 use crate::errors::{Error, Result};
+use crate::object::Object;
 use crate::renderer::for_loop::{ForLoop, ForLoopState};
 use crate::renderer::stack_frame::{FrameContext, FrameType, StackFrame, Val};
 use crate::template::Template;
@@ -14,23 +15,37 @@ use crate::Context;
 #[derive(Debug)]
 pub struct UserContext<'a> {
     // Read-only context
-    inner: &'a Context,
+    inner: &'a LayeredContext<'a>,
+    // The globals of the `Lysine` instance, looked up after the context
+    globals: &'a Context,
 }
 
 impl<'a> UserContext<'a> {
     // Create an immutable user context to be used in the call stack
-    pub fn new(context: &'a Context) -> Self {
-        UserContext { inner: context }
+    pub fn new(context: &'a LayeredContext<'a>, globals: &'a Context) -> Self {
+        UserContext { inner: context, globals }
     }
 
     pub fn find_value(&self, key: &str) -> Option<&'a Value> {
-        self.inner.get(key)
+        self.inner.get(key).or_else(|| self.globals.get(key))
     }
 
-    pub fn find_value_by_dotted_pointer(&self, pointer: &str) -> Option<&'a Value> {
-        let root = pointer.split('.').next().unwrap().replace("~1", "/").replace("~0", "~");
-        let rest = &pointer[root.len() + 1..];
-        self.inner.get(&root).and_then(|val| dotted_pointer(val, rest))
+    pub fn find_object(&self, key: &str) -> Option<&'a dyn Object> {
+        self.inner.get_object(key)
+    }
+
+    // Finds a value from a plain or dotted key, the objects only resolve the part of the key
+    // they are asked for
+    pub fn lookup(&self, key: &str) -> Option<Val<'a>> {
+        let (root, rest) = match key.split_once('.') {
+            Some((root, rest)) => (root.replace("~1", "/").replace("~0", "~"), rest),
+            None => (key.to_string(), ""),
+        };
+
+        if let Some(object) = self.find_object(&root) {
+            return object_pointer(object, rest).map(Cow::Owned);
+        }
+        self.find_value(&root).and_then(|val| dotted_pointer(val, rest)).map(Cow::Borrowed)
     }
 }
 
@@ -45,10 +60,14 @@ pub struct CallStack<'a> {
 
 impl<'a> CallStack<'a> {
     // Create the initial call stack
-    pub fn new(context: &'a Context, template: &'a Template) -> CallStack<'a> {
+    pub fn new(
+        context: &'a LayeredContext<'a>,
+        globals: &'a Context,
+        template: &'a Template,
+    ) -> CallStack<'a> {
         CallStack {
             stack: vec![StackFrame::new(FrameType::Origin, "ORIGIN", template)],
-            context: UserContext::new(context),
+            context: UserContext::new(context, globals),
         }
     }
 
@@ -57,6 +76,11 @@ impl<'a> CallStack<'a> {
         self.stack.push(StackFrame::new_for_loop(name, tpl, for_loop));
     }
 
+    pub fn push_while_loop_frame(&mut self, name: &'a str) {
+        let tpl = self.stack.last().expect("Stack frame").active_template;
+        self.stack.push(StackFrame::new_while_loop(name, tpl));
+    }
+
     pub fn push_macro_frame(
         &mut self,
         namespace: &'a str,
@@ -98,6 +122,15 @@ impl<'a> CallStack<'a> {
         self.stack.last().expect("No current frame exists")
     }
 
+    // Returns the closest frame that isn't a for loop: the origin, a macro or an include
+    pub fn scope_frame(&self) -> &StackFrame<'a> {
+        self.stack
+            .iter()
+            .rev()
+            .find(|frame| frame.kind != FrameType::ForLoop)
+            .expect("Origin frame exists")
+    }
+
     // Pop the last frame
     pub fn pop(&mut self) {
         self.stack.pop().expect("Mistakenly popped Origin frame");
@@ -117,14 +150,22 @@ impl<'a> CallStack<'a> {
             }
         }
 
-        // Not in stack frame, look in user supplied context
-        if key.contains('.') {
-            return self.context.find_value_by_dotted_pointer(key).map(Cow::Borrowed);
-        } else if let Some(value) = self.context.find_value(key) {
-            return Some(Cow::Borrowed(value));
+        // Not in stack frame, look in user supplied context and then in the globals
+        self.context.lookup(key)
+    }
+
+    // Finds an object of the user context, unless a variable of the frames has the same name
+    pub fn lookup_object(&self, key: &str) -> Option<&'a dyn Object> {
+        for stack_frame in self.stack.iter().rev() {
+            if stack_frame.find_value(key).is_some() {
+                return None;
+            }
+            if stack_frame.kind == FrameType::Macro || stack_frame.kind == FrameType::Origin {
+                break;
+            }
         }
 
-        None
+        self.context.find_object(key)
     }
 
     // Add an assignment value (via {% set ... %} and {% set_global ... %} )
@@ -136,11 +177,11 @@ impl<'a> CallStack<'a> {
         }
     }
 
-    // Breaks current for loop
+    // Breaks current for or while loop
     pub fn break_for_loop(&mut self) -> Result<()> {
-        match self.current_frame_mut().for_loop {
-            Some(ref mut for_loop) => {
-                for_loop.break_loop();
+        match self.current_frame_mut().loop_state_mut() {
+            Some(state) => {
+                *state = ForLoopState::Break;
                 Ok(())
             }
             None => Err(Error::msg("Attempted `break` while not in `for loop`")),
@@ -160,11 +201,22 @@ impl<'a> CallStack<'a> {
         }
     }
 
-    // Continues current for loop
+    // Starts the next iteration of the current while loop, keeping what was set in its body
+    pub fn increment_while_loop(&mut self) -> Result<()> {
+        match self.current_frame_mut().while_loop {
+            Some(ref mut state) => {
+                *state = ForLoopState::Normal;
+                Ok(())
+            }
+            None => Err(Error::msg("Attempted `increment` while not in `while loop`")),
+        }
+    }
+
+    // Continues current for or while loop
     pub fn continue_for_loop(&mut self) -> Result<()> {
-        match self.current_frame_mut().for_loop {
-            Some(ref mut for_loop) => {
-                for_loop.continue_loop();
+        match self.current_frame_mut().loop_state_mut() {
+            Some(state) => {
+                *state = ForLoopState::Continue;
                 Ok(())
             }
             None => Err(Error::msg("Attempted `continue` while not in `for loop`")),
@@ -173,20 +225,15 @@ impl<'a> CallStack<'a> {
 
     // True if should break body, applicable to `break` and `continue`
     pub fn should_break_body(&self) -> bool {
-        match self.current_frame().for_loop {
-            Some(ref for_loop) => {
-                for_loop.state == ForLoopState::Break || for_loop.state == ForLoopState::Continue
-            }
+        match self.current_frame().loop_state() {
+            Some(state) => state == ForLoopState::Break || state == ForLoopState::Continue,
             None => false,
         }
     }
 
     // True if should break loop, applicable to `break` only
     pub fn should_break_for_loop(&self) -> bool {
-        match self.current_frame().for_loop {
-            Some(ref for_loop) => for_loop.state == ForLoopState::Break,
-            None => false,
-        }
+        self.current_frame().loop_state() == Some(ForLoopState::Break)
     }
 
     // Grab the current frame template
@@ -218,13 +265,13 @@ impl<'a> CallStack<'a> {
             }
         }
 
-        // If we are here we take the user context
+        // If we are here we take the globals and the user context
         // and add the values found in the stack to it.
         // We do it this way as we can override global variable temporarily in forloops
-        let mut new_ctx = self.context.inner.clone();
-        for (key, val) in context {
-            new_ctx.insert(key, &val)
-        }
-        new_ctx.into_json()
+        let mut new_ctx = Map::new();
+        self.context.globals.copy_to_json(&mut new_ctx);
+        self.context.inner.copy_to_json(&mut new_ctx);
+        new_ctx.extend(context);
+        Value::Object(new_ctx)
     }
 }
@@ -0,0 +1,57 @@
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use serde_json::Number;
+
+use crate::errors::{Error, Result};
+use crate::parser::ast::MathOperator;
+
+// Numbers are converted from their shortest representation so `0.1` is exactly 0.1
+fn to_decimal(n: &Number) -> BigDecimal {
+    BigDecimal::from_str(&n.to_string()).expect("JSON numbers are valid decimals")
+}
+
+// The number of significant digits kept for divisions that don't give an exact result, eg `1 / 3`
+const DIVISION_PRECISION: u64 = 28;
+
+// Integers that fit in 64 bits are kept as integers, other values keep all their digits thanks
+// to serde_json's `arbitrary_precision`
+fn to_number(d: BigDecimal) -> Option<Number> {
+    if d.is_integer() {
+        if let Some(i) = d.to_i64() {
+            return Some(Number::from(i));
+        }
+        if let Some(u) = d.to_u64() {
+            return Some(Number::from(u));
+        }
+    }
+
+    serde_json::from_str(&d.normalized().to_plain_string()).ok()
+}
+
+// The result of a math operation on 2 numbers computed with arbitrary-precision decimals,
+// `None` being NaN
+pub fn compute_math(l: &Number, operator: MathOperator, r: &Number) -> Result<Option<Number>> {
+    let ll = to_decimal(l);
+    let rr = to_decimal(r);
+
+    let res = match operator {
+        MathOperator::Add => ll + rr,
+        MathOperator::Sub => ll - rr,
+        MathOperator::Mul => ll * rr,
+        MathOperator::Div => {
+            if rr.is_zero() {
+                return Ok(None);
+            }
+            (ll / rr).with_prec(DIVISION_PRECISION)
+        }
+        MathOperator::Modulo => {
+            if rr.is_zero() {
+                return Err(Error::msg(format!("Tried to do a modulo by zero: {} % {}", l, r)));
+            }
+            ll % rr
+        }
+    };
+
+    Ok(to_number(res))
+}
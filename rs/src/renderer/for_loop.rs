@@ -157,16 +157,6 @@ impl<'a> ForLoop<'a> {
         self.kind == ForLoopKind::KeyValue
     }
 
-    #[inline]
-    pub fn break_loop(&mut self) {
-        self.state = ForLoopState::Break;
-    }
-
-    #[inline]
-    pub fn continue_loop(&mut self) {
-        self.state = ForLoopState::Continue;
-    }
-
     #[inline]
     pub fn get_current_value(&self) -> Val<'a> {
         self.values.current_value(self.current)
@@ -0,0 +1,174 @@
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::errors::{Error, ErrorKind, Result};
+use crate::lysine::RenderLimits;
+
+// Keeps track of the resources used by a render against its `RenderLimits`
+#[derive(Debug)]
+pub struct Budget {
+    limits: RenderLimits,
+    loop_iterations: usize,
+    call_depth: usize,
+    fuel: u64,
+    // When the render has to be done by, computed from the timeout when it starts
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub fn new(limits: RenderLimits) -> Self {
+        Budget {
+            limits,
+            loop_iterations: 0,
+            call_depth: 0,
+            fuel: 0,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+
+    // What this pass of an async render used of the limits that are for the whole render
+    #[cfg(feature = "async")]
+    pub fn spent(&self) -> Spent {
+        Spent { fuel: self.fuel, deadline: self.deadline }
+    }
+
+    // The budget for the next pass of an async render: the fuel used and the deadline are kept
+    // but the loops, calls and output start over as the template is rendered from the beginning
+    #[cfg(feature = "async")]
+    pub fn resume(limits: RenderLimits, spent: Spent) -> Self {
+        Budget { fuel: spent.fuel, deadline: spent.deadline, ..Budget::new(limits) }
+    }
+
+    // Uses some fuel for a node or an expression, also checking the deadline while at it
+    pub fn consume_fuel(&mut self) -> Result<()> {
+        self.fuel += 1;
+        if let Some(max) = self.limits.max_fuel {
+            if self.fuel > max {
+                return Err(Error::limit_exceeded(ErrorKind::FuelExhausted(max)));
+            }
+        }
+
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if Instant::now() > deadline {
+                return Err(Error::limit_exceeded(ErrorKind::Timeout(timeout)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Called before each iteration of a `{% for %}` or `{% while %}` loop
+    pub fn loop_iteration(&mut self) -> Result<()> {
+        self.loop_iterations += 1;
+        if let Some(max) = self.limits.max_loop_iterations {
+            if self.loop_iterations > max {
+                return Err(Error::limit_exceeded(ErrorKind::LoopIterationLimitExceeded(max)));
+            }
+        }
+
+        self.consume_fuel()
+    }
+
+    // Called before building an array of `len` items to iterate on, like with `range()`, so an
+    // array longer than the loop iterations left errors before being built
+    pub fn check_loop_len(&self, len: usize) -> Result<()> {
+        match (self.limits.max_loop_iterations, self.loop_iterations_left()) {
+            (Some(max), Some(left)) if len > left => {
+                Err(Error::limit_exceeded(ErrorKind::LoopIterationLimitExceeded(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // How many loop iterations the render can still do, `None` if they aren't limited
+    pub fn loop_iterations_left(&self) -> Option<usize> {
+        self.limits.max_loop_iterations.map(|max| max - self.loop_iterations.min(max))
+    }
+
+    // Called when entering a macro or an include, `exit_call` has to be called when leaving it
+    pub fn enter_call(&mut self) -> Result<()> {
+        if let Some(max) = self.limits.max_call_depth {
+            if self.call_depth >= max {
+                return Err(Error::limit_exceeded(ErrorKind::CallDepthLimitExceeded(max)));
+            }
+        }
+        self.call_depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    // Wraps the writer of the output of the render so it errors once it goes over the limit
+    pub fn output_writer<'w, W: Write>(&self, inner: &'w mut W) -> LimitedWriter<'w, W> {
+        LimitedWriter::new(inner, self.limits.max_output_bytes, ErrorKind::OutputLimitExceeded)
+    }
+
+    // Wraps the writer of a string built while rendering, like the output of a macro, so it
+    // errors once the string goes over the limit
+    pub fn value_writer<'w, W: Write>(&self, inner: &'w mut W) -> LimitedWriter<'w, W> {
+        LimitedWriter::new(inner, self.limits.max_value_bytes, ErrorKind::ValueLimitExceeded)
+    }
+
+    // Called before building a value of `bytes` bytes, like a repeated string
+    pub fn check_value_len(&self, bytes: usize) -> Result<()> {
+        match self.limits.max_value_bytes {
+            Some(max) if bytes > max => {
+                Err(Error::limit_exceeded(ErrorKind::ValueLimitExceeded(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Whether the size of the values built while rendering is limited, it is only computed then
+    pub fn limits_values(&self) -> bool {
+        self.limits.max_value_bytes.is_some()
+    }
+}
+
+// The fuel used and the deadline of an async render, kept between its passes
+#[cfg(feature = "async")]
+#[derive(Clone, Copy, Debug)]
+pub struct Spent {
+    fuel: u64,
+    deadline: Option<Instant>,
+}
+
+// A writer erroring once more than `max` bytes are written to it
+pub struct LimitedWriter<'w, W: Write> {
+    inner: &'w mut W,
+    written: usize,
+    max: Option<usize>,
+    // The error given when going over `max`
+    limit_kind: fn(usize) -> ErrorKind,
+}
+
+impl<'w, W: Write> LimitedWriter<'w, W> {
+    fn new(inner: &'w mut W, max: Option<usize>, limit_kind: fn(usize) -> ErrorKind) -> Self {
+        LimitedWriter { inner, written: 0, max, limit_kind }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max) = self.max {
+            if self.written.saturating_add(buf.len()) > max {
+                let error = Error::limit_exceeded((self.limit_kind)(max));
+                return Err(io::Error::other(error));
+            }
+        }
+
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
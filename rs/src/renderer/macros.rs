@@ -1,8 +1,11 @@
 use crate::errors::{Error, Result};
 use crate::parser::ast::MacroDefinition;
-use crate::template::Template;
+use crate::template::{MacroScope, Template};
 use crate::lysine::Lysine;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
 
 // Types around Macros get complicated, simplify it a bit by using aliases
 
@@ -13,15 +16,59 @@ pub type MacroNamespaceMap<'a> = HashMap<&'a str, (&'a str, &'a MacroDefinitionM
 // Maps { template => { namespace => ( macro_template, { macro => macro_definition }) }
 pub type MacroTemplateMap<'a> = HashMap<&'a str, MacroNamespaceMap<'a>>;
 
+// Maps { bound name => ( macro_template, macro_definition ) }
+pub type MacroBindingMap<'a> = HashMap<&'a str, (&'a str, &'a MacroDefinition)>;
+// Maps { template => { scope => { bound name => ( macro_template, macro_definition ) } } }
+pub type MacroScopeMap<'a> = HashMap<&'a str, HashMap<&'a MacroScope, MacroBindingMap<'a>>>;
+
+// Key of the object a macro reference like `macros::my_macro` evaluates to
+pub static MACRO_REF_KEY: &str = "__lysine_macro";
+
+// Builds the value standing for a macro so it can be stored in variables and passed around.
+// It only holds the index of the macro in the references of the render and the random key of
+// that render, so a value coming from the context can't point to an arbitrary macro
+pub fn macro_ref_to_value(key: &str, index: usize) -> Value {
+    json!({ MACRO_REF_KEY: { "key": key, "index": index } })
+}
+
+// Returns the (key, index) of a value created by `macro_ref_to_value`
+pub fn value_to_macro_ref(value: &Value) -> Option<(&str, usize)> {
+    let obj = value.as_object()?;
+    if obj.len() != 1 {
+        return None;
+    }
+    let macro_ref = obj.get(MACRO_REF_KEY)?;
+
+    Some((macro_ref.get("key")?.as_str()?, macro_ref.get("index")?.as_u64()? as usize))
+}
+
+// A key that can't be guessed, so macro references can only be created by the render itself
+pub fn new_macro_ref_key() -> String {
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+// A macro that was referenced during a render, see `macro_ref_to_value`
+#[derive(Clone, Copy, Debug)]
+pub struct MacroRefTarget<'a> {
+    // The namespace used to reference the macro
+    pub namespace: &'a str,
+    // The template the macro is defined in
+    pub template_name: &'a str,
+    pub definition: &'a MacroDefinition,
+}
+
 // Collection of all macro templates by file
 #[derive(Clone, Debug, Default)]
 pub struct MacroCollection<'a> {
     macros: MacroTemplateMap<'a>,
+    // Macros imported one by one with `{% from ... import ... %}`
+    bindings: MacroScopeMap<'a>,
 }
 
 impl<'a> MacroCollection<'a> {
     pub fn from_original_template(tpl: &'a Template, lysine: &'a Lysine) -> MacroCollection<'a> {
-        let mut macro_collection = MacroCollection { macros: MacroTemplateMap::new() };
+        let mut macro_collection =
+            MacroCollection { macros: MacroTemplateMap::new(), bindings: MacroScopeMap::new() };
 
         macro_collection
             .add_macros_from_template(lysine, tpl)
@@ -69,6 +116,28 @@ impl<'a> MacroCollection<'a> {
 
         self.macros.insert(template_name, macro_namespace_map);
 
+        for (scope, filename, macro_name, bound_name) in &template.imported_macros {
+            let macro_tpl = lysine.get_template(filename)?;
+            self.add_macros_from_template(lysine, macro_tpl)?;
+
+            let macro_definition = match macro_tpl.macros.get(macro_name) {
+                Some(m) => m,
+                None => {
+                    return Err(Error::msg(format!(
+                        "Macro `{}` imported in template `{}` was not found in template `{}`",
+                        macro_name, template_name, filename
+                    )));
+                }
+            };
+
+            self.bindings
+                .entry(template_name)
+                .or_default()
+                .entry(scope)
+                .or_default()
+                .insert(&bound_name[..], (&macro_tpl.name[..], macro_definition));
+        }
+
         for parent in &template.parents {
             let parent = &parent[..];
             let parent_template = lysine.get_template(parent)?;
@@ -116,4 +185,19 @@ impl<'a> MacroCollection<'a> {
             )))
         }
     }
+
+    // Finds a macro imported with `{% from ... import ... %}` under that name in the given scope
+    // of a template
+    pub fn lookup_bound_macro(
+        &self,
+        template_name: &str,
+        scope: &MacroScope,
+        name: &str,
+    ) -> Option<(&'a str, &'a MacroDefinition)> {
+        self.bindings
+            .get(template_name)
+            .and_then(|scopes| scopes.get(scope))
+            .and_then(|bindings| bindings.get(name))
+            .copied()
+    }
 }
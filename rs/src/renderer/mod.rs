@@ -2,20 +2,33 @@ mod square_brackets;
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "async")]
+mod async_calls;
 mod call_stack;
+#[cfg(feature = "decimal")]
+mod decimal;
 mod for_loop;
+mod limits;
 mod macros;
 mod processor;
 mod stack_frame;
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::Write;
+use std::iter;
 
-use self::processor::Processor;
-use crate::errors::Result;
+use serde_json::Value;
+
+#[cfg(feature = "async")]
+pub(crate) use self::async_calls::AsyncCalls;
+use self::processor::{type_name, Processor};
+use crate::context::{Context, LayeredContext};
+use crate::errors::{Error, Result};
+use crate::parser::ast::RequiredType;
 use crate::template::Template;
-use crate::lysine::Lysine;
+use crate::lysine::{Lysine, UndefinedBehavior};
 use crate::utils::buffer_to_string;
-use crate::Context;
 
 // Given a `Lysine` and reference to `Template` and a `Context`, renders text
 #[derive(Debug)]
@@ -25,15 +38,35 @@ pub struct Renderer<'a> {
     // Houses other templates, filters, global functions, etc
     lysine: &'a Lysine,
     // Read-only context to be bound to template˝
-    context: &'a Context,
+    context: Cow<'a, LayeredContext<'a>>,
     // If set rendering should be escaped
     should_escape: bool,
+    // What happens when a variable isn't in the context
+    undefined_behavior: UndefinedBehavior,
 }
 
 impl<'a> Renderer<'a> {
     // Create a new `Renderer`
     #[inline]
     pub fn new(template: &'a Template, lysine: &'a Lysine, context: &'a Context) -> Renderer<'a> {
+        Renderer::with_context(template, lysine, Cow::Owned(LayeredContext::from(context)))
+    }
+
+    // Create a new `Renderer` from a context made of layers
+    #[inline]
+    pub fn new_layered(
+        template: &'a Template,
+        lysine: &'a Lysine,
+        context: &'a LayeredContext<'a>,
+    ) -> Renderer<'a> {
+        Renderer::with_context(template, lysine, Cow::Borrowed(context))
+    }
+
+    fn with_context(
+        template: &'a Template,
+        lysine: &'a Lysine,
+        context: Cow<'a, LayeredContext<'a>>,
+    ) -> Renderer<'a> {
         let should_escape = lysine.autoescape_suffixes.iter().any(|ext| {
             // We prefer a `path` if set, otherwise use the `name`
             if let Some(ref p) = template.path {
@@ -42,7 +75,19 @@ impl<'a> Renderer<'a> {
             template.name.ends_with(ext)
         });
 
-        Renderer { template, lysine, context, should_escape }
+        Renderer {
+            template,
+            lysine,
+            context,
+            should_escape,
+            undefined_behavior: lysine.undefined_behavior,
+        }
+    }
+
+    // Overrides the `UndefinedBehavior` of the `Lysine` instance for this render
+    pub fn with_undefined_behavior(mut self, undefined_behavior: UndefinedBehavior) -> Self {
+        self.undefined_behavior = undefined_behavior;
+        self
     }
 
     // Combines the context with the Template to generate the end result
@@ -54,9 +99,136 @@ impl<'a> Renderer<'a> {
 
     // Combines the context with the Template to write the end result to output
     pub fn render_to(&self, mut output: impl Write) -> Result<()> {
-        let mut processor =
-            Processor::new(self.template, self.lysine, self.context, self.should_escape);
+        let context = self.checked_context()?;
+        let mut processor = self.processor(&context);
 
         processor.render(&mut output)
     }
+
+    // Combines the context with the Template to generate a value, see `Processor::render_value`
+    pub fn render_value(&self) -> Result<Value> {
+        let context = self.checked_context()?;
+        let mut processor = self.processor(&context);
+
+        processor.render_value()
+    }
+
+    // Renders a single block of the template to a String
+    pub fn render_block(&self, block_name: &str) -> Result<String> {
+        let mut output = Vec::with_capacity(2000);
+        self.render_block_to(block_name, &mut output)?;
+        buffer_to_string(|| "converting rendered buffer to string".to_string(), output)
+    }
+
+    // Renders a single block of the template to output
+    pub fn render_block_to(&self, block_name: &str, mut output: impl Write) -> Result<()> {
+        let context = self.checked_context()?;
+        let mut processor = self.processor(&context);
+
+        processor.render_single_block(block_name, &mut output)
+    }
+
+    // Renders a macro of the template with the given arguments to a String
+    pub fn render_macro(&self, macro_name: &str, args: &HashMap<String, Value>) -> Result<String> {
+        let mut output = Vec::with_capacity(2000);
+        let context = self.checked_context()?;
+        let mut processor = self.processor(&context);
+        processor.render_macro(macro_name, args, &mut output)?;
+        buffer_to_string(|| "converting rendered buffer to string".to_string(), output)
+    }
+
+    // Does one pass of an async render: it stops at the first async call that wasn't awaited yet
+    #[cfg(feature = "async")]
+    pub fn render_to_with_async_calls(
+        &self,
+        mut output: impl Write,
+        async_calls: &mut AsyncCalls,
+    ) -> Result<()> {
+        let context = self.checked_context()?;
+        let mut processor = self.processor(&context).with_async_calls(async_calls);
+
+        let res = processor.render(&mut output);
+        processor.end_async_pass();
+        res
+    }
+
+    fn processor<'b>(&'b self, context: &'b LayeredContext<'b>) -> Processor<'b> {
+        Processor::new(self.template, self.lysine, context, self.should_escape)
+            .with_undefined_behavior(self.undefined_behavior)
+    }
+
+    fn checked_context(&self) -> Result<Cow<'_, LayeredContext<'_>>> {
+        self.check_requirements()
+            .map_err(|e| Error::chain(format!("Failed to render '{}'", self.template.name), e))
+    }
+
+    // Checks the context against the variables declared with `{% requires %}` by the template
+    // and its parents before anything is written. The defaults of the missing ones are put in a
+    // layer on top of the context.
+    fn check_requirements(&self) -> Result<Cow<'_, LayeredContext<'_>>> {
+        let mut defaults: Option<LayeredContext> = None;
+        let parents = self.template.parents.iter().map(|name| self.lysine.get_template(name));
+
+        for template in iter::once(Ok(self.template)).chain(parents) {
+            let template = template?;
+            for requirement in &template.requires {
+                let context = defaults.as_ref().unwrap_or(&self.context);
+                let found = match context
+                    .get_dotted(&requirement.name)
+                    .or_else(|| self.lysine.globals.get_dotted(&requirement.name))
+                {
+                    Some(val) => Some(val),
+                    None => match requirement.default {
+                        Some(ref default) => {
+                            let defaults = defaults
+                                .get_or_insert_with(|| LayeredContext::on_top_of(&self.context));
+                            if !defaults.insert_dotted(&requirement.name, default.clone()) {
+                                return Err(Error::msg(format!(
+                                    "Template `{}` can't use the default of `{}`: something on its path isn't an object",
+                                    template.name, requirement.name
+                                )));
+                            }
+                            Some(Cow::Borrowed(default))
+                        }
+                        None => None,
+                    },
+                };
+
+                match (found, requirement.kind) {
+                    (None, _) => {
+                        return Err(Error::msg(format!(
+                            "Template `{}` requires `{}` but it isn't in the context",
+                            template.name, requirement.name
+                        )));
+                    }
+                    (Some(val), Some(kind)) if !is_of_type(&val, kind) => {
+                        return Err(Error::msg(format!(
+                            "Template `{}` requires `{}` to be {} but it is {}",
+                            template.name,
+                            requirement.name,
+                            kind,
+                            type_name(&val)
+                        )));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(match defaults {
+            Some(defaults) => Cow::Owned(defaults),
+            None => Cow::Borrowed(&*self.context),
+        })
+    }
+}
+
+fn is_of_type(val: &Value, kind: RequiredType) -> bool {
+    match kind {
+        RequiredType::String => val.is_string(),
+        RequiredType::Number => val.is_number(),
+        RequiredType::Integer => val.is_i64() || val.is_u64(),
+        RequiredType::Boolean => val.is_boolean(),
+        RequiredType::Array => val.is_array(),
+        RequiredType::Object => val.is_object(),
+    }
 }
@@ -1,25 +1,39 @@
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{self, Write};
+use std::iter;
+use std::mem;
+use std::ptr;
 
-use serde_json::{to_string_pretty, to_value, Number, Value};
+use serde_json::{to_string_pretty, to_value, to_writer, Number, Value};
 
-use crate::context::{ValueRender, ValueTruthy};
+use crate::context::{LayeredContext, ValueRender, ValueTruthy};
 use crate::errors::{Error, Result};
 use crate::parser::ast::*;
+#[cfg(feature = "async")]
+use crate::renderer::async_calls::AsyncCalls;
 use crate::renderer::call_stack::CallStack;
+#[cfg(feature = "decimal")]
+use crate::renderer::decimal;
 use crate::renderer::for_loop::ForLoop;
-use crate::renderer::macros::MacroCollection;
+use crate::renderer::limits::{Budget, LimitedWriter};
+use crate::renderer::macros::{
+    macro_ref_to_value, new_macro_ref_key, value_to_macro_ref, MacroCollection, MacroRefTarget,
+};
 use crate::renderer::square_brackets::pull_out_square_bracket;
 use crate::renderer::stack_frame::{FrameContext, FrameType, Val};
-use crate::template::Template;
-use crate::lysine::Lysine;
-use crate::utils::render_to_string;
-use crate::Context;
+use crate::sandbox::{self, Sandbox};
+use crate::template::{MacroScope, Template};
+use crate::lysine::{Lysine, UndefinedBehavior};
+use crate::utils::{buffer_to_string, render_to_string};
 
 // Special string indicating request to dump context
 static MAGICAL_DUMP_VAR: &str = "__lysine_context";
 
+// The evaluated arguments of a macro call: the positional ones and the keyword ones
+type MacroArgs<'a> = (Vec<Val<'a>>, Vec<(&'a str, Val<'a>)>);
+
 // This will convert a Lysine variable to a json pointer if it is possible by replacing
 // the index with their evaluated stringified value
 fn evaluate_sub_variables(key: &str, call_stack: &CallStack) -> Result<String> {
@@ -97,6 +111,142 @@ fn process_path<'a>(path: &str, call_stack: &CallStack<'a>) -> Result<Val<'a>> {
     }
 }
 
+// Gets an object attribute from a string key or an array element from an integer key
+fn get_by_key<'v>(value: &'v Value, key: &Value) -> Option<&'v Value> {
+    match (value, key) {
+        (Value::Object(map), Value::String(k)) => map.get(k),
+        (Value::Array(arr), Value::Number(n)) => arr.get(n.as_u64()? as usize),
+        _ => None,
+    }
+}
+
+// Integers are kept as an i128 during math so that the results of operations on any i64/u64
+// can be computed before checking whether they fit in 64 bits
+fn as_integer(n: &Number) -> Option<i128> {
+    n.as_i64().map(i128::from).or_else(|| n.as_u64().map(i128::from))
+}
+
+fn integer_to_number(i: i128) -> Option<Number> {
+    match i64::try_from(i) {
+        Ok(i) => Some(Number::from(i)),
+        Err(_) => u64::try_from(i).ok().map(Number::from),
+    }
+}
+
+// The result of a math operation on 2 numbers, `None` being NaN.
+// Integers stay exact: floats are only used if one of the operands is a float or for a
+// division that doesn't give an integer.
+fn compute_math(l: &Number, operator: MathOperator, r: &Number) -> Result<Option<Number>> {
+    if let (Some(ll), Some(rr)) = (as_integer(l), as_integer(r)) {
+        let res = match operator {
+            MathOperator::Add => ll.checked_add(rr),
+            MathOperator::Sub => ll.checked_sub(rr),
+            MathOperator::Mul => ll.checked_mul(rr),
+            MathOperator::Div => {
+                if rr == 0 {
+                    return Ok(None);
+                }
+                if ll % rr != 0 {
+                    return Ok(Number::from_f64(ll as f64 / rr as f64));
+                }
+                Some(ll / rr)
+            }
+            MathOperator::Modulo => {
+                if rr == 0 {
+                    return Err(Error::msg(format!(
+                        "Tried to do a modulo by zero: {} % {}",
+                        ll, rr
+                    )));
+                }
+                Some(ll % rr)
+            }
+        };
+
+        return match res.and_then(integer_to_number) {
+            Some(n) => Ok(Some(n)),
+            None => Err(Error::msg(format!(
+                "{} {} {} results in an integer that doesn't fit in 64 bits",
+                ll, operator, rr
+            ))),
+        };
+    }
+
+    let ll = l.as_f64().unwrap();
+    let rr = r.as_f64().unwrap();
+    let res = match operator {
+        MathOperator::Add => ll + rr,
+        MathOperator::Sub => ll - rr,
+        MathOperator::Mul => ll * rr,
+        MathOperator::Div => {
+            let res = ll / rr;
+            // Whole results are given as integers if they fit, `as` would saturate otherwise
+            if res.round() == res && res >= i64::MIN as f64 && res < i64::MAX as f64 {
+                return Ok(Some(Number::from(res as i64)));
+            }
+            res
+        }
+        MathOperator::Modulo => ll % rr,
+    };
+
+    Ok(Number::from_f64(res))
+}
+
+// Compares 2 numbers exactly if they are both integers
+fn compare_numbers(l: &Number, r: &Number) -> Ordering {
+    match (as_integer(l), as_integer(r)) {
+        (Some(l), Some(r)) => l.cmp(&r),
+        _ => l.as_f64().unwrap().total_cmp(&r.as_f64().unwrap()),
+    }
+}
+
+// The ordering used by `<`, `<=`, `>` and `>=`: numbers, strings (lexicographically), booleans
+// and arrays (element by element) can be compared with values of the same type and null is
+// smaller than everything else
+fn compare_values(lhs: &Value, rhs: &Value) -> Result<Ordering> {
+    let ordering = match (lhs, rhs) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (Value::Number(l), Value::Number(r)) => compare_numbers(l, r),
+        (Value::String(l), Value::String(r)) => l.cmp(r),
+        (Value::Bool(l), Value::Bool(r)) => l.cmp(r),
+        (Value::Array(l), Value::Array(r)) => {
+            for (l, r) in l.iter().zip(r) {
+                match compare_values(l, r)? {
+                    Ordering::Equal => (),
+                    ordering => return Ok(ordering),
+                }
+            }
+            l.len().cmp(&r.len())
+        }
+        _ => return Err(Error::invalid_comparison(type_name(lhs), type_name(rhs))),
+    };
+
+    Ok(ordering)
+}
+
+// How a type is called in the error messages
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match *value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+fn value_as_number(value: &Value) -> Result<Number> {
+    match *value {
+        Value::Number(ref n) => Ok(n.clone()),
+        _ => Err(Error::msg(format!(
+            "Tried to do math with a value that is not a number: {}",
+            value
+        ))),
+    }
+}
+
 // Processes the ast and renders the output
 pub struct Processor<'a> {
     // The template we're trying to render
@@ -112,10 +262,24 @@ pub struct Processor<'a> {
     macros: MacroCollection<'a>,
     // If set, rendering should be escaped
     should_escape: bool,
+    // Whether escaping is enabled for the whole render, `should_escape` is turned off while
+    // evaluating some expressions
+    autoescape: bool,
     // Used when super() is used in a block, to know where we are in our stack of
     // definitions and for which block
     // Vec<(block name, tpl_name, level)>
     blocks: Vec<(&'a str, &'a str, usize)>,
+    // What happens when a variable isn't in the context
+    undefined_behavior: UndefinedBehavior,
+    // The macros referenced as values, like `macros::my_macro`, during the render
+    macro_refs: Vec<MacroRefTarget<'a>>,
+    // The random key of the macro references of the render
+    macro_ref_key: String,
+    // The resources used so far by the render
+    budget: Budget,
+    // The results of the async calls, only set when doing an async render
+    #[cfg(feature = "async")]
+    async_calls: Option<&'a mut AsyncCalls>,
 }
 
 impl<'a> Processor<'a> {
@@ -123,7 +287,7 @@ impl<'a> Processor<'a> {
     pub fn new(
         template: &'a Template,
         lysine: &'a Lysine,
-        context: &'a Context,
+        context: &'a LayeredContext<'a>,
         should_escape: bool,
     ) -> Self {
         // Gets the root template if we are rendering something with inheritance or just return
@@ -134,7 +298,7 @@ impl<'a> Processor<'a> {
             .map(|parent| lysine.get_template(parent).unwrap())
             .unwrap_or(template);
 
-        let call_stack = CallStack::new(context, template);
+        let call_stack = CallStack::new(context, &lysine.globals, template);
 
         Processor {
             template,
@@ -143,7 +307,57 @@ impl<'a> Processor<'a> {
             call_stack,
             macros: MacroCollection::from_original_template(template, lysine),
             should_escape,
+            autoescape: should_escape,
             blocks: Vec::new(),
+            undefined_behavior: lysine.undefined_behavior,
+            macro_refs: Vec::new(),
+            macro_ref_key: new_macro_ref_key(),
+            budget: Budget::new(lysine.render_limits),
+            #[cfg(feature = "async")]
+            async_calls: None,
+        }
+    }
+
+    // Overrides the `UndefinedBehavior` of the `Lysine` instance
+    pub fn with_undefined_behavior(mut self, undefined_behavior: UndefinedBehavior) -> Self {
+        self.undefined_behavior = undefined_behavior;
+        self
+    }
+
+    // Allows the async functions and filters to be called, using the results awaited so far
+    #[cfg(feature = "async")]
+    pub fn with_async_calls(mut self, async_calls: &'a mut AsyncCalls) -> Self {
+        if let Some(spent) = async_calls.spent {
+            self.budget = Budget::resume(self.lysine.render_limits, spent);
+        }
+        match async_calls.macro_ref_key {
+            Some(ref key) => self.macro_ref_key = key.clone(),
+            None => async_calls.macro_ref_key = Some(self.macro_ref_key.clone()),
+        }
+        self.async_calls = Some(async_calls);
+        self
+    }
+
+    // Keeps what the budget used at the end of a pass of an async render, the next pass goes
+    // on from it
+    #[cfg(feature = "async")]
+    pub fn end_async_pass(self) {
+        if let Some(async_calls) = self.async_calls {
+            async_calls.spent = Some(self.budget.spent());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn call_async(
+        &mut self,
+        key: String,
+        start: impl FnOnce() -> crate::builtins::functions::AsyncResult,
+    ) -> Result<Value> {
+        match self.async_calls {
+            Some(ref mut async_calls) => async_calls.call(key, start),
+            None => Err(Error::msg(
+                "Async functions and filters can only be used when rendering with `Lysine::render_async`",
+            )),
         }
     }
 
@@ -164,17 +378,17 @@ impl<'a> Processor<'a> {
             ExprVal::Ident(ref ident) => ident,
             ExprVal::FunctionCall(FunctionCall { ref name, .. }) => name,
             ExprVal::Array(_) => "an array lilysinel",
-            _ => return Err(Error::msg(format!(
-                "Forloop containers have to be an ident or a function call (tried to iterate on '{:?}')",
-                for_loop.container.val,
-            ))),
+            _ => "an expression",
         };
 
         let for_loop_name = &for_loop.value;
         let for_loop_body = &for_loop.body;
         let for_loop_empty_body = &for_loop.empty_body;
 
-        let container_val = self.safe_eval_expression(&for_loop.container)?;
+        let container_val = match self.eval_object_items(for_loop)? {
+            Some(items) => Cow::Owned(items),
+            None => self.safe_eval_expression(&for_loop.container)?,
+        };
 
         let for_loop = match *container_val {
             Value::Array(_) => {
@@ -213,6 +427,10 @@ impl<'a> Processor<'a> {
                     ),
                 }
             }
+            // Undefined variables can be null when the undefined behavior isn't strict
+            Value::Null if self.undefined_behavior != UndefinedBehavior::Strict => {
+                ForLoop::from_array(&for_loop.value, Cow::Owned(Value::Array(vec![])))
+            }
             _ => {
                 return Err(Error::msg(format!(
                     "Tried to iterate on a container (`{}`) that has a unsupported type",
@@ -229,6 +447,7 @@ impl<'a> Processor<'a> {
                 self.call_stack.push_for_loop_frame(for_loop_name, for_loop);
 
                 for _ in 0..len {
+                    self.budget.loop_iteration()?;
                     self.render_body(for_loop_body, write)?;
 
                     if self.call_stack.should_break_for_loop() {
@@ -245,6 +464,39 @@ impl<'a> Processor<'a> {
         }
     }
 
+    fn render_while_loop(
+        &mut self,
+        while_loop: &'a Whileloop,
+        write: &mut impl Write,
+    ) -> Result<()> {
+        let max_iterations = self.lysine.max_while_iterations;
+        self.call_stack.push_while_loop_frame("while");
+
+        let mut iterations = 0;
+        while self.eval_as_bool(&while_loop.condition)? {
+            if iterations == max_iterations {
+                return Err(Error::msg(format!(
+                    "`while` loop stopped after reaching the limit of {} iterations",
+                    max_iterations
+                )));
+            }
+            iterations += 1;
+            self.budget.loop_iteration()?;
+
+            self.render_body(&while_loop.body, write)?;
+
+            if self.call_stack.should_break_for_loop() {
+                break;
+            }
+
+            self.call_stack.increment_while_loop()?;
+        }
+
+        self.call_stack.pop();
+
+        Ok(())
+    }
+
     fn render_if_node(&mut self, if_node: &'a If, write: &mut impl Write) -> Result<()> {
         for (_, expr, body) in &if_node.conditions {
             if self.eval_as_bool(expr)? {
@@ -280,9 +532,17 @@ impl<'a> Processor<'a> {
 
         // Can we find this one block in these definitions? If so render it
         if let Some(block_def) = blocks_definitions.get(&block.name) {
-            let (_, Block { ref body, .. }) = block_def[0];
+            let (ref tpl_name, Block { ref body, required, .. }) = block_def[0];
+            if required {
+                return Err(Error::msg(format!(
+                    "Block `{}` is marked as required in `{}` but isn't overridden",
+                    block.name, tpl_name
+                )));
+            }
             self.blocks.push((&block.name[..], &level_template.name[..], level));
-            return self.render_body(body, write);
+            self.render_body(body, write)?;
+            self.blocks.pop();
+            return Ok(());
         }
 
         // Do we have more parents to look through?
@@ -291,6 +551,12 @@ impl<'a> Processor<'a> {
         }
 
         // Nope, just render the body we got
+        if block.required {
+            return Err(Error::msg(format!(
+                "Block `{}` is marked as required but isn't overridden",
+                block.name
+            )));
+        }
         self.render_body(&block.body, write)
     }
 
@@ -338,8 +604,28 @@ impl<'a> Processor<'a> {
 
     fn eval_expression(&mut self, expr: &'a Expr) -> Result<Val<'a>> {
         let mut needs_escape = false;
+        let mut res = self.eval_unescaped_expression(expr, &mut needs_escape)?;
+
+        // Checks if it's a string and we need to escape it (if the last filter is `safe` we don't)
+        if self.should_escape && needs_escape && res.is_string() && !expr.is_marked_safe() {
+            res = Cow::Owned(
+                to_value(self.lysine.get_escape_fn()(res.as_str().unwrap())).map_err(Error::json)?,
+            );
+        }
+
+        Ok(res)
+    }
+
+    // Evaluates an expression without escaping it, `needs_escape` is set to whether the
+    // result would need to be escaped when rendered
+    fn eval_unescaped_expression(
+        &mut self,
+        expr: &'a Expr,
+        needs_escape: &mut bool,
+    ) -> Result<Val<'a>> {
+        self.budget.consume_fuel()?;
 
-        let mut res = match expr.val {
+        let res = match expr.val {
             ExprVal::Array(ref arr) => {
                 let mut values = vec![];
                 for v in arr {
@@ -349,43 +635,52 @@ impl<'a> Processor<'a> {
             }
             ExprVal::In(ref in_cond) => Cow::Owned(Value::Bool(self.eval_in_condition(in_cond)?)),
             ExprVal::String(ref val) => {
-                needs_escape = true;
+                *needs_escape = true;
                 Cow::Owned(Value::String(val.to_string()))
             }
             ExprVal::StringConcat(ref str_concat) => {
-                let mut res = String::new();
+                let mut buffer = Vec::new();
+                let mut writer = self.budget.value_writer(&mut buffer);
                 for s in &str_concat.values {
-                    match *s {
-                        ExprVal::String(ref v) => res.push_str(v),
-                        ExprVal::Int(ref v) => res.push_str(&format!("{}", v)),
-                        ExprVal::Float(ref v) => res.push_str(&format!("{}", v)),
-                        ExprVal::Ident(ref i) => match *self.lookup_ident(i)? {
-                            Value::String(ref v) => res.push_str(v),
-                            Value::Number(ref v) => res.push_str(&v.to_string()),
-                            _ => return Err(Error::msg(format!(
-                                "Tried to concat a value that is not a string or a number from ident {}",
-                                i
-                            ))),
-                        },
-                        ExprVal::FunctionCall(ref fn_call) => match *self.eval_lysine_fn_call(fn_call, &mut needs_escape)? {
-                            Value::String(ref v) => res.push_str(v),
-                            Value::Number(ref v) => res.push_str(&v.to_string()),
-                            _ => return Err(Error::msg(format!(
-                                "Tried to concat a value that is not a string or a number from function call {}",
-                                fn_call.name
-                            ))),
-                        },
-                        _ => unreachable!(),
-                    };
+                    // Only the functions decide whether a concatenation needs to be escaped
+                    let mut value_needs_escape = false;
+                    let val = self.eval_unescaped_expression(s, &mut value_needs_escape)?;
+                    if let ExprVal::FunctionCall(_) = s.val {
+                        *needs_escape |= value_needs_escape;
+                    }
+                    val.render(&mut writer)?;
                 }
 
+                let res = buffer_to_string(|| "string concatenation".to_string(), buffer)?;
                 Cow::Owned(Value::String(res))
             }
+            ExprVal::Access(ref access) => match self.eval_access(access, needs_escape, false) {
+                // Only lenient accesses can be missing
+                Ok(val) => val.unwrap(),
+                // Like for idents, `!! (a).missing` is truthy instead of an error
+                Err(e) if expr.negated && !e.is_async_call_pending() => {
+                    return Ok(Cow::Owned(Value::Bool(true)))
+                }
+                Err(e) => return Err(e),
+            },
+            ExprVal::Coalesce(ref coalesce) => self.eval_coalesce(coalesce, needs_escape)?,
+            ExprVal::Group(ref inner) => {
+                let val = self.eval_unescaped_expression(inner, needs_escape)?;
+                if inner.is_marked_safe() {
+                    *needs_escape = false;
+                }
+                val
+            }
             ExprVal::Int(val) => Cow::Owned(Value::Number(val.into())),
             ExprVal::Float(val) => Cow::Owned(Value::Number(Number::from_f64(val).unwrap())),
             ExprVal::Bool(val) => Cow::Owned(Value::Bool(val)),
+            ExprVal::Ident(_) if self.eval_object_len(expr).is_some() => {
+                let len = self.eval_object_len(expr).unwrap();
+                let res = Cow::Owned(Value::from(len));
+                return self.apply_filters_from(expr, 1, res, needs_escape);
+            }
             ExprVal::Ident(ref ident) => {
-                needs_escape = ident != MAGICAL_DUMP_VAR;
+                *needs_escape = ident != MAGICAL_DUMP_VAR;
                 // Negated idents are special cased as `not undefined_ident` should not
                 // error but instead be falsy values
                 match self.lookup_ident(ident) {
@@ -399,40 +694,63 @@ impl<'a> Processor<'a> {
                     Err(e) => {
                         if expr.has_default_filter() {
                             self.get_default_value(expr)?
-                        } else {
-                            if !expr.negated {
-                                return Err(e);
-                            }
+                        } else if expr.negated {
                             // A negative undefined ident is !false so truthy
                             return Ok(Cow::Owned(Value::Bool(true)));
+                        } else {
+                            self.undefined_value(ident, e)?
                         }
                     }
                 }
             }
             ExprVal::FunctionCall(ref fn_call) => {
-                self.eval_lysine_fn_call(fn_call, &mut needs_escape)?
+                self.eval_lysine_fn_call(fn_call, needs_escape)?
+            }
+            ExprVal::MethodCall(ref method_call) => {
+                self.eval_method_call(method_call, needs_escape)?
             }
             ExprVal::MacroCall(ref macro_call) => {
-                let val = render_to_string(
+                let val = self.render_to_limited_string(
                     || format!("macro {}", macro_call.name),
-                    |w| self.eval_macro_call(macro_call, w),
+                    |p, w| p.eval_macro_call(macro_call, w),
                 )?;
                 Cow::Owned(Value::String(val))
             }
+            ExprVal::MacroRef(ref macro_ref) => Cow::Owned(self.eval_macro_ref(macro_ref)?),
             ExprVal::Test(ref test) => Cow::Owned(Value::Bool(self.eval_test(test)?)),
             ExprVal::Logic(_) => Cow::Owned(Value::Bool(self.eval_as_bool(expr)?)),
-            ExprVal::Math(_) => match self.eval_as_number(&expr.val) {
-                Ok(Some(n)) => Cow::Owned(Value::Number(n)),
-                Ok(None) => Cow::Owned(Value::String("NaN".to_owned())),
-                Err(e) => return Err(Error::msg(e)),
+            ExprVal::Math(ref math) => match self.eval_math(expr, math, needs_escape)? {
+                Some(val) => val,
+                None => Cow::Owned(Value::String("NaN".to_owned())),
             },
         };
 
-        for filter in &expr.filters {
+        self.apply_filters(expr, res, needs_escape)
+    }
+
+    // Runs the filters of an expression on its value and negates it if needed
+    fn apply_filters(
+        &mut self,
+        expr: &'a Expr,
+        res: Val<'a>,
+        needs_escape: &mut bool,
+    ) -> Result<Val<'a>> {
+        self.apply_filters_from(expr, 0, res, needs_escape)
+    }
+
+    // Same as `apply_filters` but skipping the first filters, which were already applied
+    fn apply_filters_from(
+        &mut self,
+        expr: &'a Expr,
+        skip: usize,
+        mut res: Val<'a>,
+        needs_escape: &mut bool,
+    ) -> Result<Val<'a>> {
+        for filter in &expr.filters[skip..] {
             if filter.name == "safe" || filter.name == "default" {
                 continue;
             }
-            res = self.eval_filter(&res, filter, &mut needs_escape)?;
+            res = self.eval_filter(&res, filter, needs_escape)?;
         }
 
         // Lastly, we need to check if the expression is negated, thus turning it into a bool
@@ -440,14 +758,274 @@ impl<'a> Processor<'a> {
             return Ok(Cow::Owned(Value::Bool(!res.is_truthy())));
         }
 
-        // Checks if it's a string and we need to escape it (if the last filter is `safe` we don't)
-        if self.should_escape && needs_escape && res.is_string() && !expr.is_marked_safe() {
-            res = Cow::Owned(
-                to_value(self.lysine.get_escape_fn()(res.as_str().unwrap())).map_err(Error::json)?,
-            );
+        Ok(res)
+    }
+
+    // Math on numbers, with `+` also concatenating 2 arrays and `*` repeating a string.
+    // Returns `None` for NaN
+    fn eval_math(
+        &mut self,
+        expr: &'a Expr,
+        math: &'a MathExpr,
+        needs_escape: &mut bool,
+    ) -> Result<Option<Val<'a>>> {
+        if let MathOperator::Sub | MathOperator::Div | MathOperator::Modulo = math.operator {
+            let n = self.eval_as_number(&expr.val)?;
+            return Ok(n.map(|n| Cow::Owned(Value::Number(n))));
         }
 
-        Ok(res)
+        let (lhs, rhs) = match (self.eval_operand(&math.lhs)?, self.eval_operand(&math.rhs)?) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return Ok(None),
+        };
+
+        let res = match (math.operator, &*lhs, &*rhs) {
+            (_, Value::Number(l), Value::Number(r)) => {
+                return Ok(self.compute_math(l, math.operator, r)?.map(|n| Cow::Owned(Value::Number(n))));
+            }
+            (MathOperator::Add, Value::Array(l), Value::Array(r)) => {
+                // Concatenating an array to itself doubles it, its size as JSON is checked before
+                // building it
+                if self.budget.limits_values() {
+                    let mut sink = io::sink();
+                    let mut size = self.budget.value_writer(&mut sink);
+                    to_writer(&mut size, l)
+                        .and_then(|_| to_writer(&mut size, r))
+                        .map_err(io::Error::from)?;
+                }
+                Value::Array(l.iter().chain(r).cloned().collect())
+            }
+            (MathOperator::Mul, Value::String(s), Value::Number(n))
+            | (MathOperator::Mul, Value::Number(n), Value::String(s)) => match n.as_u64() {
+                Some(times) => {
+                    // The size is checked before building the string
+                    self.budget.check_value_len(s.len().saturating_mul(times as usize))?;
+                    Value::String(s.repeat(times as usize))
+                }
+                None => {
+                    return Err(Error::msg(format!(
+                        "Tried to repeat a string {} times but it can only be repeated a positive integer number of times",
+                        n
+                    )))
+                }
+            },
+            (MathOperator::Add, l, r) => {
+                return Err(Error::msg(format!(
+                    "Tried to add {} and {} but `+` only works on 2 numbers or 2 arrays",
+                    type_name(l),
+                    type_name(r)
+                )))
+            }
+            (_, l, r) => {
+                return Err(Error::msg(format!(
+                    "Tried to multiply {} by {} but `*` only works on 2 numbers or a string and an integer",
+                    type_name(l),
+                    type_name(r)
+                )))
+            }
+        };
+
+        *needs_escape = true;
+        Ok(Some(Cow::Owned(res)))
+    }
+
+    // Math on 2 numbers, with decimals instead of floats if the decimal mode is enabled
+    fn compute_math(
+        &self,
+        l: &Number,
+        operator: MathOperator,
+        r: &Number,
+    ) -> Result<Option<Number>> {
+        #[cfg(feature = "decimal")]
+        if self.lysine.decimal_math && (l.is_f64() || r.is_f64() || operator == MathOperator::Div) {
+            return decimal::compute_math(l, operator, r);
+        }
+
+        compute_math(l, operator, r)
+    }
+
+    // The operands of math expressions and comparisons, `None` being NaN
+    fn eval_operand(&mut self, expr: &'a Expr) -> Result<Option<Val<'a>>> {
+        match expr.val {
+            ExprVal::Math(ref math) if expr.filters.is_empty() && !expr.negated => {
+                self.eval_math(expr, math, &mut false)
+            }
+            _ => self.safe_eval_expression(expr).map(Some),
+        }
+    }
+
+    // `object | length` uses `Object::len` instead of converting the whole object to a value
+    fn eval_object_len(&self, expr: &'a Expr) -> Option<usize> {
+        match (&expr.val, expr.filters.first()) {
+            (ExprVal::Ident(ident), Some(filter)) if filter.name == "length" => {
+                self.call_stack.lookup_object(ident)?.len()
+            }
+            _ => None,
+        }
+    }
+
+    // The items of a `{% for %}` loop on an object that can be iterated on, taken from
+    // `Object::iter` instead of converting the whole object to a value. No more items than
+    // the loop iterations left are taken from the iterator
+    fn eval_object_items(&self, for_loop: &'a Forloop) -> Result<Option<Value>> {
+        let container = &for_loop.container;
+        let object = match container.val {
+            ExprVal::Ident(ref ident)
+                if container.filters.is_empty() && !container.negated && for_loop.key.is_none() =>
+            {
+                match self.call_stack.lookup_object(ident) {
+                    Some(object) => object,
+                    None => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        if let Some(len) = object.len() {
+            self.budget.check_loop_len(len)?;
+        }
+        let items = match object.iter() {
+            Some(items) => items,
+            None => return Ok(None),
+        };
+        let items: Vec<_> = match self.budget.loop_iterations_left() {
+            Some(left) => items.take(left.saturating_add(1)).collect(),
+            None => items.collect(),
+        };
+        self.budget.check_loop_len(items.len())?;
+
+        Ok(Some(Value::Array(items)))
+    }
+
+    // Calls a method of an object of the context
+    fn eval_method_call(
+        &mut self,
+        method_call: &'a MethodCall,
+        needs_escape: &mut bool,
+    ) -> Result<Val<'a>> {
+        let name = format!("{}.{}", method_call.object, method_call.call.name);
+        let object = match self.call_stack.lookup_object(&method_call.object) {
+            Some(object) => object,
+            None => {
+                return Err(Error::msg(format!(
+                    "Tried to call the method `{}` but `{}` isn't an object of the context",
+                    name, method_call.object
+                )));
+            }
+        };
+
+        let err_wrap = |e| Error::call_function(&name, e);
+        let args = self.eval_call_args("Method", &method_call.call, err_wrap)?;
+        *needs_escape = true;
+
+        Ok(Cow::Owned(object.call_method(&method_call.call.name, &args).map_err(err_wrap)?))
+    }
+
+    // Evaluates an expression that is allowed to be missing, like the left side of `??`:
+    // an undefined variable or a missing attribute gives `None` instead of an error
+    fn eval_optional_expression(
+        &mut self,
+        expr: &'a Expr,
+        needs_escape: &mut bool,
+    ) -> Result<Option<Val<'a>>> {
+        let res = match expr.val {
+            ExprVal::Ident(ref ident) => match self.lookup_ident(ident) {
+                Ok(val) => {
+                    *needs_escape = ident != MAGICAL_DUMP_VAR;
+                    val
+                }
+                Err(_) => return Ok(None),
+            },
+            ExprVal::Access(ref access) => match self.eval_access(access, needs_escape, true)? {
+                Some(val) => val,
+                None => return Ok(None),
+            },
+            _ => return self.eval_unescaped_expression(expr, needs_escape).map(Some),
+        };
+
+        self.apply_filters(expr, res, needs_escape).map(Some)
+    }
+
+    // The right side is only evaluated if the left side is missing or null
+    fn eval_coalesce(
+        &mut self,
+        coalesce: &'a Coalesce,
+        needs_escape: &mut bool,
+    ) -> Result<Val<'a>> {
+        match self.eval_optional_expression(&coalesce.lhs, needs_escape)? {
+            Some(val) if !val.is_null() => Ok(val),
+            _ => {
+                *needs_escape = false;
+                self.eval_unescaped_expression(&coalesce.rhs, needs_escape)
+            }
+        }
+    }
+
+    // Evaluates `target.attr[index]...` on any expression. Accessing something with `?.` gives
+    // null instead of an error when the value before it is null or doesn't have that key.
+    // When `lenient` is set, the other missing values give `None` instead of an error.
+    fn eval_access(
+        &mut self,
+        access: &'a Access,
+        needs_escape: &mut bool,
+        lenient: bool,
+    ) -> Result<Option<Val<'a>>> {
+        let null = || Ok(Some(Cow::Owned(Value::Null)));
+
+        let target_is_optional = access.keys.first().is_some_and(|&(_, optional)| optional);
+        let target = if lenient || target_is_optional {
+            self.eval_optional_expression(&access.target, needs_escape)?
+        } else {
+            Some(self.eval_unescaped_expression(&access.target, needs_escape)?)
+        };
+        let mut res = match target {
+            Some(val) => val,
+            None if target_is_optional => return null(),
+            None => return Ok(None),
+        };
+
+        for (key, optional) in &access.keys {
+            if *optional && res.is_null() {
+                return null();
+            }
+
+            let (key, key_name) = match *key {
+                AccessKey::Attr(ref name) => {
+                    (Value::String(name.to_string()), format!(".{}", name))
+                }
+                AccessKey::Index(ref expr) => {
+                    let key = self.safe_eval_expression(expr)?.into_owned();
+                    let key_name = format!("[{}]", key);
+                    (key, key_name)
+                }
+            };
+
+            let found = match res {
+                Cow::Borrowed(val) => get_by_key(val, &key).map(Cow::Borrowed),
+                Cow::Owned(ref val) => get_by_key(val, &key).cloned().map(Cow::Owned),
+            };
+            res = match found {
+                Some(val) => val,
+                None if *optional => return null(),
+                None if lenient => return Ok(None),
+                None => {
+                    if let (Value::Array(arr), Value::Number(_)) = (&*res, &key) {
+                        return Err(Error::msg(format!(
+                            "Tried to access `{}` on an array of {} values: the index is out of bounds",
+                            key_name,
+                            arr.len()
+                        )));
+                    }
+                    return Err(Error::msg(format!(
+                        "Tried to access `{}` on a value that doesn't have it",
+                        key_name
+                    )));
+                }
+            };
+        }
+
+        *needs_escape = true;
+        Ok(Some(res))
     }
 
     // Render an expression and never escape its result
@@ -467,6 +1045,7 @@ impl<'a> Processor<'a> {
     }
 
     fn eval_test(&mut self, test: &'a Test) -> Result<bool> {
+        self.check_sandbox(|s| &s.tests, "test", &test.name)?;
         let tester_fn = self.lysine.get_tester(&test.name)?;
         let err_wrap = |e| Error::call_test(&test.name, e);
 
@@ -476,7 +1055,14 @@ impl<'a> Processor<'a> {
                 .push(self.safe_eval_expression(arg).map_err(err_wrap)?.clone().into_owned());
         }
 
-        let found = self.lookup_ident(&test.ident).map(|found| found.clone().into_owned()).ok();
+        // A missing variable is given to the test as `None`, eg for `is defined`, filters like
+        // `default` handle missing values themselves
+        let found = if test.expr.filters.is_empty() {
+            self.eval_optional_expression(&test.expr, &mut false)?
+        } else {
+            Some(self.safe_eval_expression(&test.expr)?)
+        };
+        let found = found.map(|found| found.into_owned());
 
         let result = tester_fn.test(found.as_ref(), &tester_args).map_err(err_wrap)?;
         if test.negated {
@@ -491,24 +1077,237 @@ impl<'a> Processor<'a> {
         function_call: &'a FunctionCall,
         needs_escape: &mut bool,
     ) -> Result<Val<'a>> {
+        // Macros imported by name shadow the global functions
+        if let Some((macro_template_name, macro_definition)) =
+            self.lookup_bound_macro(&function_call.name)
+        {
+            *needs_escape = false;
+            let val = self.render_to_limited_string(
+                || format!("macro {}", function_call.name),
+                |p, w| {
+                    p.eval_macro_with_args(macro_template_name, macro_definition, function_call, w)
+                },
+            )?;
+            return Ok(Cow::Owned(Value::String(val)));
+        }
+
+        self.check_sandbox(|s| &s.functions, "function", &function_call.name)?;
+
+        // Variables holding a macro reference can be called like functions
+        let macro_ref = match self.call_stack.lookup(&function_call.name) {
+            Some(val) => self.resolve_macro_ref(&val),
+            None => None,
+        };
+        if let Some(target) = macro_ref {
+            self.check_sandbox(|s| &s.templates, "template", target.template_name)?;
+            *needs_escape = false;
+            let val = self.render_to_limited_string(
+                || format!("macro {}", function_call.name),
+                |p, w| {
+                    p.eval_macro_with_args(
+                        target.template_name,
+                        target.definition,
+                        function_call,
+                        w,
+                    )
+                },
+            )?;
+            return Ok(Cow::Owned(Value::String(val)));
+        }
+
+        if self.lysine.is_renderer_function(&function_call.name) {
+            *needs_escape = false;
+            return if function_call.name == "block" {
+                self.eval_block_fn_call(function_call)
+            } else {
+                self.eval_include_fn_call(function_call)
+            };
+        }
+
+        let err_wrap = |e| Error::call_function(&function_call.name, e);
+
+        #[cfg(feature = "async")]
+        if let Some(async_fn) = self.lysine.async_functions.get(&function_call.name) {
+            *needs_escape = !async_fn.is_safe();
+            let args = self.eval_call_args("Function", function_call, err_wrap)?;
+            let key = AsyncCalls::key(&function_call.name, None, &args);
+            let val = self.call_async(key, || async_fn.call(args)).map_err(err_wrap)?;
+            return Ok(Cow::Owned(val));
+        }
+
         let lysine_fn = self.lysine.get_function(&function_call.name)?;
         *needs_escape = !lysine_fn.is_safe();
 
-        let err_wrap = |e| Error::call_function(&function_call.name, e);
+        let args = self.eval_call_args("Function", function_call, err_wrap)?;
+        if let Some(len) = lysine_fn.len_hint(&args) {
+            self.budget.check_loop_len(len)?;
+        }
 
-        let mut args = HashMap::with_capacity(function_call.args.len());
-        for (arg_name, expr) in &function_call.args {
+        Ok(Cow::Owned(lysine_fn.call(&args).map_err(err_wrap)?))
+    }
+
+    // Evaluates the arguments of a function or filter, which only takes keyword arguments
+    fn eval_call_args(
+        &mut self,
+        kind: &str,
+        fn_call: &'a FunctionCall,
+        err_wrap: impl Fn(Error) -> Error,
+    ) -> Result<HashMap<String, Value>> {
+        if !fn_call.positional_args.is_empty() {
+            return Err(Error::msg(format!(
+                "{} `{}` only takes keyword arguments",
+                kind, fn_call.name
+            )));
+        }
+
+        let mut args = HashMap::with_capacity(fn_call.args.len());
+        for (arg_name, expr) in &fn_call.args {
             args.insert(
                 arg_name.to_string(),
-                self.safe_eval_expression(expr).map_err(err_wrap)?.clone().into_owned(),
+                self.safe_eval_expression(expr).map_err(&err_wrap)?.clone().into_owned(),
             );
         }
 
-        Ok(Cow::Owned(lysine_fn.call(&args).map_err(err_wrap)?))
+        Ok(args)
     }
 
-    fn eval_macro_call(&mut self, macro_call: &'a MacroCall, write: &mut impl Write) -> Result<()> {
-        let active_template_name = if let Some(block) = self.blocks.last() {
+    // `block("name")` renders a block of the current template hierarchy another time, eg
+    // to show the title of a page in both `<title>` and `<h1>`
+    fn eval_block_fn_call(&mut self, function_call: &'a FunctionCall) -> Result<Val<'a>> {
+        let name_expr = match function_call.positional_args.first() {
+            Some(expr) => Some(expr),
+            None => function_call.args.get("name"),
+        };
+        let name = match name_expr {
+            Some(expr) => match *self.safe_eval_expression(expr)? {
+                Value::String(ref name) => name.to_string(),
+                ref val => {
+                    return Err(Error::msg(format!(
+                        "Function `block` received name={} but `name` can only be a string",
+                        val
+                    )));
+                }
+            },
+            None => return Err(Error::msg("Function `block` requires the name of a block")),
+        };
+
+        if self.blocks.iter().any(|&(block_name, _, _)| block_name == name) {
+            return Err(Error::msg(format!(
+                "Block `{}` can't be rendered with `block()` from inside itself",
+                name
+            )));
+        }
+
+        let block = self.find_block(self.call_stack.active_template(), &name)?;
+
+        let val = self.render_to_limited_string(
+            || format!("block {}", block.name),
+            |p, w| p.render_block(block, 0, w),
+        )?;
+        Ok(Cow::Owned(Value::String(val)))
+    }
+
+    // `include("name")` renders a template like `{% include %}` but returns its output so it can
+    // go through filters or be assigned. It also takes an array of names and `ignore_missing=true`
+    fn eval_include_fn_call(&mut self, function_call: &'a FunctionCall) -> Result<Val<'a>> {
+        let name_expr = match function_call.positional_args.first() {
+            Some(expr) => Some(expr),
+            None => function_call.args.get("name"),
+        };
+        let tpl_names = match name_expr {
+            Some(expr) => match *self.safe_eval_expression(expr)? {
+                Value::String(ref name) => vec![name.to_string()],
+                Value::Array(ref names) if names.iter().all(Value::is_string) => {
+                    names.iter().map(|name| name.as_str().unwrap().to_string()).collect()
+                }
+                ref val => {
+                    return Err(Error::msg(format!(
+                        "Function `include` received name={} but `name` can only be a string or an array of strings",
+                        val
+                    )));
+                }
+            },
+            None => {
+                return Err(Error::msg("Function `include` requires the name of a template"));
+            }
+        };
+        let ignore_missing = match function_call.args.get("ignore_missing") {
+            Some(expr) => match *self.safe_eval_expression(expr)? {
+                Value::Bool(b) => b,
+                ref val => {
+                    return Err(Error::msg(format!(
+                        "Function `include` received ignore_missing={} but `ignore_missing` can only be a boolean",
+                        val
+                    )));
+                }
+            },
+            None => false,
+        };
+
+        // The included template is escaped like it would be with `{% include %}`, even when
+        // the result is assigned with `set`
+        let should_escape = mem::replace(&mut self.should_escape, self.autoescape);
+        let res = self.render_to_limited_string(
+            || format!("include {}", tpl_names.join(", ")),
+            |p, w| p.render_include(&tpl_names, ignore_missing, w),
+        );
+        self.should_escape = should_escape;
+        Ok(Cow::Owned(Value::String(res?)))
+    }
+
+    // Renders a macro of the template with the given arguments, without going through a template
+    // calling it
+    pub fn render_macro(
+        &mut self,
+        macro_name: &'a str,
+        args: &'a HashMap<String, Value>,
+        write: &mut impl Write,
+    ) -> Result<()> {
+        let template_name = &self.template.name[..];
+        let (macro_template_name, macro_definition) =
+            self.macros.lookup_macro(template_name, "self", macro_name).map_err(|_| {
+                Error::msg(format!(
+                    "Macro `{}` not found in template `{}`",
+                    macro_name, template_name
+                ))
+            })?;
+        let call_args =
+            args.iter().map(|(name, value)| (&name[..], Cow::Borrowed(value))).collect();
+
+        let mut write = self.budget.output_writer(write);
+        let write = &mut write;
+        self.eval_macro("self", macro_template_name, macro_definition, vec![], call_args, write)
+            .map_err(|e| Error::chain(self.get_error_location(), e))
+    }
+
+    // Finds a block in a template or its parents, `render_block` then renders the definition
+    // of the block from the template at the bottom of the hierarchy
+    fn find_block(&self, template: &'a Template, name: &str) -> Result<&'a Block> {
+        // The template can be the hidden template of an embed, which isn't looked up by name
+        let parents =
+            template.parents.iter().filter_map(|name| self.lysine.get_template(name).ok());
+        let block = iter::once(template).chain(parents).find_map(|tpl| tpl.blocks.get(name));
+
+        match block {
+            Some(block) => Ok(block),
+            None => Err(Error::msg(format!(
+                "Block `{}` not found in template `{}` or its parents",
+                name, template.name
+            ))),
+        }
+    }
+
+    // Finds the macro `namespace::name` from the template we are currently rendering
+    fn lookup_macro(
+        &self,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> Result<(&'a str, &'a MacroDefinition)> {
+        // A macro body uses the namespaces of the template it is defined in
+        let scope_frame = self.call_stack.scope_frame();
+        let active_template_name = if scope_frame.kind == FrameType::Macro {
+            &scope_frame.active_template.name
+        } else if let Some(block) = self.blocks.last() {
             block.1
         } else if self.template.name != self.template_root.name {
             &self.template_root.name
@@ -516,41 +1315,258 @@ impl<'a> Processor<'a> {
             &self.call_stack.active_template().name
         };
 
-        let (macro_template_name, macro_definition) = self.macros.lookup_macro(
-            active_template_name,
-            &macro_call.namespace[..],
-            &macro_call.name[..],
-        )?;
+        self.macros.lookup_macro(active_template_name, namespace, name)
+    }
+
+    // Turns `namespace::name` into a value that can be passed around, the macro is looked up
+    // with the imports of the current template
+    fn eval_macro_ref(&mut self, macro_ref: &'a MacroRef) -> Result<Value> {
+        let (template_name, definition) =
+            self.lookup_macro(&macro_ref.namespace, &macro_ref.name)?;
+
+        let index = match self.macro_refs.iter().position(|r| ptr::eq(r.definition, definition)) {
+            Some(index) => index,
+            None => {
+                let namespace = &macro_ref.namespace[..];
+                self.macro_refs.push(MacroRefTarget { namespace, template_name, definition });
+                self.macro_refs.len() - 1
+            }
+        };
+
+        Ok(macro_ref_to_value(&self.macro_ref_key, index))
+    }
+
+    // Errors if the sandbox doesn't allow using the `kind` called `name`. The templates are
+    // checked when they are loaded, this catches what is only known when rendering
+    fn check_sandbox(
+        &self,
+        access: fn(&Sandbox) -> &sandbox::Access,
+        kind: &str,
+        name: &str,
+    ) -> Result<()> {
+        match self.lysine.sandbox {
+            Some(ref sandbox) if !access(sandbox).allows(name) => {
+                Err(sandbox::not_allowed(&self.call_stack.active_template().name, kind, name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Renders to a string like the output of a macro, erroring if the string goes over the
+    // size limit of the values
+    fn render_to_limited_string<C, F>(&mut self, context: C, render: F) -> Result<String>
+    where
+        C: FnOnce() -> String,
+        F: FnOnce(&mut Self, &mut LimitedWriter<Vec<u8>>) -> Result<()>,
+    {
+        render_to_string(context, |w| {
+            let mut w = self.budget.value_writer(w);
+            render(self, &mut w)
+        })
+    }
+
+    // Finds the macro a value created from a macro reference points to, if it is one. Only the
+    // references created by this render are resolved
+    fn resolve_macro_ref(&self, value: &Value) -> Option<MacroRefTarget<'a>> {
+        match value_to_macro_ref(value) {
+            Some((key, index)) if key == self.macro_ref_key => self.macro_refs.get(index).copied(),
+            _ => None,
+        }
+    }
+
+    fn eval_macro_call(&mut self, macro_call: &'a MacroCall, write: &mut impl Write) -> Result<()> {
+        let (macro_template_name, macro_definition) =
+            self.lookup_macro(&macro_call.namespace, &macro_call.name)?;
+        if macro_call.namespace != "self" {
+            self.check_sandbox(|s| &s.templates, "template", macro_template_name)?;
+        }
+
+        let (positional_args, args) =
+            self.eval_macro_args(&macro_call.positional_args, &macro_call.args)?;
+
+        self.eval_macro(
+            &macro_call.namespace,
+            macro_template_name,
+            macro_definition,
+            positional_args,
+            args,
+            write,
+        )
+    }
 
+    // Calls a macro that isn't namespaced: either imported by name or from a macro reference
+    fn eval_macro_with_args(
+        &mut self,
+        macro_template_name: &'a str,
+        macro_definition: &'a MacroDefinition,
+        function_call: &'a FunctionCall,
+        write: &mut impl Write,
+    ) -> Result<()> {
+        let (positional_args, args) =
+            self.eval_macro_args(&function_call.positional_args, &function_call.args)?;
+
+        self.eval_macro(
+            macro_template_name,
+            macro_template_name,
+            macro_definition,
+            positional_args,
+            args,
+            write,
+        )
+    }
+
+    // The call arguments are evaluated in the context of the caller
+    fn eval_macro_args(
+        &mut self,
+        positional_args: &'a [Expr],
+        args: &'a HashMap<String, Expr>,
+    ) -> Result<MacroArgs<'a>> {
+        let mut positional_values = Vec::with_capacity(positional_args.len());
+        for expr in positional_args {
+            positional_values.push(self.safe_eval_expression(expr)?);
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        for (arg_name, expr) in args {
+            values.push((&arg_name[..], self.safe_eval_expression(expr)?));
+        }
+
+        Ok((positional_values, values))
+    }
+
+    // Finds a macro imported with `{% from ... import ... %}` visible from where we are
+    // rendering: a macro body only sees its own imports and the ones of its template while
+    // blocks see their imports and the ones at the top-level of the templates
+    fn lookup_bound_macro(&self, name: &str) -> Option<(&'a str, &'a MacroDefinition)> {
+        let frame = self.call_stack.scope_frame();
+        let frame_template_name = &frame.active_template.name[..];
+
+        if frame.kind == FrameType::Macro {
+            return self
+                .macros
+                .lookup_bound_macro(
+                    frame_template_name,
+                    &MacroScope::Macro(frame.name.to_string()),
+                    name,
+                )
+                .or_else(|| {
+                    self.macros.lookup_bound_macro(frame_template_name, &MacroScope::Template, name)
+                });
+        }
+
+        for &(block_name, tpl_name, _) in self.blocks.iter().rev() {
+            let found = self.macros.lookup_bound_macro(
+                tpl_name,
+                &MacroScope::Block(block_name.to_string()),
+                name,
+            );
+            if found.is_some() {
+                return found;
+            }
+        }
+
+        let block_template_name = self.blocks.last().map(|block| block.1);
+        block_template_name
+            .into_iter()
+            .chain([frame_template_name, &self.template.name[..]])
+            .find_map(|tpl_name| {
+                self.macros.lookup_bound_macro(tpl_name, &MacroScope::Template, name)
+            })
+    }
+
+    fn eval_macro(
+        &mut self,
+        macro_namespace: &'a str,
+        macro_template_name: &'a str,
+        macro_definition: &'a MacroDefinition,
+        positional_args: Vec<Val<'a>>,
+        call_args: Vec<(&'a str, Val<'a>)>,
+        write: &mut impl Write,
+    ) -> Result<()> {
         let mut frame_context = FrameContext::with_capacity(macro_definition.args.len());
 
-        // First the default arguments
-        for (arg_name, default_value) in &macro_definition.args {
-            let value = match macro_call.args.get(arg_name) {
-                Some(val) => self.safe_eval_expression(val)?,
-                None => match *default_value {
-                    Some(ref val) => self.safe_eval_expression(val)?,
-                    None => {
+        let positional_args_count = positional_args.len();
+        let mut extra_positional_args = vec![];
+        for (i, value) in positional_args.into_iter().enumerate() {
+            match macro_definition.args.get(i) {
+                Some((arg_name, _)) => {
+                    frame_context.insert(arg_name.as_str(), value);
+                }
+                None => extra_positional_args.push(value.into_owned()),
+            }
+        }
+
+        if let Some(ref varargs) = macro_definition.varargs {
+            frame_context.insert(varargs.as_str(), Cow::Owned(Value::Array(extra_positional_args)));
+        } else if !extra_positional_args.is_empty() {
+            return Err(Error::msg(format!(
+                "Macro `{}` takes {} positional arguments but {} were given",
+                macro_definition.name,
+                macro_definition.args.len(),
+                positional_args_count
+            )));
+        }
+
+        let mut extra_kwargs = serde_json::Map::new();
+        for (arg_name, value) in call_args {
+            match macro_definition.args.iter().find(|(name, _)| name == arg_name) {
+                Some((name, _)) => {
+                    if frame_context.contains_key(name.as_str()) {
                         return Err(Error::msg(format!(
-                            "Macro `{}` is missing the argument `{}`",
-                            macro_call.name, arg_name
+                            "Macro `{}` received the argument `{}` both positionally and by name",
+                            macro_definition.name, arg_name
                         )));
                     }
-                },
-            };
-            frame_context.insert(arg_name, value);
+                    frame_context.insert(name.as_str(), value);
+                }
+                None if macro_definition.kwargs.is_some() => {
+                    extra_kwargs.insert(arg_name.to_string(), value.into_owned());
+                }
+                None => {
+                    return Err(Error::msg(format!(
+                        "Macro `{}` received an unknown argument `{}`",
+                        macro_definition.name, arg_name
+                    )));
+                }
+            }
+        }
+
+        if let Some(ref kwargs) = macro_definition.kwargs {
+            frame_context.insert(kwargs.as_str(), Cow::Owned(Value::Object(extra_kwargs)));
         }
 
+        let missing_args: Vec<_> = macro_definition
+            .args
+            .iter()
+            .filter(|(arg_name, _)| !frame_context.contains_key(arg_name.as_str()))
+            .collect();
+        if let Some((arg_name, _)) = missing_args.iter().find(|(_, default)| default.is_none()) {
+            return Err(Error::msg(format!(
+                "Macro `{}` is missing the argument `{}`",
+                macro_definition.name, arg_name
+            )));
+        }
+
+        self.budget.enter_call()?;
         self.call_stack.push_macro_frame(
-            &macro_call.namespace,
-            &macro_call.name,
+            macro_namespace,
+            &macro_definition.name,
             frame_context,
             self.lysine.get_template(macro_template_name)?,
         );
 
+        // The defaults are evaluated inside the macro so they can refer to the previous arguments
+        for (arg_name, default_value) in missing_args {
+            if let Some(ref expr) = default_value {
+                let value = self.safe_eval_expression(expr)?;
+                self.call_stack.current_frame_mut().insert(arg_name, value);
+            }
+        }
+
         self.render_body(&macro_definition.body, write)?;
 
         self.call_stack.pop();
+        self.budget.exit_call();
 
         Ok(())
     }
@@ -561,20 +1577,92 @@ impl<'a> Processor<'a> {
         fn_call: &'a FunctionCall,
         needs_escape: &mut bool,
     ) -> Result<Val<'a>> {
+        self.check_sandbox(|s| &s.filters, "filter", &fn_call.name)?;
+        let err_wrap = |e| Error::call_filter(&fn_call.name, e);
+
+        #[cfg(feature = "async")]
+        if let Some(async_filter) = self.lysine.async_filters.get(&fn_call.name) {
+            *needs_escape = !async_filter.is_safe();
+            let args = self.eval_call_args("Filter", fn_call, err_wrap)?;
+            let key = AsyncCalls::key(&fn_call.name, Some(value), &args);
+            let value = value.clone().into_owned();
+            let val =
+                self.call_async(key, || async_filter.filter(value, args)).map_err(err_wrap)?;
+            return Ok(Cow::Owned(val));
+        }
+
         let filter_fn = self.lysine.get_filter(&fn_call.name)?;
+
+        // Rendering each item with a macro needs the processor
+        if filter_fn.renders_macro() && fn_call.args.contains_key("macro") {
+            *needs_escape = false;
+            return self.eval_map_macro(value, fn_call);
+        }
+
         *needs_escape = !filter_fn.is_safe();
 
+        let args = self.eval_call_args("Filter", fn_call, err_wrap)?;
+
+        Ok(Cow::Owned(filter_fn.filter(value, &args).map_err(err_wrap)?))
+    }
+
+    // Calls the macro given in the `macro` argument of a filter like `map` with each item of the
+    // array as first argument, the other arguments of the filter are passed to the macro as well
+    fn eval_map_macro(&mut self, value: &Val<'a>, fn_call: &'a FunctionCall) -> Result<Val<'a>> {
         let err_wrap = |e| Error::call_filter(&fn_call.name, e);
 
-        let mut args = HashMap::with_capacity(fn_call.args.len());
-        for (arg_name, expr) in &fn_call.args {
-            args.insert(
-                arg_name.to_string(),
-                self.safe_eval_expression(expr).map_err(err_wrap)?.clone().into_owned(),
-            );
+        let macro_val = self.safe_eval_expression(&fn_call.args["macro"]).map_err(err_wrap)?;
+        let (macro_template_name, macro_definition) = match self.resolve_macro_ref(&macro_val) {
+            Some(target) => {
+                self.check_sandbox(|s| &s.templates, "template", target.template_name)?;
+                (target.template_name, target.definition)
+            }
+            None => {
+                return Err(err_wrap(Error::msg(format!(
+                    "The `macro` argument of the `{}` filter has to be a macro, like `macros::my_macro`",
+                    fn_call.name
+                ))))
+            }
+        };
+
+        let items = match **value {
+            Value::Array(ref items) => items,
+            _ => {
+                return Err(err_wrap(Error::msg(format!(
+                    "Filter `{}` was called on an incorrect value: got `{}` but expected an array",
+                    fn_call.name, value
+                ))))
+            }
+        };
+
+        let mut rendered = Vec::with_capacity(items.len());
+        for item in items {
+            let mut args = Vec::with_capacity(fn_call.args.len() - 1);
+            for (arg_name, expr) in &fn_call.args {
+                if arg_name != "macro" {
+                    args.push((&arg_name[..], self.safe_eval_expression(expr).map_err(err_wrap)?));
+                }
+            }
+
+            let val = self
+                .render_to_limited_string(
+                    || format!("macro {}", macro_definition.name),
+                    |p, w| {
+                        p.eval_macro(
+                            macro_template_name,
+                            macro_template_name,
+                            macro_definition,
+                            vec![Cow::Owned(item.clone())],
+                            args,
+                            w,
+                        )
+                    },
+                )
+                .map_err(err_wrap)?;
+            rendered.push(Value::String(val));
         }
 
-        Ok(Cow::Owned(filter_fn.filter(value, &args).map_err(err_wrap)?))
+        Ok(Cow::Owned(Value::Array(rendered)))
     }
 
     fn eval_as_bool(&mut self, bool_expr: &'a Expr) -> Result<bool> {
@@ -587,53 +1675,47 @@ impl<'a> Processor<'a> {
                     | LogicOperator::Gte
                     | LogicOperator::Lt
                     | LogicOperator::Lte => {
-                        let l = self.eval_expr_as_number(lhs)?;
-                        let r = self.eval_expr_as_number(rhs)?;
-                        let (ll, rr) = match (l, r) {
-                            (Some(nl), Some(nr)) => (nl, nr),
+                        let l = self.eval_operand(lhs)?;
+                        let r = self.eval_operand(rhs)?;
+                        let ordering = match (l, r) {
+                            (Some(l), Some(r)) => compare_values(&l, &r)?,
                             _ => return Err(Error::msg("Comparison to NaN")),
                         };
 
                         match *operator {
-                            LogicOperator::Gte => ll.as_f64().unwrap() >= rr.as_f64().unwrap(),
-                            LogicOperator::Gt => ll.as_f64().unwrap() > rr.as_f64().unwrap(),
-                            LogicOperator::Lte => ll.as_f64().unwrap() <= rr.as_f64().unwrap(),
-                            LogicOperator::Lt => ll.as_f64().unwrap() < rr.as_f64().unwrap(),
+                            LogicOperator::Gte => ordering != Ordering::Less,
+                            LogicOperator::Gt => ordering == Ordering::Greater,
+                            LogicOperator::Lte => ordering != Ordering::Greater,
+                            LogicOperator::Lt => ordering == Ordering::Less,
                             _ => unreachable!(),
                         }
                     }
                     LogicOperator::Eq | LogicOperator::NotEq => {
-                        let mut lhs_val = self.eval_expression(lhs)?;
-                        let mut rhs_val = self.eval_expression(rhs)?;
-
-                        // Monomorphize number vals.
-                        if lhs_val.is_number() || rhs_val.is_number() {
-                            // We're not implementing JS so can't compare things of different types
-                            if !lhs_val.is_number() || !rhs_val.is_number() {
-                                return Ok(false);
-                            }
+                        let lhs_val = self.eval_expression(lhs)?;
+                        let rhs_val = self.eval_expression(rhs)?;
 
-                            lhs_val = Cow::Owned(Value::Number(
-                                Number::from_f64(lhs_val.as_f64().unwrap()).unwrap(),
-                            ));
-                            rhs_val = Cow::Owned(Value::Number(
-                                Number::from_f64(rhs_val.as_f64().unwrap()).unwrap(),
-                            ));
-                        }
+                        // `1 == 1.0` but integers are compared exactly
+                        let equal = match (&*lhs_val, &*rhs_val) {
+                            (Value::Number(l), Value::Number(r)) => {
+                                compare_numbers(l, r) == Ordering::Equal
+                            }
+                            (l, r) => l == r,
+                        };
 
                         match *operator {
-                            LogicOperator::Eq => *lhs_val == *rhs_val,
-                            LogicOperator::NotEq => *lhs_val != *rhs_val,
+                            LogicOperator::Eq => equal,
+                            LogicOperator::NotEq => !equal,
                             _ => unreachable!(),
                         }
                     }
                 }
             }
-            ExprVal::Ident(_) => {
-                let mut res = self
-                    .eval_expression(bool_expr)
-                    .unwrap_or(Cow::Owned(Value::Bool(false)))
-                    .is_truthy();
+            ExprVal::Ident(_) | ExprVal::Access(_) => {
+                let mut res = match self.eval_expression(bool_expr) {
+                    Ok(val) => val.is_truthy(),
+                    Err(e) if e.is_async_call_pending() => return Err(e),
+                    Err(_) => false,
+                };
                 if bool_expr.negated {
                     res = !res;
                 }
@@ -665,12 +1747,27 @@ impl<'a> Processor<'a> {
                 let res = self.eval_expression(bool_expr)?;
                 !res.as_str().unwrap().is_empty()
             }
+            ExprVal::Group(_)
+            | ExprVal::Array(_)
+            | ExprVal::Coalesce(_)
+            | ExprVal::MethodCall(_) => {
+                // Negated expressions are already evaluated as a bool
+                let res = self.eval_expression(bool_expr)?.is_truthy();
+                if bool_expr.negated {
+                    !res
+                } else {
+                    res
+                }
+            }
             ExprVal::MacroCall(ref macro_call) => {
                 let mut buf = Vec::new();
                 self.eval_macro_call(macro_call, &mut buf)?;
                 !buf.is_empty()
             }
-            _ => unreachable!("unimplemented logic operation for {:?}", bool_expr),
+            ExprVal::MacroRef(ref macro_ref) => {
+                self.lookup_macro(&macro_ref.namespace, &macro_ref.name)?;
+                true
+            }
         };
 
         if bool_expr.negated {
@@ -700,12 +1797,8 @@ impl<'a> Processor<'a> {
         let result = match *expr {
             ExprVal::Ident(ref ident) => {
                 let v = &*self.lookup_ident(ident)?;
-                if v.is_i64() {
-                    Some(Number::from(v.as_i64().unwrap()))
-                } else if v.is_u64() {
-                    Some(Number::from(v.as_u64().unwrap()))
-                } else if v.is_f64() {
-                    Some(Number::from_f64(v.as_f64().unwrap()).unwrap())
+                if let Some(n) = v.as_number() {
+                    Some(n.clone())
                 } else {
                     return Err(Error::msg(format!(
                         "Variable `{}` was used in a math operation but is not a number",
@@ -722,156 +1815,12 @@ impl<'a> Processor<'a> {
                     _ => return Ok(None),
                 };
 
-                match *operator {
-                    MathOperator::Mul => {
-                        if l.is_i64() && r.is_i64() {
-                            let ll = l.as_i64().unwrap();
-                            let rr = r.as_i64().unwrap();
-                            let res = match ll.checked_mul(rr) {
-                                Some(s) => s,
-                                None => {
-                                    return Err(Error::msg(format!(
-                                        "{} x {} results in an out of bounds i64",
-                                        ll, rr
-                                    )));
-                                }
-                            };
-
-                            Some(Number::from(res))
-                        } else if l.is_u64() && r.is_u64() {
-                            let ll = l.as_u64().unwrap();
-                            let rr = r.as_u64().unwrap();
-                            let res = match ll.checked_mul(rr) {
-                                Some(s) => s,
-                                None => {
-                                    return Err(Error::msg(format!(
-                                        "{} x {} results in an out of bounds u64",
-                                        ll, rr
-                                    )));
-                                }
-                            };
-                            Some(Number::from(res))
-                        } else {
-                            let ll = l.as_f64().unwrap();
-                            let rr = r.as_f64().unwrap();
-                            Number::from_f64(ll * rr)
-                        }
-                    }
-                    MathOperator::Div => {
-                        let ll = l.as_f64().unwrap();
-                        let rr = r.as_f64().unwrap();
-                        let res = ll / rr;
-                        if res.is_nan() {
-                            None
-                        } else if res.round() == res && res.is_finite() {
-                            Some(Number::from(res as i64))
-                        } else {
-                            Number::from_f64(res)
-                        }
-                    }
-                    MathOperator::Add => {
-                        if l.is_i64() && r.is_i64() {
-                            let ll = l.as_i64().unwrap();
-                            let rr = r.as_i64().unwrap();
-                            let res = match ll.checked_add(rr) {
-                                Some(s) => s,
-                                None => {
-                                    return Err(Error::msg(format!(
-                                        "{} + {} results in an out of bounds i64",
-                                        ll, rr
-                                    )));
-                                }
-                            };
-                            Some(Number::from(res))
-                        } else if l.is_u64() && r.is_u64() {
-                            let ll = l.as_u64().unwrap();
-                            let rr = r.as_u64().unwrap();
-                            let res = match ll.checked_add(rr) {
-                                Some(s) => s,
-                                None => {
-                                    return Err(Error::msg(format!(
-                                        "{} + {} results in an out of bounds u64",
-                                        ll, rr
-                                    )));
-                                }
-                            };
-                            Some(Number::from(res))
-                        } else {
-                            let ll = l.as_f64().unwrap();
-                            let rr = r.as_f64().unwrap();
-                            Some(Number::from_f64(ll + rr).unwrap())
-                        }
-                    }
-                    MathOperator::Sub => {
-                        if l.is_i64() && r.is_i64() {
-                            let ll = l.as_i64().unwrap();
-                            let rr = r.as_i64().unwrap();
-                            let res = match ll.checked_sub(rr) {
-                                Some(s) => s,
-                                None => {
-                                    return Err(Error::msg(format!(
-                                        "{} - {} results in an out of bounds i64",
-                                        ll, rr
-                                    )));
-                                }
-                            };
-                            Some(Number::from(res))
-                        } else if l.is_u64() && r.is_u64() {
-                            let ll = l.as_u64().unwrap();
-                            let rr = r.as_u64().unwrap();
-                            let res = match ll.checked_sub(rr) {
-                                Some(s) => s,
-                                None => {
-                                    return Err(Error::msg(format!(
-                                        "{} - {} results in an out of bounds u64",
-                                        ll, rr
-                                    )));
-                                }
-                            };
-                            Some(Number::from(res))
-                        } else {
-                            let ll = l.as_f64().unwrap();
-                            let rr = r.as_f64().unwrap();
-                            Some(Number::from_f64(ll - rr).unwrap())
-                        }
-                    }
-                    MathOperator::Modulo => {
-                        if l.is_i64() && r.is_i64() {
-                            let ll = l.as_i64().unwrap();
-                            let rr = r.as_i64().unwrap();
-                            if rr == 0 {
-                                return Err(Error::msg(format!(
-                                    "Tried to do a modulo by zero: {:?}/{:?}",
-                                    lhs, rhs
-                                )));
-                            }
-                            Some(Number::from(ll % rr))
-                        } else if l.is_u64() && r.is_u64() {
-                            let ll = l.as_u64().unwrap();
-                            let rr = r.as_u64().unwrap();
-                            if rr == 0 {
-                                return Err(Error::msg(format!(
-                                    "Tried to do a modulo by zero: {:?}/{:?}",
-                                    lhs, rhs
-                                )));
-                            }
-                            Some(Number::from(ll % rr))
-                        } else {
-                            let ll = l.as_f64().unwrap();
-                            let rr = r.as_f64().unwrap();
-                            Number::from_f64(ll % rr)
-                        }
-                    }
-                }
+                self.compute_math(&l, *operator, &r)?
             }
             ExprVal::FunctionCall(ref fn_call) => {
                 let v = self.eval_lysine_fn_call(fn_call, &mut false)?;
-                if v.is_i64() {
-                    Some(Number::from(v.as_i64().unwrap()))
-                } else if v.is_u64() {
-                    Some(Number::from(v.as_u64().unwrap()))
-                } else if v.is_f64() {
-                    Some(Number::from_f64(v.as_f64().unwrap()).unwrap())
+                if let Some(n) = v.as_number() {
+                    Some(n.clone())
                 } else {
                     return Err(Error::msg(format!(
                         "Function `{}` was used in a math operation but is not returning a number",
@@ -894,7 +1843,39 @@ impl<'a> Processor<'a> {
             ExprVal::Test(ref test) => {
                 return Err(Error::msg(format!("Tried to do math with a test: {}", test.name)));
             }
-            _ => unreachable!("unimplemented math expression for {:?}", expr),
+            ExprVal::MacroRef(ref macro_ref) => {
+                return Err(Error::msg(format!(
+                    "Tried to do math with a macro: {}::{}",
+                    macro_ref.namespace, macro_ref.name
+                )));
+            }
+            ExprVal::Access(ref access) => {
+                let v = self.eval_access(access, &mut false, false)?.unwrap();
+                Some(value_as_number(&v)?)
+            }
+            ExprVal::Coalesce(ref coalesce) => {
+                let v = self.eval_coalesce(coalesce, &mut false)?;
+                Some(value_as_number(&v)?)
+            }
+            ExprVal::MethodCall(ref method_call) => {
+                let v = self.eval_method_call(method_call, &mut false)?;
+                Some(value_as_number(&v)?)
+            }
+            ExprVal::Group(ref inner) => return self.eval_expr_as_number(inner),
+            ExprVal::Array(_) => {
+                return Err(Error::msg(
+                    "Tried to do math with an array: only `+` can be used to concatenate 2 arrays",
+                ));
+            }
+            ExprVal::Logic(_) | ExprVal::In(_) => {
+                return Err(Error::msg("Tried to do math with a boolean expression"));
+            }
+            ExprVal::MacroCall(ref macro_call) => {
+                return Err(Error::msg(format!(
+                    "Tried to do math with a macro call: {}::{}",
+                    macro_call.namespace, macro_call.name
+                )));
+            }
         };
 
         Ok(result)
@@ -920,12 +1901,6 @@ impl<'a> Processor<'a> {
 
                 self.render_body(body, write)?;
                 self.blocks.pop();
-
-                // Can't go any higher for that block anymore?
-                if next_level >= self.template.parents.len() {
-                    // then remove it from the stack, we're done with it
-                    self.blocks.pop();
-                }
                 return Ok(());
             } else {
                 next_level += 1;
@@ -935,6 +1910,119 @@ impl<'a> Processor<'a> {
         Err(Error::msg("Tried to use super() in the top level block"))
     }
 
+    // An included template extending another one is rendered like a top-level template: from the
+    // AST of its root template and with its own blocks stack so `super()` and the blocks lookups
+    // don't see the blocks of the template including it
+    fn render_included_hierarchy(
+        &mut self,
+        template: &'a Template,
+        write: &mut impl Write,
+    ) -> Result<()> {
+        let template_root = match template.parents.last() {
+            Some(parent) => self.lysine.get_template(parent)?,
+            None => template,
+        };
+
+        let outer_template = mem::replace(&mut self.template, template);
+        let outer_template_root = mem::replace(&mut self.template_root, template_root);
+        let outer_blocks = mem::take(&mut self.blocks);
+
+        let res = self.render_body(&template_root.ast, write);
+
+        self.template = outer_template;
+        self.template_root = outer_template_root;
+        self.blocks = outer_blocks;
+
+        res
+    }
+
+    // Renders the first template of `tpl_names` that exists, erroring if none of them do unless
+    // `ignore_missing` is set
+    fn render_include(
+        &mut self,
+        tpl_names: &[String],
+        ignore_missing: bool,
+        write: &mut impl Write,
+    ) -> Result<()> {
+        let template = match tpl_names.iter().find_map(|name| self.lysine.get_template(name).ok()) {
+            Some(template) => template,
+            None if ignore_missing => return Ok(()),
+            None => {
+                return Err(Error::template_not_found(["[", &tpl_names.join(", "), "]"].join("")));
+            }
+        };
+        self.check_sandbox(|s| &s.templates, "template", &template.name)?;
+
+        self.macros.add_macros_from_template(self.lysine, template)?;
+        self.budget.enter_call()?;
+        self.call_stack.push_include_frame(&template.name, template);
+        let res = if template.parents.is_empty() {
+            self.render_body(&template.ast, write)
+        } else {
+            self.render_included_hierarchy(template, write)
+        };
+        self.call_stack.pop();
+        self.budget.exit_call();
+
+        res
+    }
+
+    // The value of an identifier that isn't in the context depending on the `UndefinedBehavior`:
+    // either the lookup error or null, which renders as nothing and is falsy
+    fn undefined_value(&self, ident: &str, err: Error) -> Result<Val<'a>> {
+        let is_undefined = match self.undefined_behavior {
+            UndefinedBehavior::Strict => false,
+            // Only the last part of the path can be missing, `a.b` is an error if `a` is missing
+            UndefinedBehavior::Lenient => {
+                let parent_end =
+                    if ident.ends_with(']') { ident.rfind('[') } else { ident.rfind('.') };
+                match parent_end {
+                    Some(end) => self.lookup_ident(&ident[..end]).is_ok(),
+                    None => true,
+                }
+            }
+            UndefinedBehavior::Chainable | UndefinedBehavior::Debug => true,
+        };
+
+        if is_undefined {
+            Ok(Cow::Owned(Value::Null))
+        } else {
+            Err(err)
+        }
+    }
+
+    // Renders a `{{ }}` block and returns the value it rendered, `None` if what was written
+    // isn't a value. In debug mode, an undefined variable is rendered as a marker with its name
+    fn render_variable_block(
+        &mut self,
+        expr: &'a Expr,
+        write: &mut impl Write,
+    ) -> Result<Option<Val<'a>>> {
+        if let ExprVal::Ident(ref ident) = expr.val {
+            if self.undefined_behavior == UndefinedBehavior::Debug
+                && !expr.negated
+                && !expr.has_default_filter()
+                && self.lookup_ident(ident).is_err()
+            {
+                write!(write, "{{{{ missing: {} }}}}", ident)?;
+                return Ok(None);
+            }
+        }
+
+        let value = self.eval_expression(expr)?;
+        match self.resolve_macro_ref(&value) {
+            // A macro reference renders as the name it was referenced with
+            Some(target) => {
+                write!(write, "{}::{}", target.namespace, target.definition.name)?;
+                Ok(None)
+            }
+            None => {
+                value.render(write)?;
+                Ok(Some(value))
+            }
+        }
+    }
+
     // Looks up identifier and returns its value
     fn lookup_ident(&self, key: &str) -> Result<Val<'a>> {
         // Magical variable that just dumps the context
@@ -954,16 +2042,37 @@ impl<'a> Processor<'a> {
     // Process the given node, appending the string result to the buffer
     // if it is possible
     fn render_node(&mut self, node: &'a Node, write: &mut impl Write) -> Result<()> {
+        let res = self.render_node_content(node, write);
+
+        // A pass of an async render that is waiting for calls goes on after the nodes failing
+        // without their results to find the other calls, its output is thrown away
+        #[cfg(feature = "async")]
+        if let Err(ref e) = res {
+            if self.async_calls.as_ref().is_some_and(|calls| calls.is_waiting())
+                && !e.is_limit_exceeded()
+            {
+                return Ok(());
+            }
+        }
+
+        res
+    }
+
+    fn render_node_content(&mut self, node: &'a Node, write: &mut impl Write) -> Result<()> {
+        self.budget.consume_fuel()?;
+
         match *node {
             // Comments are ignored when rendering
             Node::Comment(_, _) => (),
             Node::Text(ref s) | Node::Raw(_, ref s, _) => write!(write, "{}", s)?,
-            Node::VariableBlock(_, ref expr) => self.eval_expression(expr)?.render(write)?,
+            Node::VariableBlock(_, ref expr) => {
+                self.render_variable_block(expr, write)?;
+            }
             Node::Set(_, ref set) => self.eval_set(set)?,
             Node::FilterSection(_, FilterSection { ref filter, ref body }, _) => {
-                let body = render_to_string(
+                let body = self.render_to_limited_string(
                     || format!("filter {}", filter.name),
-                    |w| self.render_body(body, w),
+                    |p, w| p.render_body(body, w),
                 )?;
                 // the safe filter doesn't actually exist
                 if filter.name == "safe" {
@@ -974,9 +2083,10 @@ impl<'a> Processor<'a> {
                 }
             }
             // Macros have been imported at the beginning
-            Node::ImportMacro(_, _, _) => (),
+            Node::ImportMacro(_, _, _) | Node::FromImport(_, _, _) => (),
             Node::If(ref if_node, _) => self.render_if_node(if_node, write)?,
             Node::Forloop(_, ref forloop, _) => self.render_for_loop(forloop, write)?,
+            Node::Whileloop(_, ref whileloop, _) => self.render_while_loop(whileloop, write)?,
             Node::Break(_) => {
                 self.call_stack.break_for_loop()?;
             }
@@ -986,32 +2096,24 @@ impl<'a> Processor<'a> {
             Node::Block(_, ref block, _) => self.render_block(block, 0, write)?,
             Node::Super => self.do_super(write)?,
             Node::Include(_, ref tpl_names, ignore_missing) => {
-                let mut found = false;
-                for tpl_name in tpl_names {
-                    let template = self.lysine.get_template(tpl_name);
-                    if template.is_err() {
-                        continue;
-                    }
-                    let template = template.unwrap();
-                    self.macros.add_macros_from_template(self.lysine, template)?;
-                    self.call_stack.push_include_frame(tpl_name, template);
-                    self.render_body(&template.ast, write)?;
-                    self.call_stack.pop();
-                    found = true;
-                    break;
-                }
-                if !found && !ignore_missing {
-                    return Err(Error::template_not_found(
-                        ["[", &tpl_names.join(", "), "]"].join(""),
-                    ));
-                }
+                self.render_include(tpl_names, ignore_missing, write)?
             }
-            Node::Extends(_, ref name) => {
-                return Err(Error::msg(format!(
-                    "Inheritance in included templates is currently not supported: extended `{}`",
-                    name
-                )));
+            // The blocks of an embed live in a hidden template extending the embedded one
+            Node::Embed(_, ref embed, _) => {
+                self.check_sandbox(|s| &s.templates, "template", &embed.template)?;
+                let template = self.lysine.get_embed(&embed.name)?;
+                self.macros.add_macros_from_template(self.lysine, template)?;
+                self.budget.enter_call()?;
+                self.call_stack.push_include_frame(&embed.name, template);
+                self.render_included_hierarchy(template, write)?;
+                self.call_stack.pop();
+                self.budget.exit_call();
             }
+            // The inheritance chain is resolved before rendering, we only ever render the
+            // AST of the root template
+            Node::Extends(_, _) => (),
+            // The requirements are checked against the context before rendering
+            Node::Requires(_, _) => (),
             // Macro definitions are ignored when rendering
             Node::MacroDefinition(_, _, _) => (),
         };
@@ -1056,11 +2158,57 @@ impl<'a> Processor<'a> {
 
     // Entry point for the rendering
     pub fn render(&mut self, write: &mut impl Write) -> Result<()> {
+        let mut write = self.budget.output_writer(write);
         for node in &self.template_root.ast {
-            self.render_node(node, write)
+            self.render_node(node, &mut write)
                 .map_err(|e| Error::chain(self.get_error_location(), e))?;
         }
 
         Ok(())
     }
+
+    // Renders the template to a value: when its output is a single `{{ }}` with only whitespace
+    // around it, the value of the expression is returned with its type. Otherwise, it is the
+    // rendered string
+    pub fn render_value(&mut self) -> Result<Value> {
+        let mut buffer = Vec::new();
+        let mut output = self.budget.output_writer(&mut buffer);
+        let mut values = Vec::new();
+        let mut only_values = true;
+
+        for node in &self.template_root.ast {
+            let start = output.get_ref().len();
+            let res = match *node {
+                Node::VariableBlock(_, ref expr) => {
+                    self.render_variable_block(expr, &mut output).map(|value| match value {
+                        Some(value) => values.push(value.into_owned()),
+                        None => only_values = false,
+                    })
+                }
+                _ => self.render_node(node, &mut output),
+            };
+            res.map_err(|e| Error::chain(self.get_error_location(), e))?;
+
+            if !matches!(node, Node::VariableBlock(..)) {
+                only_values &= output.get_ref()[start..].iter().all(u8::is_ascii_whitespace);
+            }
+        }
+
+        match values.pop() {
+            Some(value) if only_values && values.is_empty() => Ok(value),
+            _ => Ok(Value::String(buffer_to_string(
+                || "converting rendered buffer to string".to_string(),
+                buffer,
+            )?)),
+        }
+    }
+
+    // Only renders the given block of the template, as it would be rendered in the whole page.
+    // What is outside of the block isn't rendered so a `{% set %}` done outside of it isn't visible
+    pub fn render_single_block(&mut self, block_name: &str, write: &mut impl Write) -> Result<()> {
+        let block = self.find_block(self.template, block_name)?;
+        let mut write = self.budget.output_writer(write);
+        self.render_block(block, 0, &mut write)
+            .map_err(|e| Error::chain(self.get_error_location(), e))
+    }
 }
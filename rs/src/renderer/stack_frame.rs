@@ -4,7 +4,7 @@ use std::collections::HashMap;
 use serde_json::Value;
 
 use crate::context::dotted_pointer;
-use crate::renderer::for_loop::ForLoop;
+use crate::renderer::for_loop::{ForLoop, ForLoopState};
 use crate::template::Template;
 
 pub type Val<'a> = Cow<'a, Value>;
@@ -26,7 +26,7 @@ pub enum FrameType {
     Origin,
     // New frame for macro call
     Macro,
-    // New frame for for loop
+    // New frame for a for or while loop
     ForLoop,
     // Include template
     Include,
@@ -49,6 +49,8 @@ pub struct StackFrame<'a> {
     pub active_template: &'a Template,
     // `ForLoop` if frame is for a for loop
     pub for_loop: Option<ForLoop<'a>>,
+    // The state of the loop if frame is for a while loop
+    pub while_loop: Option<ForLoopState>,
     // Macro namespace if MacroFrame
     pub macro_namespace: Option<&'a str>,
 }
@@ -61,6 +63,7 @@ impl<'a> StackFrame<'a> {
             context: FrameContext::new(),
             active_template: tpl,
             for_loop: None,
+            while_loop: None,
             macro_namespace: None,
         }
     }
@@ -72,6 +75,21 @@ impl<'a> StackFrame<'a> {
             context: FrameContext::new(),
             active_template: tpl,
             for_loop: Some(for_loop),
+            while_loop: None,
+            macro_namespace: None,
+        }
+    }
+
+    // The context of a while loop frame is kept between iterations so the condition sees
+    // the variables set in the body
+    pub fn new_while_loop(name: &'a str, tpl: &'a Template) -> Self {
+        StackFrame {
+            kind: FrameType::ForLoop,
+            name,
+            context: FrameContext::new(),
+            active_template: tpl,
+            for_loop: None,
+            while_loop: Some(ForLoopState::Normal),
             macro_namespace: None,
         }
     }
@@ -88,6 +106,7 @@ impl<'a> StackFrame<'a> {
             context,
             active_template: tpl,
             for_loop: None,
+            while_loop: None,
             macro_namespace: Some(macro_namespace),
         }
     }
@@ -99,10 +118,26 @@ impl<'a> StackFrame<'a> {
             context: FrameContext::new(),
             active_template: tpl,
             for_loop: None,
+            while_loop: None,
             macro_namespace: None,
         }
     }
 
+    // The state of the for or while loop of the frame, if it is a loop frame
+    pub fn loop_state(&self) -> Option<ForLoopState> {
+        match self.for_loop {
+            Some(ref for_loop) => Some(for_loop.state),
+            None => self.while_loop,
+        }
+    }
+
+    pub fn loop_state_mut(&mut self) -> Option<&mut ForLoopState> {
+        match self.for_loop {
+            Some(ref mut for_loop) => Some(&mut for_loop.state),
+            None => self.while_loop.as_mut(),
+        }
+    }
+
     // Finds a value in the stack frame.
     // Looks first in `frame_context`, then compares to for_loop key_name and value_name.
     pub fn find_value(&self, key: &str) -> Option<Val<'a>> {
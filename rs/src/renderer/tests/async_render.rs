@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::context::Context;
+use crate::errors::{ErrorKind, Result};
+use crate::lysine::{Lysine, RenderLimits};
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+}
+
+fn lysine_with_async_callables(calls: Arc<AtomicUsize>) -> Lysine {
+    let mut lysine = Lysine::default();
+    lysine.register_async_function("get_user", move |args: HashMap<String, Value>| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        async move {
+            match args.get("id").and_then(Value::as_u64) {
+                Some(id) => Ok(json!({"id": id, "name": format!("user{}", id)})),
+                None => Err("`id` is required".into()),
+            }
+        }
+    });
+    lysine.register_async_filter("shout", |value: Value, _: HashMap<String, Value>| async move {
+        Ok(Value::String(format!("{}!", value.as_str().unwrap_or_default().to_uppercase())))
+    });
+    lysine
+}
+
+#[test]
+fn render_async_awaits_functions_and_filters() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut lysine = lysine_with_async_callables(calls.clone());
+    lysine
+        .add_raw_template(
+            "users.html",
+            "{% for id in [1, 2, 1] %}{{ get_user(id=id).name | shout }} {% endfor %}{{ '<b>' | upper }}",
+        )
+        .unwrap();
+
+    let result = block_on(lysine.render_async("users.html", &Context::new())).unwrap();
+
+    assert_eq!(result, "USER1! USER2! USER1! &lt;B&gt;");
+    // The same call is only awaited once
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn render_to_async_writes_the_output() {
+    let mut lysine = lysine_with_async_callables(Arc::new(AtomicUsize::new(0)));
+    lysine.add_raw_template("user", "Hello {{ get_user(id=3).name }}").unwrap();
+
+    let mut output = Vec::new();
+    block_on(lysine.render_to_async("user", &Context::new(), &mut output)).unwrap();
+
+    assert_eq!(output, b"Hello user3");
+}
+
+#[test]
+fn render_async_future_is_send() {
+    fn assert_send<T: Send>(_: T) {}
+
+    let lysine = Lysine::default();
+    let context = Context::new();
+    assert_send(lysine.render_async("tpl", &context));
+}
+
+#[test]
+fn error_async_function_failing() {
+    let mut lysine = lysine_with_async_callables(Arc::new(AtomicUsize::new(0)));
+    lysine.add_raw_template("user", "Hello {{ get_user() }}").unwrap();
+
+    let result: Result<String> = block_on(lysine.render_async("user", &Context::new()));
+    let err = result.unwrap_err();
+
+    assert_eq!(err.to_string(), "Failed to render 'user'");
+    assert_eq!(err.source().unwrap().to_string(), "Function call 'get_user' failed");
+    assert_eq!(err.source().unwrap().source().unwrap().to_string(), "`id` is required");
+}
+
+#[test]
+fn render_async_keeps_failed_calls() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut lysine = lysine_with_async_callables(calls.clone());
+    lysine
+        .add_raw_templates(vec![
+            ("ifs", "{% if get_user().admin %}A{% endif %}{% if get_user().admin %}B{% endif %}"),
+            ("twice", "{{ get_user() }}{{ get_user() }}"),
+        ])
+        .unwrap();
+
+    let result = block_on(lysine.render_async("ifs", &Context::new()));
+    assert_eq!(result.unwrap(), "");
+
+    let err = block_on(lysine.render_async("twice", &Context::new())).unwrap_err();
+    assert_eq!(err.source().unwrap().source().unwrap().to_string(), "`id` is required");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn render_async_awaits_the_calls_of_a_pass_together() {
+    let passes = Arc::new(AtomicUsize::new(0));
+    let mut lysine = lysine_with_async_callables(Arc::new(AtomicUsize::new(0)));
+    let counter = passes.clone();
+    lysine.register_function("count_pass", move |_: &HashMap<String, Value>| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Ok(Value::Null)
+    });
+    lysine
+        .add_raw_template(
+            "users",
+            "{{ count_pass() }}{% for id in [1, 2, 3] %}{{ get_user(id=get_user(id=id).id + 10).name }} {% endfor %}",
+        )
+        .unwrap();
+
+    let result = block_on(lysine.render_async("users", &Context::new())).unwrap();
+
+    assert_eq!(result, "user11 user12 user13 ");
+    // One pass for the 3 inner calls, one for the 3 outer ones and the final one
+    assert_eq!(passes.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn error_async_function_in_sync_render() {
+    let mut lysine = lysine_with_async_callables(Arc::new(AtomicUsize::new(0)));
+    lysine.add_raw_template("user", "Hello {{ get_user(id=1) }}").unwrap();
+
+    let result = lysine.render("user", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().source().unwrap().to_string(),
+        "Async functions and filters can only be used when rendering with `Lysine::render_async`"
+    );
+}
+
+#[test]
+fn render_async_keeps_the_fuel_between_passes() {
+    let template = "{% for id in [1, 2, 3, 4] %}{{ get_user(id=id).name }}{% endfor %}";
+    let limits = RenderLimits { max_fuel: Some(30), ..Default::default() };
+
+    let mut lysine = Lysine::default();
+    lysine.register_function("get_user", |args: &HashMap<String, Value>| {
+        Ok(json!({"name": format!("user{}", args["id"])}))
+    });
+    lysine.render_limits(limits);
+    lysine.add_raw_template("users", template).unwrap();
+    assert_eq!(lysine.render("users", &Context::new()).unwrap(), "user1user2user3user4");
+
+    // The async render does a second pass once the calls are awaited, using fuel again
+    let mut lysine = lysine_with_async_callables(Arc::new(AtomicUsize::new(0)));
+    lysine.render_limits(limits);
+    lysine.add_raw_template("users", template).unwrap();
+    let err = block_on(lysine.render_async("users", &Context::new())).unwrap_err();
+    let source = err.source().unwrap().downcast_ref::<crate::Error>().unwrap();
+    assert!(matches!(source.kind, ErrorKind::FuelExhausted(30)));
+}
+
+#[test]
+fn render_async_with_macro_reference_argument() {
+    let mut lysine = Lysine::default();
+    lysine.register_async_function("identity", |args: HashMap<String, Value>| async move {
+        Ok(args["value"].clone())
+    });
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro hello() %}Hello{% endmacro hello %}"),
+            (
+                "tpl",
+                "{% import \"macros\" as macros %}{% set h = identity(value=macros::hello) %}{{ h() }} {{ h }}",
+            ),
+        ])
+        .unwrap();
+
+    let result = block_on(lysine.render_async("tpl", &Context::new())).unwrap();
+
+    assert_eq!(result, "Hello macros::hello");
+}
@@ -5,12 +5,13 @@ use std::sync::Arc;
 
 use lazy_static::lazy_static;
 use serde_derive::Serialize;
-use serde_json::{json, Value};
+use serde_json::{json, to_value, Value};
 
 use crate::builtins::functions::Function;
-use crate::context::Context;
+use crate::context::{Context, LayeredContext};
 use crate::errors::Result;
-use crate::lysine::Lysine;
+use crate::lysine::{Lysine, UndefinedBehavior};
+use crate::object::Object;
 
 use super::Review;
 
@@ -196,6 +197,337 @@ fn render_variable_block_logic_expr() {
     }
 }
 
+#[test]
+fn render_access_on_any_expression() {
+    let mut context = Context::new();
+    context.insert("users", &vec![json!({"name": "bob"}), json!({"name": "<alice>"})]);
+    context.insert("numbers", &vec![1, 2, 3]);
+    context.insert("a", &1);
+
+    let inputs = vec![
+        ("{{ (users | first).name }}", "bob"),
+        ("{{ (users | last).name | upper }}", "&lt;ALICE&gt;"),
+        ("{{ get_user().email }}", "bob@example.com"),
+        ("{{ get_user()['email'] }}", "bob@example.com"),
+        ("{{ numbers[a + 1] }}", "3"),
+        ("{{ [10, 20, 30][1] }}", "20"),
+        ("{{ (numbers | reverse)[0] * 2 }}", "6"),
+        ("{{ 'a' ~ (1 + 2) }}", "a3"),
+        ("{{ 'n: ' ~ (numbers | length) }}", "n: 3"),
+        ("{{ get_user().email ~ '!' }}", "bob@example.com!"),
+        ("{% if (users | first).name == 'bob' %}yes{% endif %}", "yes"),
+        ("{% if !! (users | first).age %}no age{% endif %}", "no age"),
+        ("{% for n in (numbers | reverse) %}{{ n }}{% endfor %}", "321"),
+    ];
+
+    for (input, expected) in inputs {
+        let mut lysine = Lysine::default();
+        lysine.add_raw_template("tpl.html", input).unwrap();
+        lysine.register_function("get_user", |_: &HashMap<String, Value>| {
+            Ok(json!({"email": "bob@example.com"}))
+        });
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(lysine.render("tpl.html", &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn error_accessing_missing_attribute_on_expression() {
+    let mut context = Context::new();
+    context.insert("users", &vec![json!({"name": "bob"})]);
+
+    let result = render_template("{{ (users | first).age }}", &context);
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Tried to access `.age` on a value that doesn't have it"
+    );
+}
+
+#[test]
+fn render_tests_and_negation_on_any_expression() {
+    let mut context = Context::new();
+    context.insert("numbers", &vec![1, 2, 3]);
+    context.insert("big", &9007199254740993u64);
+    context.insert("a", &2);
+
+    let inputs = vec![
+        ("{{ (numbers | first) is odd }}", "true"),
+        ("{{ (numbers | last) is not odd }}", "false"),
+        ("{{ numbers | length is odd }}", "true"),
+        ("{{ numbers[a] + 1 is even }}", "true"),
+        ("{{ (undefined | default(value=3)) is odd }}", "true"),
+        ("{% if !! numbers | first is even %}yes{% endif %}", "yes"),
+        ("{{ -(1 + 2) }}", "-3"),
+        ("{{ -big }}", "-9007199254740993"),
+        ("{{ -a * 3 }}", "-6"),
+        ("{{ 1 - -a }}", "3"),
+        ("{{ -(numbers | length) }}", "-3"),
+    ];
+
+    for (input, expected) in inputs {
+        assert_eq!(render_template(input, &context).unwrap(), expected, "{}", input);
+    }
+}
+
+#[test]
+fn error_index_out_of_bounds_on_expression() {
+    let mut context = Context::new();
+    context.insert("numbers", &vec![1, 2, 3]);
+
+    let result = render_template("{{ numbers[-1] }}", &context);
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Tried to access `[-1]` on an array of 3 values: the index is out of bounds"
+    );
+}
+
+#[test]
+fn render_null_coalescing() {
+    let mut context = Context::new();
+    context.insert("name", &"john");
+    context.insert("malicious", &"<html>");
+    context.insert("nothing", &Value::Null);
+    context.insert("user", &json!({"name": "bob", "address": null}));
+
+    let inputs = vec![
+        ("{{ name ?? 'x' }}", "john"),
+        ("{{ undefined ?? 'x' }}", "x"),
+        ("{{ nothing ?? 'x' }}", "x"),
+        ("{{ user.age ?? 'x' }}", "x"),
+        ("{{ user.address ?? 'x' }}", "x"),
+        ("{{ undefined ?? nothing ?? 'last' }}", "last"),
+        ("{{ undefined ?? 1 + 1 }}", "2"),
+        ("{{ (undefined ?? 1) * 3 }}", "3"),
+        ("{{ undefined ?? name | upper }}", "JOHN"),
+        ("{{ (get_user() ?? user).name }}", "bob"),
+        ("{{ name ?? fail() }}", "john"),
+        ("{{ undefined ?? malicious }}", "&lt;html&gt;"),
+        ("{% if undefined ?? true %}yes{% endif %}", "yes"),
+        ("{% if nothing ?? false %}yes{% else %}no{% endif %}", "no"),
+    ];
+
+    for (input, expected) in inputs {
+        let mut lysine = Lysine::default();
+        lysine.add_raw_template("tpl.html", input).unwrap();
+        lysine.register_function("get_user", |_: &HashMap<String, Value>| Ok(Value::Null));
+        lysine.register_function("fail", |_: &HashMap<String, Value>| Err("should not run".into()));
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(lysine.render("tpl.html", &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn render_safe_navigation() {
+    let mut context = Context::new();
+    context.insert("user", &json!({"name": "bob", "address": null, "tags": ["admin"]}));
+    context.insert("users", &vec![json!({"name": "bob"})]);
+
+    let inputs = vec![
+        ("{{ user?.name }}", "bob"),
+        ("{{ user?.address?.city }}", ""),
+        ("{{ user?.address?.city ?? 'unknown' }}", "unknown"),
+        ("{{ user?.job?.title ?? 'none' }}", "none"),
+        ("{{ user.address?.city.zip ?? 'none' }}", "none"),
+        ("{{ undefined?.name ?? 'anon' }}", "anon"),
+        ("{{ users?.[0]?.name }}", "bob"),
+        ("{{ users?.[3]?.name ?? 'nobody' }}", "nobody"),
+        ("{{ user?.tags?.[0] }}", "admin"),
+        ("{{ user.name?.first ?? 'x' }}", "x"),
+        ("{{ (users | first)?.name }}", "bob"),
+        ("{% if user?.address?.city %}yes{% else %}no{% endif %}", "no"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(render_template(input, &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn error_safe_navigation_only_covers_its_own_access() {
+    let mut context = Context::new();
+    context.insert("user", &json!({"name": "bob", "address": {}}));
+
+    let result = render_template("{{ user?.address.city }}", &context);
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Tried to access `.city` on a value that doesn't have it"
+    );
+}
+
+#[test]
+fn render_array_and_string_operators() {
+    let mut context = Context::new();
+    context.insert("extra_items", &vec![3, 4]);
+    context.insert("sep", &"=");
+    context.insert("width", &3);
+    context.insert("nothing", &Value::Null);
+    context.insert("malicious", &"<html>");
+
+    let inputs = vec![
+        ("{{ [1, 2] + extra_items }}", "[1, 2, 3, 4]"),
+        ("{{ [1] + [2] + extra_items + [] }}", "[1, 2, 3, 4]"),
+        ("{{ ([1, 2] + extra_items) | length }}", "4"),
+        ("{{ '-' * 5 }}", "-----"),
+        ("{{ 2 * 'ab' }}", "abab"),
+        ("{{ sep * width }}", "==="),
+        ("{{ sep * (width + 1) ~ '>' }}", "====>"),
+        ("{{ '-' * 0 }}", ""),
+        ("{{ 1 + 2 * 3 }}", "7"),
+        ("{{ 1 / 0 + 1 }}", "NaN"),
+        ("{{ malicious * 2 }}", "&lt;html&gt;&lt;html&gt;"),
+        ("{{ 'a' ~ true ~ nothing ~ 1.5 ~ [1, 2] }}", "atrue1.5[1, 2]"),
+        ("{{ 'is ' ~ (1 > 2) }}", "is false"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(render_template(input, &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn error_array_and_string_operators_with_wrong_types() {
+    let inputs = vec![
+        ("{{ [1] + 1 }}", "Tried to add an array and a number but `+` only works on 2 numbers or 2 arrays"),
+        ("{{ 'a' + 'b' }}", "Tried to add a string and a string but `+` only works on 2 numbers or 2 arrays"),
+        (
+            "{{ 'a' * 'b' }}",
+            "Tried to multiply a string by a string but `*` only works on 2 numbers or a string and an integer",
+        ),
+        (
+            "{{ '-' * -1 }}",
+            "Tried to repeat a string -1 times but it can only be repeated a positive integer number of times",
+        ),
+        ("{{ [1] - 1 }}", "Tried to do math with an array: only `+` can be used to concatenate 2 arrays"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        let result = render_template(input, &Context::new());
+        assert_eq!(result.unwrap_err().source().unwrap().to_string(), expected);
+    }
+}
+
+#[test]
+fn render_ordering_comparisons() {
+    let mut context = Context::new();
+    context.insert("name", &"john");
+    context.insert("malicious", &"<html>");
+    context.insert("published", &"2023-04-01T10:00:00Z");
+    context.insert("nothing", &Value::Null);
+    context.insert("version", &vec![1, 4, 2]);
+
+    let inputs = vec![
+        ("{{ name < 'm' }}", "true"),
+        ("{{ name < 'M' }}", "false"),
+        ("{{ name >= 'john' }}", "true"),
+        ("{{ 'Zoe' < 'adam' }}", "true"),
+        ("{{ malicious < '<i>' }}", "true"),
+        ("{{ published > '2023-01-31T23:59:59Z' }}", "true"),
+        ("{{ published <= '2023-04-01' }}", "false"),
+        ("{{ version < [1, 10] }}", "true"),
+        ("{{ version > [1, 4] }}", "true"),
+        ("{{ version >= [1, 4, 2] }}", "true"),
+        ("{{ [] < [0] }}", "true"),
+        ("{{ nothing < 0 }}", "true"),
+        ("{{ nothing <= nothing }}", "true"),
+        ("{{ 'a' > nothing }}", "true"),
+        ("{{ false < true }}", "true"),
+        ("{{ 2 > 1.5 }}", "true"),
+        ("{{ 9007199254740993 > 9007199254740992 }}", "true"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(render_template(input, &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn render_exact_integer_math() {
+    let mut context = Context::new();
+    context.insert("id", &9_007_199_254_740_993u64);
+    context.insert("max_u64", &u64::MAX);
+    context.insert("price_cents", &1999);
+
+    let inputs = vec![
+        ("{{ id + 1 }}", "9007199254740994"),
+        ("{{ id - 1 }}", "9007199254740992"),
+        ("{{ id * 1 }}", "9007199254740993"),
+        ("{{ id / 1 }}", "9007199254740993"),
+        ("{{ id % 10 }}", "3"),
+        ("{{ max_u64 - 1 }}", "18446744073709551614"),
+        ("{{ -1 + max_u64 }}", "18446744073709551614"),
+        ("{{ max_u64 / 5 }}", "3689348814741910323"),
+        ("{{ price_cents * 3 }}", "5997"),
+        ("{{ 10 / 4 }}", "2.5"),
+        ("{{ -7 % 3 }}", "-1"),
+        ("{{ 0.1 + 0.2 }}", "0.30000000000000004"),
+        ("{{ 100000000000000000000.0 / 2.0 }}", "50000000000000000000"),
+        ("{{ -100000000000000000000.0 / 2.0 }}", "-50000000000000000000"),
+        ("{{ id == 9007199254740992 }}", "false"),
+        ("{{ id > 9007199254740992 }}", "true"),
+        ("{{ 1 == 1.0 }}", "true"),
+        ("{{ id is odd }}", "true"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(render_template(input, &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn error_integer_overflow() {
+    let mut context = Context::new();
+    context.insert("max_u64", &u64::MAX);
+    context.insert("min_i64", &i64::MIN);
+
+    let inputs = vec![
+        ("{{ max_u64 + 1 }}", "18446744073709551615 + 1 results in an integer that doesn't fit in 64 bits"),
+        ("{{ min_i64 - 1 }}", "-9223372036854775808 - 1 results in an integer that doesn't fit in 64 bits"),
+        ("{{ max_u64 * -1 }}", "18446744073709551615 * -1 results in an integer that doesn't fit in 64 bits"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        let result = render_template(input, &context);
+        assert_eq!(result.unwrap_err().source().unwrap().to_string(), expected);
+    }
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn render_decimal_math() {
+    let mut context = Context::new();
+    context.insert("price", &19.99);
+    context.insert("id", &9_007_199_254_740_993u64);
+
+    let inputs = vec![
+        ("{{ 0.1 + 0.2 }}", "0.3"),
+        ("{{ price * 3 }}", "59.97"),
+        ("{{ 1.1 - 0.1 }}", "1"),
+        ("{{ 10 / 4 }}", "2.5"),
+        ("{{ 1 / 3 }}", "0.3333333333333333333333333333"),
+        ("{{ 5.5 % 2 }}", "1.5"),
+        ("{{ id / 1 }}", "9007199254740993"),
+        ("{{ id + 0.5 }}", "9007199254740993.5"),
+        ("{{ (id + 0.5) * 2 }}", "18014398509481987"),
+        ("{{ id * 1.5 }}", "13510798882111489.5"),
+        ("{{ 1 / 0 }}", "NaN"),
+        ("{{ 0.1 + 0.2 == 0.3 }}", "true"),
+    ];
+
+    for (input, expected) in inputs {
+        let mut lysine = Lysine::default();
+        lysine.decimal_math(true);
+        lysine.add_raw_template("tpl", input).unwrap();
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(lysine.render("tpl", &context).unwrap(), expected);
+    }
+}
+
 #[test]
 fn render_variable_block_autoescaping_disabled() {
     let mut context = Context::new();
@@ -314,6 +646,57 @@ fn can_set_variables_in_included_templates() {
     assert_eq!(result, "<h1>Hello world</h1>".to_owned());
 }
 
+#[test]
+fn render_include_function() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("address.yaml", "street: {{ street }}\ncity: Paris"),
+        ("card.html", "<p>{{ name }}</p>"),
+        ("user.yaml", "address:\n{{ include(\"address.yaml\") | indent(first=true) }}"),
+        ("page.html", "{% set card = include(\"card.html\") %}{{ card | safe }}{{ card | length }}"),
+        ("fallback.html", "{{ include([\"missing\", \"card.html\"]) }}|{{ include(name=\"missing\", ignore_missing=true) }}|"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("street", "Rue de Rivoli");
+    context.insert("name", "<b>Bob</b>");
+
+    let result = lysine.render("user.yaml", &context).unwrap();
+    assert_eq!(result, "address:\n    street: Rue de Rivoli\n    city: Paris");
+
+    // The included template escapes its own output, it isn't escaped a second time
+    let result = lysine.render("page.html", &context).unwrap();
+    assert_eq!(result, "<p>&lt;b&gt;Bob&lt;&#x2F;b&gt;</p>34");
+
+    let result = lysine.render("fallback.html", &context).unwrap();
+    assert_eq!(result, "<p>&lt;b&gt;Bob&lt;&#x2F;b&gt;</p>||");
+}
+
+#[test]
+fn render_requires_with_defaults() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("base", "{% requires site = \"Lysine\" %}{{ site }}: {% block body %}{% endblock body %}"),
+        (
+            "page",
+            "{% extends \"base\" %}{% requires user: object, cart.items: array = [], locale = \"en\" %}{% block body %}{{ user.name }} ({{ locale }}) {{ cart.items | length }}{% endblock body %}",
+        ),
+    ])
+    .unwrap();
+
+    let mut context = Context::new();
+    context.insert("user", &json!({"name": "Bob"}));
+    context.insert("cart", &json!({"total": 0}));
+    let result = lysine.render("page", &context).unwrap();
+    assert_eq!(result, "Lysine: Bob (en) 0");
+
+    context.insert("site", "Shop");
+    context.insert("locale", "fr");
+    context.insert("cart", &json!({"items": [1, 2]}));
+    let result = lysine.render("page", &context).unwrap();
+    assert_eq!(result, "Shop: Bob (fr) 2");
+}
+
 #[test]
 fn render_raw_tag() {
     let inputs = vec![
@@ -588,6 +971,43 @@ fn render_for() {
     }
 }
 
+#[test]
+fn render_while() {
+    let mut context = Context::new();
+    context.insert("pages", &vec![vec!["a", "b"], vec!["c"], vec![]]);
+
+    let inputs = vec![
+        ("{% set i = 0 %}{% while i < 3 %}{{ i }}{% set i = i + 1 %}{% endwhile %}{{ i }}", "0120"),
+        ("{% while false %}never{% endwhile %}", ""),
+        (
+            "{% set page = 0 %}{% while pages[page] | length > 0 %}{% for item in pages[page] %}{{ item }}{% endfor %}{% set page = page + 1 %}{% endwhile %}",
+            "abc"
+        ),
+        (
+            "{% set_global total = 0 %}{% while total < 10 %}{% set_global total = total + 4 %}{% endwhile %}{{ total }}",
+            "12"
+        ),
+        // Loop control (`break` and `continue`)
+        (
+            "{% set i = 0 %}{% while true %}{% set i = i + 1 %}{% if i == 3 %}{% break %}{% endif %}{{ i }}{% endwhile %}",
+            "12"
+        ),
+        (
+            "{% set i = 0 %}{% while i < 4 %}{% set i = i + 1 %}{% if i == 2 %}{% continue %}{% endif %}{{ i }}{% endwhile %}",
+            "134"
+        ),
+        (
+            "{% set i = 0 %}{% while i < 2 %}{% set i = i + 1 %}{% for j in [1, 2, 3] %}{% if j == 2 %}{% break %}{% endif %}{{ i }}{{ j }}{% endfor %}{% endwhile %}",
+            "1121"
+        ),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(render_template(input, &context).unwrap(), expected);
+    }
+}
+
 #[test]
 fn render_magic_variable_isnt_escaped() {
     let mut context = Context::new();
@@ -990,3 +1410,249 @@ fn safe_function_works() {
     let res = lysine.render("test.html", &Context::new());
     assert_eq!(res.unwrap(), "<div>Hello</div>");
 }
+
+#[test]
+fn render_undefined_behaviors() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("tpl", "[{{ missing }}|{{ user.naem }}|{{ user.name }}|{% if user.naem %}yes{% else %}no{% endif %}|{% for i in missing %}{{ i }}{% else %}empty{% endfor %}|{{ missing | default(value=1) }}]"),
+        ("chain", "[{{ missing.a.b }}|{{ missing.a.b ?? \"default\" }}]"),
+    ]).unwrap();
+    let mut context = Context::new();
+    context.insert("user", &json!({"name": "Bob"}));
+
+    let lenient = "[||Bob|no|empty|1]";
+    let result = lysine.render_with_undefined_behavior("tpl", &context, UndefinedBehavior::Lenient);
+    assert_eq!(result.unwrap(), lenient);
+    let result =
+        lysine.render_with_undefined_behavior("tpl", &context, UndefinedBehavior::Chainable);
+    assert_eq!(result.unwrap(), lenient);
+    let result =
+        lysine.render_with_undefined_behavior("chain", &context, UndefinedBehavior::Chainable);
+    assert_eq!(result.unwrap(), "[|default]");
+
+    lysine.undefined_behavior(UndefinedBehavior::Debug);
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap(), "[{{ missing: missing }}|{{ missing: user.naem }}|Bob|no|empty|1]");
+    let result = lysine.render("chain", &context);
+    assert_eq!(result.unwrap(), "[{{ missing: missing.a.b }}|default]");
+
+    let result = lysine.render_with_undefined_behavior("tpl", &context, UndefinedBehavior::Strict);
+    assert!(result.is_err());
+}
+
+#[test]
+fn render_value_keeps_type_of_single_expression() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("number", "{# the port #}{% set base = 8000 %}\n  {{ base + offset }}\n"),
+        ("object", "{{ config }}"),
+        ("filtered", "{{ config.hosts | length }}"),
+        ("string.html", "{{ \"<b>\" }}"),
+        ("text", "port={{ 8000 + offset }}"),
+        ("multiple", "{{ offset }} {{ offset }}"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("offset", &80);
+    context.insert("config", &json!({"hosts": ["a", "b"], "debug": true}));
+
+    let tests = vec![
+        ("number", json!(8080)),
+        ("object", json!({"hosts": ["a", "b"], "debug": true})),
+        ("filtered", json!(2)),
+        ("string.html", json!("&lt;b&gt;")),
+        ("text", json!("port=8080")),
+        ("multiple", json!("80 80")),
+    ];
+    for (name, expected) in tests {
+        assert_eq!(lysine.render_value(name, &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn render_value_renders_like_a_variable_block() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro hello() %}Hello{% endmacro hello %}"),
+        ("missing", "{{ user.naem }}"),
+        ("macro_ref", "{% import \"macros\" as macros %}{{ macros::hello }}"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("user", &json!({"name": "Bob"}));
+
+    lysine.undefined_behavior(UndefinedBehavior::Debug);
+    assert_eq!(lysine.render_value("missing", &context).unwrap(), json!("{{ missing: user.naem }}"));
+    assert_eq!(lysine.render_value("macro_ref", &context).unwrap(), json!("macros::hello"));
+
+    lysine.undefined_behavior(UndefinedBehavior::Chainable);
+    assert_eq!(lysine.render_value("missing", &context).unwrap(), json!(null));
+
+    lysine.undefined_behavior(UndefinedBehavior::Strict);
+    assert!(lysine.render_value("missing", &context).is_err());
+}
+
+#[test]
+fn render_globals() {
+    let mut lysine = Lysine::default();
+    lysine.add_global("site", &json!({"name": "Lysine", "url": "https://example.com"}));
+    lysine.add_global("version", "1.0");
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro footer() %}{{ site.name }} {{ version }}{% endmacro footer %}"),
+        ("tpl", "{% requires version: string %}{% import \"macros\" as macros %}{{ site.url }} {{ title }} {% for version in [2] %}{{ version }}{% endfor %} {{ macros::footer() }}"),
+    ]).unwrap();
+    let mut context = Context::new();
+    context.insert("title", "Home");
+    context.insert("version", "1.1");
+
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap(), "https://example.com Home 2 Lysine 1.1");
+
+    context.remove("version");
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap(), "https://example.com Home 2 Lysine 1.0");
+}
+
+#[test]
+fn render_layered_context() {
+    let mut lysine = Lysine::default();
+    lysine.add_global("version", "1.0");
+    lysine.add_raw_template("tpl", "{% requires user.locale = \"en\" %}{{ title }} {{ site.name }} {{ user.name }} {{ user.locale }} {{ version }}").unwrap();
+    let mut site = Context::new();
+    site.insert("site", &json!({"name": "Lysine"}));
+    site.insert("title", "Untitled");
+    let mut page = Context::new();
+    page.insert("title", "Home");
+    let user = json!({"name": "Bob"});
+
+    let mut context = LayeredContext::from(&site);
+    context.push(&page);
+    context.insert_ref("user", &user);
+
+    let result = lysine.render_layered("tpl", &context);
+    assert_eq!(result.unwrap(), "Home Lysine Bob en 1.0");
+    assert_eq!(user, json!({"name": "Bob"}));
+
+    let mut output = Vec::new();
+    context.insert("version", "2.0");
+    lysine.render_layered_to("tpl", &context, &mut output).unwrap();
+    assert_eq!(output, b"Home Lysine Bob en 2.0");
+}
+
+struct Users {
+    names: Vec<&'static str>,
+    gets: AtomicUsize,
+}
+
+impl Object for Users {
+    fn get(&self, key: &str) -> Option<Value> {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        match key {
+            "first" => Some(json!({ "name": self.names[0] })),
+            _ => None,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        to_value(&self.names).unwrap()
+    }
+
+    fn iter(&self) -> Option<Box<dyn Iterator<Item = Value> + '_>> {
+        Some(Box::new(self.names.iter().map(|n| json!(n))))
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.names.len())
+    }
+
+    fn call_method(&self, name: &str, args: &HashMap<String, Value>) -> Result<Value> {
+        match name {
+            "page" => {
+                let number = args["number"].as_u64().unwrap() as usize;
+                Ok(to_value(&self.names[number - 1..number]).unwrap())
+            }
+            _ => Err(crate::Error::msg("unknown method")),
+        }
+    }
+}
+
+#[test]
+fn render_objects() {
+    let users = Arc::new(Users { names: vec!["Bob", "Alice", "Eve"], gets: AtomicUsize::new(0) });
+    let mut context = Context::new();
+    context.insert_object("users", Users { names: vec!["Bob", "Alice"], gets: AtomicUsize::new(0) });
+
+    let inputs = vec![
+        ("{{ users.first.name }}", "Bob"),
+        ("{% for user in users %}{{ user }} {% endfor %}", "Bob Alice "),
+        ("{{ users | length }}", "2"),
+        ("{{ users | length + 1 }}", "3"),
+        ("{{ users.page(number=2) | first }}", "Alice"),
+        ("{{ users | join(sep=\",\") }}", "Bob,Alice"),
+        ("{% if users.missing %}yes{% else %}no{% endif %}", "no"),
+        ("{% set users = 1 %}{{ users }}", "1"),
+        ("{% for users in [1] %}{{ users }}{% endfor %}", "1"),
+    ];
+
+    for (input, expected) in inputs {
+        println!("{:?} -> {:?}", input, expected);
+        assert_eq!(render_template(input, &context).unwrap(), expected);
+    }
+
+    let mut lysine = Lysine::default();
+    lysine.add_raw_template("tpl", "{{ users.first.name }}").unwrap();
+    let mut context = Context::new();
+    context.insert_object("users", SharedUsers(users.clone()));
+    assert_eq!(lysine.render("tpl", &context).unwrap(), "Bob");
+    assert_eq!(users.gets.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn render_requires_with_objects() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_template("tpl", "{% requires users.first.name: string %}{{ users.first.name }}").unwrap();
+    let mut context = Context::new();
+    context.insert_object("users", Users { names: vec!["Bob", "Alice"], gets: AtomicUsize::new(0) });
+
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap(), "Bob");
+}
+
+#[test]
+fn render_magic_variable_includes_globals_and_objects() {
+    let mut lysine = Lysine::default();
+    lysine.add_global("lang", "en");
+    lysine.add_raw_template("tpl", "{{ __lysine_context }}").unwrap();
+    let mut site = Context::new();
+    site.insert_object("users", Users { names: vec!["Bob", "Alice"], gets: AtomicUsize::new(0) });
+    let version = json!("1.0");
+    let mut context = LayeredContext::from(&site);
+    context.insert_ref("version", &version);
+
+    let result = lysine.render_layered("tpl", &context);
+
+    assert_eq!(
+        result.unwrap(),
+        r#"{
+  "lang": "en",
+  "users": [
+    "Bob",
+    "Alice"
+  ],
+  "version": "1.0"
+}"#
+    );
+}
+
+struct SharedUsers(Arc<Users>);
+
+impl Object for SharedUsers {
+    fn get(&self, key: &str) -> Option<Value> {
+        self.0.get(key)
+    }
+
+    fn to_value(&self) -> Value {
+        self.0.to_value()
+    }
+}
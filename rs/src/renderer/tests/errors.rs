@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::time::Duration;
 
 use crate::context::Context;
-use crate::lysine::Lysine;
+use crate::errors::ErrorKind;
+use crate::lysine::{Lysine, RenderLimits, UndefinedBehavior};
+use crate::object::Object;
 
 #[test]
 fn error_location_basic() {
@@ -204,55 +207,654 @@ fn right_variable_name_is_needed_in_for_loop() {
     );
 }
 
-// https://github.com/Keats/tera/issues/385
-// https://github.com/Keats/tera/issues/370
 #[test]
-fn errors_with_inheritance_in_included_template() {
+fn error_string_concat_math_logic() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{{ 'ho' ~ name < 10 }}")]).unwrap();
+    let mut context = Context::new();
+    context.insert("name", &"john");
+
+    let result = lysine.render("tpl", &context);
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Tried to compare a string with a number"
+    );
+}
+
+#[test]
+fn error_comparing_different_types() {
+    let mut context = Context::new();
+    context.insert("name", &"john");
+    context.insert("object", &HashMap::<String, i32>::new());
+
+    let inputs = vec![
+        ("{{ name < 1 }}", "a string", "a number"),
+        ("{{ [1] >= 'a' }}", "an array", "a string"),
+        ("{{ object > object }}", "an object", "an object"),
+        ("{{ [1, 'a'] < [1, 2] }}", "a string", "a number"),
+    ];
+
+    for (input, lhs, rhs) in inputs {
+        let mut lysine = Lysine::default();
+        lysine.add_raw_template("tpl", input).unwrap();
+        let err = lysine.render("tpl", &context).unwrap_err();
+        let source = err.source().unwrap().downcast_ref::<crate::Error>().unwrap();
+
+        match source.kind {
+            ErrorKind::InvalidComparison { lhs: ref l, rhs: ref r } => {
+                assert_eq!((l.as_str(), r.as_str()), (lhs, rhs));
+            }
+            _ => panic!("Unexpected error for {}: {}", input, source),
+        }
+    }
+}
+
+#[test]
+fn error_gives_source_on_tests() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{% if a is undefined(1) %}-{% endif %}")]).unwrap();
+    let result = lysine.render("tpl", &Context::new());
+    println!("{:?}", result);
+    let err = result.unwrap_err();
+
+    let source = err.source().unwrap();
+    assert_eq!(source.to_string(), "Test call \'undefined\' failed");
+    let source2 = source.source().unwrap();
+
+    assert_eq!(
+        source2.to_string(),
+        "Tester `undefined` was called with some args but this test doesn\'t take args"
+    );
+}
+
+#[test]
+fn error_macro_unknown_argument() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro hello(name) %}Hello {{ name }}{% endmacro hello %}"),
+            ("tpl", "{% import \"macros\" as macros %}{{ macros::hello(name=\"a\", nmae=\"b\") }}"),
+        ])
+        .unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Macro `hello` received an unknown argument `nmae`"
+    );
+}
+
+#[test]
+fn error_macro_too_many_positional_arguments() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro hello(name) %}Hello {{ name }}{% endmacro hello %}"),
+            ("tpl", "{% import \"macros\" as macros %}{{ macros::hello(\"a\", \"b\") }}"),
+        ])
+        .unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Macro `hello` takes 1 positional arguments but 2 were given"
+    );
+}
+
+#[test]
+fn error_macro_argument_given_twice() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro hello(name) %}Hello {{ name }}{% endmacro hello %}"),
+            ("tpl", "{% import \"macros\" as macros %}{{ macros::hello(\"a\", name=\"b\") }}"),
+        ])
+        .unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Macro `hello` received the argument `name` both positionally and by name"
+    );
+}
+
+#[test]
+fn error_function_positional_arguments() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{{ range(5) }}")]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Function `range` only takes keyword arguments"
+    );
+}
+
+#[test]
+fn error_map_filter_with_non_macro() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{{ [1, 2] | map(macro=\"hello\") }}")]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().source().unwrap().to_string(),
+        "The `macro` argument of the `map` filter has to be a macro, like `macros::my_macro`"
+    );
+}
+
+#[test]
+fn error_block_function_inside_same_block() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{% block title %}{{ block(\"title\") }}{% endblock title %}")]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Block `title` can't be rendered with `block()` from inside itself"
+    );
+}
+
+#[test]
+fn error_include_function_missing_template() {
     let mut lysine = Lysine::default();
     lysine.add_raw_templates(vec![
-        ("base", "Base - {% include \"child\" %}"),
-        ("parent", "{% block title %}Parent{% endblock %}"),
-        ("child", "{% extends \"parent\" %}{% block title %}{{ super() }} - Child{% endblock %}"),
+        ("missing", "{{ include(\"nope\") | upper }}"),
+        ("wrong_name", "{{ include(1) }}"),
     ])
     .unwrap();
 
-    let result = lysine.render("base", &Context::new());
+    let result = lysine.render("missing", &Context::new());
+    assert_eq!(result.unwrap_err().source().unwrap().to_string(), "Template '[nope]' not found");
 
+    let result = lysine.render("wrong_name", &Context::new());
     assert_eq!(
         result.unwrap_err().source().unwrap().to_string(),
-        "Inheritance in included templates is currently not supported: extended `parent`"
+        "Function `include` received name=1 but `name` can only be a string or an array of strings"
     );
 }
 
 #[test]
-fn error_string_concat_math_logic() {
+fn error_embed_missing_required_block() {
     let mut lysine = Lysine::default();
-    lysine.add_raw_templates(vec![("tpl", "{{ 'ho' ~ name < 10 }}")]).unwrap();
+    let res = lysine.add_raw_templates(vec![
+        ("panel", "{% block body required %}{% endblock body %}"),
+        ("page", "{% embed \"panel\" %}{% endembed %}"),
+    ]);
+
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "Template `page:embed:0` needs to override the block `body` marked as required in `panel`"
+    );
+}
+
+#[test]
+fn error_embed_with_text() {
+    let mut lysine = Lysine::default();
+    let res = lysine.add_raw_templates(vec![
+        ("panel", "{% block body %}{% endblock body %}"),
+        ("page", "{% embed \"panel\" %}Hello{% endembed %}"),
+    ]);
+
+    assert_eq!(
+        res.unwrap_err().source().unwrap().to_string(),
+        "Only blocks and comments can be used inside `{% embed %}`, found the text `Hello`"
+    );
+}
+
+#[test]
+fn error_while_loop_reaching_max_iterations() {
+    let mut lysine = Lysine::default();
+    lysine.max_while_iterations(5);
+    lysine
+        .add_raw_templates(vec![
+            ("bounded", "{% set i = 0 %}{% while i < 5 %}{% set i = i + 1 %}{% endwhile %}{{ i }}"),
+            ("forever", "{% while true %}.{% endwhile %}"),
+        ])
+        .unwrap();
+
+    assert_eq!(lysine.render("bounded", &Context::new()).unwrap(), "0");
+
+    let result = lysine.render("forever", &Context::new());
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "`while` loop stopped after reaching the limit of 5 iterations"
+    );
+}
+
+#[test]
+fn error_requires_missing_variable_before_writing() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_templates(vec![
+            ("base", "{% requires title %}<title>{{ title }}</title>{% block body %}{% endblock body %}"),
+            ("page", "{% extends \"base\" %}{% block body %}Hello{% endblock body %}"),
+        ])
+        .unwrap();
+
+    let mut output = Vec::new();
+    let result = lysine.render_to("page", &Context::new(), &mut output);
+
+    assert!(output.is_empty());
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Template `base` requires `title` but it isn't in the context"
+    );
+}
+
+#[test]
+fn error_requires_wrong_type() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_template("tpl", "{% requires cart.items: array %}{{ cart.items }}").unwrap();
     let mut context = Context::new();
-    context.insert("name", &"john");
+    context.insert("cart", &serde_json::json!({"items": "none"}));
 
     let result = lysine.render("tpl", &context);
 
     assert_eq!(
         result.unwrap_err().source().unwrap().to_string(),
-        "Tried to do math with a string concatenation: 'ho' ~ name"
+        "Template `tpl` requires `cart.items` to be an array but it is a string"
     );
 }
 
 #[test]
-fn error_gives_source_on_tests() {
+fn error_requires_default_on_a_non_object() {
     let mut lysine = Lysine::default();
-    lysine.add_raw_templates(vec![("tpl", "{% if a is undefined(1) %}-{% endif %}")]).unwrap();
+    lysine.add_raw_template("tpl", "{% requires title.text = \"Home\" %}{{ title.text }}").unwrap();
+    let mut context = Context::new();
+    context.insert("title", "Home");
+
+    let result = lysine.render("tpl", &context);
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Template `tpl` can't use the default of `title.text`: something on its path isn't an object"
+    );
+}
+
+#[test]
+fn error_call_macro_checks_requirements() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_template("forms", "{% requires title %}{% macro input(name) %}{{ name }}{% endmacro input %}")
+        .unwrap();
+
+    let mut args = HashMap::new();
+    args.insert("name".to_string(), serde_json::Value::from("email"));
+    let result = lysine.call_macro("forms", "input", &args);
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Template `forms` requires `title` but it isn't in the context"
+    );
+}
+
+#[test]
+fn error_render_block_missing_block() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("base", "{% block content %}{% endblock content %}"),
+        ("page", "{% extends \"base\" %}{% block content %}{{ missing }}{% endblock content %}"),
+    ])
+    .unwrap();
+
+    let result = lysine.render_block("page", "title", &Context::new());
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Block `title` not found in template `page` or its parents"
+    );
+
+    let result = lysine.render_block("page", "content", &Context::new());
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Variable `missing` not found in context while rendering 'page'"
+    );
+}
+
+#[test]
+fn error_call_macro_from_rust() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("forms", "{% macro input(name) %}{{ name }}{% endmacro input %}")]).unwrap();
+
+    let result = lysine.call_macro("forms", "select", &HashMap::new());
+    assert_eq!(result.unwrap_err().to_string(), "Macro `select` not found in template `forms`");
+
+    let result = lysine.call_macro("forms", "input", &HashMap::new());
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Macro `input` is missing the argument `name`"
+    );
+}
+
+#[test]
+fn error_lenient_undefined_attribute_of_undefined_variable() {
+    let mut lysine = Lysine::default();
+    lysine.undefined_behavior(UndefinedBehavior::Lenient);
+    lysine.add_raw_templates(vec![("tpl", "{{ missing.name }}")]).unwrap();
+
     let result = lysine.render("tpl", &Context::new());
-    println!("{:?}", result);
-    let err = result.unwrap_err();
 
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Variable `missing.name` not found in context while rendering 'tpl'"
+    );
+}
+
+struct Empty;
+
+impl Object for Empty {
+    fn get(&self, _: &str) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn to_value(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+}
+
+#[test]
+fn error_method_call_on_non_object() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{{ users.page(number=2) }}")]).unwrap();
+    let mut context = Context::new();
+    context.insert("users", &vec!["Bob"]);
+
+    let result = lysine.render("tpl", &context);
+
+    assert_eq!(
+        result.unwrap_err().source().unwrap().to_string(),
+        "Tried to call the method `users.page` but `users` isn't an object of the context"
+    );
+}
+
+#[test]
+fn error_method_call_missing_method() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("tpl", "{{ users.page(number=2) }}")]).unwrap();
+    let mut context = Context::new();
+    context.insert_object("users", Empty);
+
+    let result = lysine.render("tpl", &context);
+
+    let err = result.unwrap_err();
     let source = err.source().unwrap();
-    assert_eq!(source.to_string(), "Test call \'undefined\' failed");
-    let source2 = source.source().unwrap();
+    assert_eq!(source.to_string(), "Function call 'users.page' failed");
+    assert_eq!(source.source().unwrap().to_string(), "The object doesn't have a method `page`");
+}
+
+fn render_with_limits(content: &str, limits: RenderLimits) -> crate::Error {
+    let mut lysine = Lysine::default();
+    lysine.render_limits(limits);
+    lysine
+        .add_raw_templates(vec![
+            (
+                "macros",
+                "{% macro rec(n) %}{{ n }}{{ self::rec(n=n + 1) }}{% endmacro rec %}{% macro line(n) %}{% for i in range(end=n) %}-{% endfor %}{% endmacro line %}",
+            ),
+            ("self_include", "{% include \"self_include\" %}"),
+            ("tpl", content),
+        ])
+        .unwrap();
+
+    lysine.render("tpl", &Context::new()).unwrap_err()
+}
+
+#[test]
+fn error_render_limits() {
+    let inputs = vec![
+        (
+            "{% for i in range(end=100) %}{{ i }}{% endfor %}",
+            RenderLimits { max_output_bytes: Some(50), ..Default::default() },
+            "The rendered output exceeded the limit of 50 bytes",
+        ),
+        (
+            "{% for i in range(end=10) %}{% for j in range(end=10) %}{% endfor %}{% endfor %}",
+            RenderLimits { max_loop_iterations: Some(50), ..Default::default() },
+            "Loops exceeded the limit of 50 iterations",
+        ),
+        (
+            "{% set i = 0 %}{% while true %}{% set i = i + 1 %}{% endwhile %}",
+            RenderLimits { max_loop_iterations: Some(50), ..Default::default() },
+            "Loops exceeded the limit of 50 iterations",
+        ),
+        (
+            "{% import \"macros\" as macros %}{{ macros::rec(n=0) }}",
+            RenderLimits { max_call_depth: Some(20), ..Default::default() },
+            "Macro calls and includes exceeded the maximum depth of 20",
+        ),
+        (
+            "{% include \"self_include\" %}",
+            RenderLimits { max_call_depth: Some(20), ..Default::default() },
+            "Macro calls and includes exceeded the maximum depth of 20",
+        ),
+        (
+            "{% for i in range(end=100) %}{{ i }}{% endfor %}",
+            RenderLimits { max_fuel: Some(100), ..Default::default() },
+            "The render exceeded the limit of 100 evaluations",
+        ),
+        (
+            "{% for i in range(end=10000) %}{% for j in range(end=10000) %}{% endfor %}{% endfor %}",
+            RenderLimits { timeout: Some(Duration::from_millis(10)), ..Default::default() },
+            "The render took longer than the limit of 10ms",
+        ),
+    ];
+
+    for (input, limits, expected) in inputs {
+        let err = render_with_limits(input, limits);
+        let mut source: &(dyn Error + 'static) = &err;
+        while let Some(s) = source.source() {
+            source = s;
+        }
+        assert_eq!(source.to_string(), expected);
+    }
+}
+
+// What isn't written to the output can't use unbounded memory either
+#[test]
+fn error_render_limits_on_intermediate_values() {
+    let inputs = vec![
+        (
+            "{{ range(end=100000000) | length }}",
+            RenderLimits { max_loop_iterations: Some(1000), ..Default::default() },
+            "Loops exceeded the limit of 1000 iterations",
+        ),
+        (
+            "{% set x = \"ab\" * 500000000 %}",
+            RenderLimits { max_value_bytes: Some(1000), ..Default::default() },
+            "A value built while rendering exceeded the limit of 1000 bytes",
+        ),
+        (
+            "{% set x = \"ab\" ~ \"cd\" * 499 ~ \"ef\" %}",
+            RenderLimits { max_value_bytes: Some(1000), ..Default::default() },
+            "A value built while rendering exceeded the limit of 1000 bytes",
+        ),
+        (
+            "{% set a = [1] %}{% for i in range(end=40) %}{% set_global a = a + a %}{% endfor %}",
+            RenderLimits { max_value_bytes: Some(1000), ..Default::default() },
+            "A value built while rendering exceeded the limit of 1000 bytes",
+        ),
+        (
+            "{% import \"macros\" as macros %}{% set x = macros::line(n=100) %}",
+            RenderLimits { max_value_bytes: Some(50), ..Default::default() },
+            "A value built while rendering exceeded the limit of 50 bytes",
+        ),
+        (
+            "{% filter upper %}{% for i in range(end=100) %}{{ i }}{% endfor %}{% endfilter %}",
+            RenderLimits { max_value_bytes: Some(50), ..Default::default() },
+            "A value built while rendering exceeded the limit of 50 bytes",
+        ),
+    ];
+
+    for (input, limits, expected) in inputs {
+        let err = render_with_limits(input, limits);
+        let mut source: &(dyn Error + 'static) = &err;
+        while let Some(s) = source.source() {
+            source = s;
+        }
+        assert_eq!(source.to_string(), expected);
+    }
+}
+
+// An object with as many items as asked
+struct Endless {
+    len: Option<usize>,
+}
+
+impl Object for Endless {
+    fn get(&self, _: &str) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn to_value(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    fn iter(&self) -> Option<Box<dyn Iterator<Item = serde_json::Value> + '_>> {
+        Some(Box::new(std::iter::repeat(serde_json::Value::from(1))))
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.len
+    }
+}
+
+#[test]
+fn error_render_limits_on_object_items() {
+    let mut lysine = Lysine::default();
+    lysine.render_limits(RenderLimits { max_loop_iterations: Some(10), ..Default::default() });
+    lysine.add_raw_template("tpl", "{% for i in items %}{{ i }}{% endfor %}").unwrap();
+
+    for len in [None, Some(usize::MAX)] {
+        let mut context = Context::new();
+        context.insert_object("items", Endless { len });
 
+        let err = lysine.render("tpl", &context).unwrap_err();
+
+        let source = err.source().unwrap().downcast_ref::<crate::Error>().unwrap();
+        assert!(matches!(source.kind, ErrorKind::LoopIterationLimitExceeded(10)));
+    }
+}
+
+#[test]
+fn error_render_limits_have_their_own_kind() {
+    let err = render_with_limits(
+        "{% while true %}{% endwhile %}",
+        RenderLimits { max_loop_iterations: Some(5), ..Default::default() },
+    );
+
+    let source = err.source().unwrap().downcast_ref::<crate::Error>().unwrap();
+    assert!(matches!(source.kind, ErrorKind::LoopIterationLimitExceeded(5)));
+
+    let err = render_with_limits(
+        "{% for i in range(end=100) %}{{ i }}{% endfor %}",
+        RenderLimits { max_output_bytes: Some(10), ..Default::default() },
+    );
+
+    let source = err.source().unwrap().downcast_ref::<crate::Error>().unwrap();
+    assert!(matches!(source.kind, ErrorKind::OutputLimitExceeded(10)));
+
+    let err = render_with_limits(
+        "{% set x = \"ab\" * 10 %}",
+        RenderLimits { max_value_bytes: Some(10), ..Default::default() },
+    );
+
+    let source = err.source().unwrap().downcast_ref::<crate::Error>().unwrap();
+    assert!(matches!(source.kind, ErrorKind::ValueLimitExceeded(10)));
+}
+
+#[test]
+fn render_limits_allow_renders_within_them() {
+    let mut lysine = Lysine::default();
+    lysine.render_limits(RenderLimits {
+        max_output_bytes: Some(10),
+        max_value_bytes: Some(10),
+        max_loop_iterations: Some(10),
+        max_call_depth: Some(2),
+        max_fuel: Some(100),
+        timeout: Some(Duration::from_secs(10)),
+    });
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro hello() %}{% include \"name\" %}{% endmacro hello %}"),
+            ("name", "Bob"),
+            (
+                "tpl",
+                "{% import \"macros\" as macros %}{% for i in range(end=3) %}{{ i }}{% endfor %}{{ macros::hello() }}",
+            ),
+        ])
+        .unwrap();
+
+    assert_eq!(lysine.render("tpl", &Context::new()).unwrap(), "012Bob");
+    // The limits apply to each render separately
+    assert_eq!(lysine.render("tpl", &Context::new()).unwrap(), "012Bob");
+}
+
+// The values built while rendering don't count against the output limit, only what is written
+#[test]
+fn render_output_limit_only_counts_the_output() {
+    let inputs = vec![
+        ("{{ \"-\" * 60 }}", "-".repeat(60)),
+        ("{% filter upper %}{{ \"a\" * 59 }}{% endfilter %}", "A".repeat(59)),
+        ("{% set x = \"a\" * 100 %}{{ x | length }}", "100".to_string()),
+        (
+            "{% set a = [] %}{% for i in range(end=12) %}{% set_global a = a + [i] %}{% endfor %}{{ a | length }}",
+            "12".to_string(),
+        ),
+        ("{% import \"macros\" as macros %}{{ macros::line() }}", "=".repeat(60)),
+    ];
+
+    for (input, expected) in inputs {
+        let mut lysine = Lysine::default();
+        lysine.render_limits(RenderLimits { max_output_bytes: Some(60), ..Default::default() });
+        lysine
+            .add_raw_templates(vec![
+                ("macros", "{% macro line() %}{{ \"=\" * 60 }}{% endmacro line %}"),
+                ("tpl", input),
+            ])
+            .unwrap();
+        assert_eq!(lysine.render("tpl", &Context::new()).unwrap(), expected);
+    }
+
+    let err = render_with_limits(
+        "{{ \"-\" * 61 }}",
+        RenderLimits { max_output_bytes: Some(60), ..Default::default() },
+    );
     assert_eq!(
-        source2.to_string(),
-        "Tester `undefined` was called with some args but this test doesn\'t take args"
+        err.source().unwrap().to_string(),
+        "The rendered output exceeded the limit of 60 bytes"
+    );
+}
+
+#[test]
+fn error_macro_reference_from_context_is_not_callable() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_templates(vec![
+            ("secret", "{% macro hidden() %}SECRET{% endmacro hidden %}"),
+            ("macros", "{% macro hello() %}Hello{% endmacro hello %}"),
+            (
+                "tpl",
+                "{% import \"macros\" as macros %}{% set h = macros::hello %}{{ h() }}{{ m() }}",
+            ),
+        ])
+        .unwrap();
+    let mut context = Context::new();
+    // Neither the old format nor a copy of a reference of another render resolve
+    context.insert(
+        "m",
+        &serde_json::json!({"__lysine_macro": {"template": "secret", "name": "hidden"}}),
     );
+
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap_err().source().unwrap().to_string(), "Function 'm' not found");
+
+    context.insert("m", &serde_json::json!({"__lysine_macro": {"key": "0", "index": 0}}));
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap_err().source().unwrap().to_string(), "Function 'm' not found");
 }
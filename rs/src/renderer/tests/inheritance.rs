@@ -174,3 +174,210 @@ fn render_super_in_grandchild_without_redefining_in_parent_works() {
     let result = lysine.render("child", &Context::new());
     assert_eq!(result.unwrap(), "Title - More".to_string());
 }
+
+#[test]
+fn render_required_block_overridden_by_grandchild() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("grandparent", "<title>{% block title required %}{% endblock title %}</title>"),
+        ("parent", "{% extends \"grandparent\" %}"),
+        ("child", "{% extends \"parent\" %}{% block title %}Hello{% endblock title %}"),
+    ])
+    .unwrap();
+    let result = lysine.render("child", &Context::new());
+
+    assert_eq!(result.unwrap(), "<title>Hello</title>".to_string());
+}
+
+#[test]
+fn error_on_required_block_not_overridden() {
+    let mut lysine = Lysine::default();
+    let result = lysine.add_raw_templates(vec![
+        ("parent", "{% block title required %}{% endblock title %}{% block body %}{% endblock body %}"),
+        ("child", "{% extends \"parent\" %}{% block body %}Hello{% endblock body %}"),
+    ]);
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Template `child` needs to override the block `title` marked as required in `parent`"
+    );
+}
+
+#[test]
+fn error_on_rendering_required_block_directly() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![("parent", "{% block title required %}{% endblock title %}")]).unwrap();
+    let result = lysine.render("parent", &Context::new());
+
+    assert_eq!(
+        std::error::Error::source(&result.unwrap_err()).unwrap().to_string(),
+        "Block `title` is marked as required in `parent` but isn't overridden"
+    );
+}
+
+#[test]
+fn render_block_function() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("top", "<title>{% block title %}Top{% endblock title %}</title>{% block body %}<h1>{{ block(\"title\") }}</h1>{% endblock body %}"),
+        ("bottom", "{% extends \"top\" %}{% block title %}Bottom - {{ super() }}{% endblock title %}"),
+    ])
+    .unwrap();
+    let result = lysine.render("bottom", &Context::new());
+
+    assert_eq!(result.unwrap(), "<title>Bottom - Top</title><h1>Bottom - Top</h1>".to_string());
+}
+
+#[test]
+fn render_scoped_block_in_forloop() {
+    let mut context = Context::new();
+    context.insert("items", &vec!["a", "b"]);
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("top", "{% for item in items %}{% block item scoped %}{{ item }}{% endblock item %}{% endfor %}"),
+        ("bottom", "{% extends \"top\" %}{% block item %}[{{ loop.index }}:{{ item }}]{% endblock item %}"),
+    ])
+    .unwrap();
+
+    assert_eq!(lysine.render("top", &context).unwrap(), "ab".to_string());
+    assert_eq!(lysine.render("bottom", &context).unwrap(), "[1:a][2:b]".to_string());
+}
+
+// https://github.com/Keats/tera/issues/385
+// https://github.com/Keats/tera/issues/370
+#[test]
+fn render_inheritance_in_included_template() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("base", "Base - {% include \"child\" %}"),
+        ("parent", "{% block title %}Parent{% endblock %}"),
+        ("child", "{% extends \"parent\" %}{% block title %}{{ super() }} - Child{% endblock %}"),
+    ])
+    .unwrap();
+
+    let result = lysine.render("base", &Context::new());
+
+    assert_eq!(result.unwrap(), "Base - Parent - Child".to_string());
+}
+
+#[test]
+fn included_template_blocks_are_independent_of_the_page_blocks() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("frame", "<div>{% block title %}Frame{% endblock title %}: {% block content %}{% endblock content %}</div>"),
+        ("widget", "{% extends \"frame\" %}{% block content %}{{ super() }}Widget {{ name }}{% endblock content %}"),
+        ("layout", "{% block title %}Layout{% endblock title %}|{% block content %}{% endblock content %}"),
+        ("page", "{% extends \"layout\" %}{% block title %}Page{% endblock title %}{% block content %}{{ super() }}{% include \"widget\" %}{{ block(\"title\") }}{% endblock content %}"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("name", "clock");
+
+    let result = lysine.render("page", &context);
+
+    assert_eq!(result.unwrap(), "Page|<div>Frame: Widget clock</div>Page".to_string());
+}
+
+#[test]
+fn render_macros_in_included_template_with_inheritance() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro hello(name) %}Hello {{ name }}{% endmacro hello %}"),
+        ("frame", "{% import \"macros\" as macros %}[{% block content %}{{ macros::hello(name=\"frame\") }}{% endblock content %}]"),
+        ("widget", "{% extends \"frame\" %}{% block content %}{{ super() }}, {{ macros::hello(name=\"widget\") }}{% endblock content %}"),
+        ("page", "{% include \"widget\" %}"),
+    ])
+    .unwrap();
+
+    let result = lysine.render("page", &Context::new());
+
+    assert_eq!(result.unwrap(), "[Hello frame, Hello widget]".to_string());
+}
+
+#[test]
+fn render_embed_with_overridden_blocks() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("panel", "<div>{% block title %}Panel{% endblock title %}|{% block body %}Empty{% endblock body %}</div>"),
+        ("page", "Hi {{ name }} {% embed \"panel\" %}\n  {# only the body #}\n  {% block body %}{{ super() }} {{ name }}{% endblock body %}\n{% endembed %} {% embed \"panel\" %}{% block title %}Other{% endblock title %}{% endembed %}"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("name", "Bob");
+
+    let result = lysine.render("page", &context);
+
+    assert_eq!(
+        result.unwrap(),
+        "Hi Bob <div>Panel|Empty Bob</div> <div>Other|Empty</div>".to_string()
+    );
+}
+
+#[test]
+fn render_embed_in_blocks_and_loops() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro bold(text) %}<b>{{ text }}</b>{% endmacro bold %}"),
+        ("card", "{% extends \"frame\" %}{% block content %}({% block body required %}{% endblock body %}){% endblock content %}"),
+        ("frame", "[{% block content %}{% endblock content %}]"),
+        ("base", "{% block main %}{% endblock main %}"),
+        ("page", "{% extends \"base\" %}{% import \"macros\" as macros %}{% block main %}{% for item in items %}{% embed \"card\" %}{% block body %}{{ macros::bold(text=item) }}{% embed \"frame\" %}{% block content %}{{ loop.index }}{% endblock content %}{% endembed %}{% endblock body %}{% endembed %}{% endfor %}{% endblock main %}"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("items", &vec!["a", "b"]);
+
+    let result = lysine.render("page", &context);
+
+    assert_eq!(result.unwrap(), "[(<b>a</b>[1])][(<b>b</b>[2])]".to_string());
+}
+
+#[test]
+fn embed_templates_belong_to_their_template() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("panel", "<div>{% block body %}Empty{% endblock body %}</div>"),
+        ("page", "{% embed \"panel\" %}{% block body %}Page{% endblock body %}{% endembed %}"),
+    ])
+    .unwrap();
+
+    let mut names: Vec<_> = lysine.get_template_names().collect();
+    names.sort_unstable();
+    assert_eq!(names, vec!["page", "panel"]);
+    assert!(lysine.render("page:embed:0", &Context::new()).is_err());
+
+    // A template can have the same name as a hidden one
+    lysine.add_raw_template("page:embed:0", "Mine").unwrap();
+    assert_eq!(lysine.render("page", &Context::new()).unwrap(), "<div>Page</div>");
+    assert_eq!(lysine.render("page:embed:0", &Context::new()).unwrap(), "Mine");
+
+    lysine.add_raw_template("page", "{% embed \"panel\" %}{% endembed %}").unwrap();
+    assert_eq!(lysine.render("page", &Context::new()).unwrap(), "<div>Empty</div>");
+
+    let result = lysine.render_str("{% embed \"panel\" %}{% endembed %}", &Context::new());
+    assert_eq!(result.unwrap(), "<div>Empty</div>");
+    assert_eq!(lysine.get_template_names().count(), 3);
+}
+
+#[test]
+fn render_single_block() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro bold(text) %}<b>{{ text }}</b>{% endmacro bold %}"),
+        ("base", "<main>{% block content %}Base{% block inner %}Inner{% endblock inner %}{% endblock content %}</main>"),
+        ("page", "{% extends \"base\" %}{% import \"macros\" as macros %}{% set ignored = 1 %}{% block inner %}{{ super() }} {{ macros::bold(text=name) }}{% endblock inner %}"),
+    ])
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("name", "Bob");
+
+    assert_eq!(lysine.render_block("page", "inner", &context).unwrap(), "Inner <b>Bob</b>");
+    assert_eq!(
+        lysine.render_block("page", "content", &context).unwrap(),
+        "BaseInner <b>Bob</b>"
+    );
+
+    let mut output = Vec::new();
+    lysine.render_block_to("base", "inner", &context, &mut output).unwrap();
+    assert_eq!(output, b"Inner");
+}
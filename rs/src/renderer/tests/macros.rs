@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+
+use serde_json::{to_value, Value};
+
 use crate::context::Context;
 use crate::lysine::Lysine;
 
@@ -367,3 +371,209 @@ fn parent_macro_cant_access_child_macro_context() {
     let result = lysine.render("parent", &Context::new());
     assert_eq!(result.unwrap(), "macro-macro".to_string());
 }
+
+#[test]
+fn render_selectively_imported_macros() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("forms", r#"{% macro input(name) %}<input name="{{ name }}">{% endmacro input %}{% macro select(name) %}<select name="{{ name }}"></select>{% endmacro select %}"#),
+        ("tpl", r#"{% from "forms" import input, select as dropdown %}{{ input(name="a") }}{{ dropdown(name="b") }}"#),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), r#"<input name="a"><select name="b"></select>"#.to_string());
+}
+
+#[test]
+fn render_selectively_imported_macros_in_child_template() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("forms", "{% macro input(name) %}[{{ name }}]{% endmacro input %}"),
+        ("base", "{% block body %}{% endblock body %}"),
+        ("child", r#"{% extends "base" %}{% from "forms" import input %}{% block body %}{{ input(name="a") }}{% endblock body %}"#),
+    ]).unwrap();
+
+    let result = lysine.render("child", &Context::new());
+    assert_eq!(result.unwrap(), "[a]".to_string());
+}
+
+#[test]
+fn render_macros_imported_inside_block_and_macro() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("forms", "{% macro input(name) %}[{{ name }}]{% endmacro input %}"),
+        ("widgets", r#"{% macro field(name) %}{% from "forms" import input as widget %}<p>{{ widget(name=name) }}</p>{% endmacro field %}"#),
+        ("tpl", r#"{% block body %}{% from "widgets" import field %}{{ field(name="a") }}{% endblock body %}"#),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), "<p>[a]</p>".to_string());
+}
+
+#[test]
+fn selectively_imported_macros_are_scoped() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("forms", "{% macro input(name) %}[{{ name }}]{% endmacro input %}"),
+        ("tpl", r#"{% block body %}{% from "forms" import input %}{% endblock body %}{{ input(name="a") }}"#),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert!(result.is_err());
+}
+
+#[test]
+fn error_on_selectively_importing_unknown_macro() {
+    let mut lysine = Lysine::default();
+    let result = lysine.add_raw_templates(vec![
+        ("forms", "{% macro input(name) %}[{{ name }}]{% endmacro input %}"),
+        ("tpl", r#"{% from "forms" import textarea %}{{ textarea(name="a") }}"#),
+    ]);
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Template `tpl` imports the macro `textarea` from `forms` but it isn't defined there"
+    );
+}
+
+#[test]
+fn render_macros_with_positional_args() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro link(url, text, class='btn') %}<a class=\"{{ class }}\" href=\"{{ url }}\">{{ text }}</a>{% endmacro link %}"),
+        ("tpl", "{% import \"macros\" as macros %}{{ macros::link(\"/\", \"Home\") }}{{ macros::link(\"/a\", text=\"A\", class=\"nav\") }}"),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(
+        result.unwrap(),
+        "<a class=\"btn\" href=\"/\">Home</a><a class=\"nav\" href=\"/a\">A</a>".to_string()
+    );
+}
+
+#[test]
+fn render_macros_with_expression_defaults() {
+    let mut context = Context::new();
+    context.insert("site", "lysine");
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro title(page, full=site ~ \" - \" ~ page, size=page | length * 2) %}{{ full }}:{{ size }}{% endmacro title %}"),
+        ("tpl", "{% import \"macros\" as macros %}{{ macros::title(page=\"docs\") }}|{{ macros::title(\"blog\", size=1) }}"),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap(), "lysine - docs:8|lysine - blog:1".to_string());
+}
+
+#[test]
+fn render_macros_with_varargs_and_kwargs() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro tag(name, *children, **attrs) %}<{{ name }}{% for key, value in attrs %} {{ key }}=\"{{ value }}\"{% endfor %}>{{ children | join(sep=\",\") }}</{{ name }}>{% endmacro tag %}"),
+        ("tpl", "{% import \"macros\" as macros %}{{ macros::tag(\"ul\", 1, 2, 3, id=\"list\") }}{{ macros::tag(\"br\") }}"),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), "<ul id=\"list\">1,2,3</ul><br></br>".to_string());
+}
+
+#[test]
+fn render_selectively_imported_macros_with_positional_args() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("forms", "{% macro input(name, type=\"text\") %}[{{ type }}:{{ name }}]{% endmacro input %}"),
+        ("tpl", r#"{% from "forms" import input %}{{ input("a") }}{{ input("b", "password") }}"#),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), "[text:a][password:b]".to_string());
+}
+
+#[test]
+fn render_macro_passed_to_another_macro() {
+    let mut context = Context::new();
+    context.insert("rows", &vec![vec![1, 2], vec![3, 4]]);
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro table(rows, cell) %}{% for row in rows %}<tr>{% for value in row %}{{ cell(value) }}{% endfor %}</tr>{% endfor %}{% endmacro table %}{% macro bold(value) %}<b>{{ value }}</b>{% endmacro bold %}"),
+        ("tpl", "{% import \"macros\" as macros %}{{ macros::table(rows, cell=macros::bold) }}"),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &context);
+    assert_eq!(
+        result.unwrap(),
+        "<tr><b>1</b><b>2</b></tr><tr><b>3</b><b>4</b></tr>".to_string()
+    );
+}
+
+#[test]
+fn render_macro_stored_in_variable() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("tpl", "{% macro hello(name) %}Hello {{ name }}{% endmacro hello %}{% set greet = self::hello %}{{ greet(\"Bob\") }}{% if greet %}!{% endif %}"),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), "Hello Bob!".to_string());
+}
+
+#[test]
+fn render_map_filter_with_macro() {
+    let mut context = Context::new();
+    context.insert("names", &vec!["a", "b"]);
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("macros", "{% macro item(name, prefix=\"-\") %}{{ prefix }}{{ name }}{% endmacro item %}"),
+        ("tpl", "{% import \"macros\" as macros %}{{ names | map(macro=macros::item) | join(sep=\",\") }}|{{ names | map(macro=macros::item, prefix=\"*\") | join(sep=\",\") }}"),
+    ]).unwrap();
+
+    let result = lysine.render("tpl", &context);
+    assert_eq!(result.unwrap(), "-a,-b|*a,*b".to_string());
+}
+
+#[test]
+fn call_macro_from_rust() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_templates(vec![
+        ("helpers", "{% macro label(text) %}<label>{{ text }}</label>{% endmacro label %}"),
+        ("base.html", "{% block content %}{% endblock content %}"),
+        ("forms.html", "{% extends \"base.html\" %}{% import \"helpers\" as helpers %}{% macro input(name, type=\"text\") %}{{ helpers::label(text=name) }}<input name=\"{{ name }}\" type=\"{{ type }}\">{% endmacro input %}"),
+    ]).unwrap();
+
+    let mut args = HashMap::new();
+    args.insert("name".to_string(), to_value("<email>").unwrap());
+    let result = lysine.call_macro("forms.html", "input", &args);
+    assert_eq!(
+        result.unwrap(),
+        "<label>&lt;email&gt;</label><input name=\"&lt;email&gt;\" type=\"text\">".to_string()
+    );
+}
+
+#[test]
+fn render_macro_reference_as_its_name() {
+    let mut lysine = Lysine::default();
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro hello() %}Hello{% endmacro hello %}"),
+            ("tpl", "{% import \"macros\" as macros %}{% set greet = macros::hello %}{{ greet }}"),
+        ])
+        .unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), "macros::hello".to_string());
+}
+
+#[test]
+fn render_map_filter_registered_by_user_ignores_macros() {
+    let mut lysine = Lysine::default();
+    lysine.register_filter("map", |_: &Value, _: &HashMap<String, Value>| Ok(to_value("mine")?));
+    lysine
+        .add_raw_templates(vec![
+            ("macros", "{% macro item(name) %}{{ name }}{% endmacro item %}"),
+            ("tpl", "{% import \"macros\" as macros %}{{ [1] | map(macro=macros::item) }}"),
+        ])
+        .unwrap();
+
+    let result = lysine.render("tpl", &Context::new());
+    assert_eq!(result.unwrap(), "mine".to_string());
+}
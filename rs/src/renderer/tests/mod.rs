@@ -1,5 +1,7 @@
 use serde_derive::Serialize;
 
+#[cfg(feature = "async")]
+mod async_render;
 mod basic;
 mod errors;
 mod inheritance;
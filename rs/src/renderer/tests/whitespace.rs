@@ -116,3 +116,54 @@ fn make_sure_not_to_delete_whitespaces() {
     let res = Lysine::one_off(input, &context, true).unwrap();
     assert_eq!(res, "    yaml_test:     ");
 }
+
+#[test]
+fn can_use_trim_blocks_and_lstrip_blocks() {
+    let mut context = Context::new();
+    context.insert("numbers", &vec![1, 2]);
+
+    let inputs = vec![
+        ("{% for n in numbers %}\n{{ n }}\n{% endfor %}\n", "1\n2\n"),
+        (
+            "<ul>\n  {% for n in numbers %}\n  <li>{{ n }}</li>\n  {% endfor %}\n</ul>",
+            "<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>",
+        ),
+        ("  {% if true %}\n    yes\n  {% else %}\n    no\n  {% endif %}\n", "    yes\n"),
+        ("  {# comment #}\nhello", "hello"),
+        ("{{ 1 }}\n  {{ 2 }}\n", "1\n  2\n"),
+        ("hey {% set a = 1 %}\nthere", "hey there"),
+        ("  {% raw %}\n{{ a }}\n  {% endraw %}\n", "{{ a }}\n"),
+        // `+` disables them for that tag
+        ("  {%+ if true +%}\nyes\n{% endif %}\n", "  \nyes\n"),
+        // `-` still trims everything
+        ("a\n  {%- if true -%}\n\n  b\n{% endif %}", "ab\n"),
+    ];
+
+    for (input, expected) in inputs {
+        let mut lysine = Lysine::default();
+        lysine.trim_blocks(true);
+        lysine.lstrip_blocks(true);
+        lysine.add_raw_template("tpl", input).unwrap();
+        assert_eq!(lysine.render("tpl", &context).unwrap(), expected);
+    }
+}
+
+#[test]
+fn can_remove_trailing_newline() {
+    let mut lysine = Lysine::default();
+    lysine.keep_trailing_newline(false);
+    lysine
+        .add_raw_templates(vec![("one", "hello\n"), ("two", "hello\n\n"), ("three", "hello")])
+        .unwrap();
+
+    assert_eq!(lysine.render("one", &Context::new()).unwrap(), "hello");
+    assert_eq!(lysine.render("two", &Context::new()).unwrap(), "hello\n");
+    assert_eq!(lysine.render("three", &Context::new()).unwrap(), "hello");
+}
+
+#[test]
+fn whitespace_options_are_off_by_default() {
+    let mut lysine = Lysine::default();
+    lysine.add_raw_template("tpl", "  {% if true %}\nyes\n  {% endif %}\n").unwrap();
+    assert_eq!(lysine.render("tpl", &Context::new()).unwrap(), "  \nyes\n  \n");
+}
@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+
+use crate::errors::{Error, Result};
+use crate::parser::ast::{AccessKey, Expr, ExprVal, FunctionCall, Node};
+use crate::template::Template;
+
+// Which names of a kind of callable, or which templates, a sandboxed template can use
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Access {
+    // Everything can be used
+    #[default]
+    All,
+    // Only those names can be used
+    Allowlist(HashSet<String>),
+    // Everything but those names can be used
+    Denylist(HashSet<String>),
+}
+
+impl Access {
+    // Only allows the given names
+    pub fn allowlist<I, S>(names: I) -> Access
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Access::Allowlist(names.into_iter().map(Into::into).collect())
+    }
+
+    // Allows everything but the given names
+    pub fn denylist<I, S>(names: I) -> Access
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Access::Denylist(names.into_iter().map(Into::into).collect())
+    }
+
+    // Whether the given name can be used
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            Access::All => true,
+            Access::Allowlist(names) => names.contains(name),
+            Access::Denylist(names) => !names.contains(name),
+        }
+    }
+}
+
+// Restricts what templates can use, for templates coming from untrusted sources. Set it with
+// `Lysine::sandbox`: the templates already loaded and all the ones added afterwards are checked
+// when they are loaded, so a template using something that isn't allowed is never rendered.
+// What is only known when rendering, like the macro behind a reference or the templates given
+// to `include()`, is checked again then.
+//
+// The default sandbox only denies the `get_env` function, which would let a template read any
+// environment variable of the process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sandbox {
+    // The filters that can be used, including in `{% filter %}` sections
+    pub filters: Access,
+    // The tests that can be used with `is`
+    pub tests: Access,
+    // The functions that can be called. The macros imported with `{% from ... import ... %}`
+    // aren't restricted, but a variable holding a macro reference is called like a function
+    // and has to be allowed as one
+    pub functions: Access,
+    // The templates that can be extended, included, embedded or imported. When they are
+    // restricted, the `include()` function can only be given the names as strings
+    pub templates: Access,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox {
+            filters: Access::All,
+            tests: Access::All,
+            functions: Access::denylist(["get_env"]),
+            templates: Access::All,
+        }
+    }
+}
+
+impl Sandbox {
+    // Checks everything a template uses, erroring on the first thing that isn't allowed
+    pub(crate) fn check(&self, template: &Template) -> Result<()> {
+        let checker = Checker { sandbox: self, template };
+
+        if let Some(ref parent) = template.parent {
+            checker.check_template(parent)?;
+        }
+        for (tpl_name, _) in &template.imported_macro_files {
+            checker.check_template(tpl_name)?;
+        }
+        for (_, tpl_name, _, _) in &template.imported_macros {
+            checker.check_template(tpl_name)?;
+        }
+
+        checker.check_body(&template.ast)
+    }
+}
+
+// The error for a template using something the sandbox doesn't allow
+pub(crate) fn not_allowed(template_name: &str, kind: &str, name: &str) -> Error {
+    Error::msg(format!(
+        "Template '{}' uses the {} `{}`, which isn't allowed by the sandbox",
+        template_name, kind, name
+    ))
+}
+
+fn literal_string(expr: &Expr) -> Option<&str> {
+    match expr.val {
+        ExprVal::String(ref s) if expr.filters.is_empty() => Some(s),
+        _ => None,
+    }
+}
+
+struct Checker<'a> {
+    sandbox: &'a Sandbox,
+    template: &'a Template,
+}
+
+impl<'a> Checker<'a> {
+    fn not_allowed(&self, kind: &str, name: &str) -> Error {
+        not_allowed(&self.template.name, kind, name)
+    }
+
+    fn check_template(&self, name: &str) -> Result<()> {
+        if self.sandbox.templates.allows(name) {
+            return Ok(());
+        }
+        Err(self.not_allowed("template", name))
+    }
+
+    fn check_body(&self, body: &[Node]) -> Result<()> {
+        for node in body {
+            self.check_node(node)?;
+        }
+        Ok(())
+    }
+
+    fn check_node(&self, node: &Node) -> Result<()> {
+        match *node {
+            Node::VariableBlock(_, ref expr) => self.check_expr(expr),
+            Node::MacroDefinition(_, ref macro_def, _) => {
+                for default in macro_def.args.iter().filter_map(|(_, default)| default.as_ref()) {
+                    self.check_expr(default)?;
+                }
+                self.check_body(&macro_def.body)
+            }
+            Node::Include(_, ref tpl_names, _) => {
+                tpl_names.iter().try_for_each(|tpl_name| self.check_template(tpl_name))
+            }
+            Node::Embed(_, ref embed, _) => {
+                self.check_template(&embed.template)?;
+                self.check_body(&embed.body)
+            }
+            Node::Set(_, ref set) => self.check_expr(&set.value),
+            Node::FilterSection(_, ref filter_section, _) => {
+                self.check_filter(&filter_section.filter)?;
+                self.check_body(&filter_section.body)
+            }
+            Node::Block(_, ref block, _) => self.check_body(&block.body),
+            Node::Forloop(_, ref forloop, _) => {
+                self.check_expr(&forloop.container)?;
+                self.check_body(&forloop.body)?;
+                match forloop.empty_body {
+                    Some(ref empty_body) => self.check_body(empty_body),
+                    None => Ok(()),
+                }
+            }
+            Node::Whileloop(_, ref whileloop, _) => {
+                self.check_expr(&whileloop.condition)?;
+                self.check_body(&whileloop.body)
+            }
+            Node::If(ref if_node, _) => {
+                for (_, expr, body) in &if_node.conditions {
+                    self.check_expr(expr)?;
+                    self.check_body(body)?;
+                }
+                match if_node.otherwise {
+                    Some((_, ref body)) => self.check_body(body),
+                    None => Ok(()),
+                }
+            }
+            // The templates of the imports and of `{% extends %}` are checked from the
+            // `Template` itself
+            Node::Super
+            | Node::Text(_)
+            | Node::Extends(_, _)
+            | Node::Requires(_, _)
+            | Node::ImportMacro(_, _, _)
+            | Node::FromImport(_, _, _)
+            | Node::Raw(_, _, _)
+            | Node::Break(_)
+            | Node::Continue(_)
+            | Node::Comment(_, _) => Ok(()),
+        }
+    }
+
+    fn check_exprs<'e>(&self, exprs: impl IntoIterator<Item = &'e Expr>) -> Result<()> {
+        exprs.into_iter().try_for_each(|expr| self.check_expr(expr))
+    }
+
+    fn check_args(&self, call: &FunctionCall) -> Result<()> {
+        self.check_exprs(call.positional_args.iter().chain(call.args.values()))
+    }
+
+    fn check_filter(&self, filter: &FunctionCall) -> Result<()> {
+        if !self.sandbox.filters.allows(&filter.name) {
+            return Err(self.not_allowed("filter", &filter.name));
+        }
+        self.check_args(filter)
+    }
+
+    fn check_function(&self, function_call: &FunctionCall) -> Result<()> {
+        // Macros imported by name are called like functions
+        let is_macro = self.template.imported_macros.iter().any(|m| m.3 == function_call.name);
+        if !is_macro && !self.sandbox.functions.allows(&function_call.name) {
+            return Err(self.not_allowed("function", &function_call.name));
+        }
+
+        if function_call.name == "include" && !is_macro {
+            self.check_include_fn_call(function_call)?;
+        }
+
+        self.check_args(function_call)
+    }
+
+    // The template names given to `include()` have to be known when loading the template to be
+    // checked
+    fn check_include_fn_call(&self, function_call: &FunctionCall) -> Result<()> {
+        if self.sandbox.templates == Access::All {
+            return Ok(());
+        }
+
+        let name_expr = match function_call.positional_args.first() {
+            Some(expr) => Some(expr),
+            None => function_call.args.get("name"),
+        };
+        let names: Option<Vec<&str>> = match name_expr {
+            Some(expr) if expr.filters.is_empty() => match expr.val {
+                ExprVal::String(ref name) => Some(vec![name]),
+                ExprVal::Array(ref names) => names.iter().map(literal_string).collect(),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match names {
+            Some(names) => names.into_iter().try_for_each(|name| self.check_template(name)),
+            None => Err(Error::msg(format!(
+                "Template '{}' calls `include()` with a template name that isn't a string, which isn't allowed by the sandbox",
+                self.template.name
+            ))),
+        }
+    }
+
+    fn check_expr(&self, expr: &Expr) -> Result<()> {
+        match expr.val {
+            ExprVal::String(_)
+            | ExprVal::Int(_)
+            | ExprVal::Float(_)
+            | ExprVal::Bool(_)
+            | ExprVal::Ident(_)
+            | ExprVal::MacroRef(_) => (),
+            ExprVal::Math(ref math) => self.check_exprs([&*math.lhs, &*math.rhs])?,
+            ExprVal::Logic(ref logic) => self.check_exprs([&*logic.lhs, &*logic.rhs])?,
+            ExprVal::Test(ref test) => {
+                if !self.sandbox.tests.allows(&test.name) {
+                    return Err(self.not_allowed("test", &test.name));
+                }
+                self.check_expr(&test.expr)?;
+                self.check_exprs(&test.args)?;
+            }
+            ExprVal::MacroCall(ref macro_call) => {
+                self.check_exprs(macro_call.positional_args.iter().chain(macro_call.args.values()))?
+            }
+            ExprVal::FunctionCall(ref function_call) => self.check_function(function_call)?,
+            // Methods belong to the objects given by the application, only their arguments
+            // are checked
+            ExprVal::MethodCall(ref method_call) => self.check_args(&method_call.call)?,
+            ExprVal::Array(ref values) => self.check_exprs(values)?,
+            ExprVal::StringConcat(ref concat) => self.check_exprs(&concat.values)?,
+            ExprVal::In(ref in_cond) => self.check_exprs([&*in_cond.lhs, &*in_cond.rhs])?,
+            ExprVal::Access(ref access) => {
+                self.check_expr(&access.target)?;
+                for (key, _) in &access.keys {
+                    if let AccessKey::Index(ref index) = key {
+                        self.check_expr(index)?;
+                    }
+                }
+            }
+            ExprVal::Coalesce(ref coalesce) => {
+                self.check_exprs([&*coalesce.lhs, &*coalesce.rhs])?
+            }
+            ExprVal::Group(ref inner) => self.check_expr(inner)?,
+        }
+
+        expr.filters.iter().try_for_each(|filter| self.check_filter(filter))
+    }
+}
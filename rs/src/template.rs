@@ -1,8 +1,19 @@
 use std::collections::HashMap;
 
 use crate::errors::{Error, Result};
-use crate::parser::ast::{Block, MacroDefinition, Node};
-use crate::parser::{parse, remove_whitespace};
+use crate::parser::ast::{Block, MacroDefinition, Node, Requirement};
+use crate::parser::{parse, remove_whitespace, WhitespaceOptions};
+
+// Where the macros imported with `{% from ... import ... %}` are visible
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MacroScope {
+    // The top-level of the template, visible everywhere in it
+    Template,
+    // Only inside the block of that name
+    Block(String),
+    // Only inside the macro of that name
+    Macro(String),
+}
 
 // This is the parsed equivalent of a template file.
 // It also does some pre-processing to ensure it does as little as possible at runtime
@@ -24,6 +35,12 @@ pub struct Template {
     pub macros: HashMap<String, MacroDefinition>,
     // (filename, namespace) for the macros imported in that file
     pub imported_macro_files: Vec<(String, String)>,
+    // (scope, filename, macro name, bound name) for the macros imported one by one
+    // with `{% from "..." import ... %}`
+    pub imported_macros: Vec<(MacroScope, String, String, String)>,
+
+    // The variables declared with `{% requires %}`, checked against the context before rendering
+    pub requires: Vec<Requirement>,
 
     // Only used during initial parsing. Rendering will use `self.parents`
     pub parent: Option<String>,
@@ -40,17 +57,91 @@ pub struct Template {
     // The order of the Vec is from the first in hierarchy to the current template and the template
     // name is needed in order to load its macros if necessary.
     pub blocks_definitions: HashMap<String, Vec<(String, Block)>>,
+
+    // The hidden templates made from the `{% embed %}` tags of that template: each one extends
+    // the embedded template and overrides its blocks. They belong to this template, so they
+    // can't be rendered directly and go away with it.
+    pub embeds: Vec<Template>,
 }
 
 impl Template {
     // Parse the template string given
     pub fn new(tpl_name: &str, tpl_path: Option<String>, input: &str) -> Result<Template> {
-        let ast = remove_whitespace(parse(input)?, None);
+        Template::new_with_options(tpl_name, tpl_path, input, &WhitespaceOptions::default())
+    }
+
+    // Parse the template string given, applying the whitespace rules of the Lysine instance
+    pub fn new_with_options(
+        tpl_name: &str,
+        tpl_path: Option<String>,
+        input: &str,
+        options: &WhitespaceOptions,
+    ) -> Result<Template> {
+        let input = if options.keep_trailing_newline {
+            input
+        } else {
+            input.strip_suffix("\r\n").or_else(|| input.strip_suffix('\n')).unwrap_or(input)
+        };
+        let mut ast = remove_whitespace(parse(input)?, options);
+
+        // Each embed gets a hidden template, we name them before anything clones the nodes
+        // Vec<(hidden template name, embedded template name, body)>
+        let mut embed_bodies = vec![];
+        fn find_embeds(
+            tpl_name: &str,
+            ast: &mut [Node],
+            embeds: &mut Vec<(String, String, Vec<Node>)>,
+        ) {
+            for node in ast {
+                match *node {
+                    Node::Embed(_, ref mut embed, _) => {
+                        let index = embeds.len();
+                        embed.name = format!("{}:embed:{}", tpl_name, index);
+                        embeds.push((embed.name.clone(), embed.template.clone(), vec![]));
+                        // Embeds can be nested in the blocks of an embed
+                        find_embeds(tpl_name, &mut embed.body, embeds);
+                        embeds[index].2 = embed.body.clone();
+                    }
+                    Node::Block(_, ref mut block, _) => {
+                        find_embeds(tpl_name, &mut block.body, embeds)
+                    }
+                    Node::MacroDefinition(_, ref mut macro_def, _) => {
+                        find_embeds(tpl_name, &mut macro_def.body, embeds)
+                    }
+                    Node::FilterSection(_, ref mut filter_section, _) => {
+                        find_embeds(tpl_name, &mut filter_section.body, embeds)
+                    }
+                    Node::Forloop(_, ref mut forloop, _) => {
+                        find_embeds(tpl_name, &mut forloop.body, embeds);
+                        if let Some(ref mut empty_body) = forloop.empty_body {
+                            find_embeds(tpl_name, empty_body, embeds);
+                        }
+                    }
+                    Node::Whileloop(_, ref mut whileloop, _) => {
+                        find_embeds(tpl_name, &mut whileloop.body, embeds)
+                    }
+                    Node::If(ref mut if_node, _) => {
+                        for (_, _, body) in &mut if_node.conditions {
+                            find_embeds(tpl_name, body, embeds);
+                        }
+                        if let Some((_, ref mut body)) = if_node.otherwise {
+                            find_embeds(tpl_name, body, embeds);
+                        }
+                    }
+                    _ => continue,
+                };
+            }
+        }
+        find_embeds(tpl_name, &mut ast, &mut embed_bodies);
 
         // First we want all the blocks used in that template
         // This is recursive as we can have blocks inside blocks
         let mut blocks = HashMap::new();
-        fn find_blocks(ast: &[Node], blocks: &mut HashMap<String, Block>) -> Result<()> {
+        fn find_blocks(
+            ast: &[Node],
+            blocks: &mut HashMap<String, Block>,
+            in_loop: bool,
+        ) -> Result<()> {
             for node in ast {
                 match *node {
                     Node::Block(_, ref block, _) => {
@@ -60,9 +151,33 @@ impl Template {
                                 block.name
                             )));
                         }
+                        if in_loop && !block.scoped {
+                            return Err(Error::msg(format!(
+                                "Block `{}` is inside a for loop and needs to be marked as `{{% block {} scoped %}}`",
+                                block.name, block.name
+                            )));
+                        }
 
                         blocks.insert(block.name.to_string(), block.clone());
-                        find_blocks(&block.body, blocks)?;
+                        find_blocks(&block.body, blocks, in_loop)?;
+                    }
+                    // Blocks can only be inside for loops if they are scoped
+                    Node::Forloop(_, ref forloop, _) => {
+                        find_blocks(&forloop.body, blocks, true)?;
+                        if let Some(ref empty_body) = forloop.empty_body {
+                            find_blocks(empty_body, blocks, true)?;
+                        }
+                    }
+                    Node::Whileloop(_, ref whileloop, _) => {
+                        find_blocks(&whileloop.body, blocks, true)?
+                    }
+                    Node::If(ref if_node, _) if in_loop => {
+                        for (_, _, body) in &if_node.conditions {
+                            find_blocks(body, blocks, in_loop)?;
+                        }
+                        if let Some((_, ref body)) = if_node.otherwise {
+                            find_blocks(body, blocks, in_loop)?;
+                        }
                     }
                     _ => continue,
                 };
@@ -70,16 +185,84 @@ impl Template {
 
             Ok(())
         }
-        find_blocks(&ast, &mut blocks)?;
+        find_blocks(&ast, &mut blocks, false)?;
+
+        // Selective imports can happen anywhere so we also need to look inside bodies
+        let mut imported_macros = vec![];
+        fn find_from_imports(
+            ast: &[Node],
+            scope: &MacroScope,
+            imports: &mut Vec<(MacroScope, String, String, String)>,
+        ) {
+            for node in ast {
+                match *node {
+                    Node::FromImport(_, ref tpl_name, ref names) => {
+                        for (name, alias) in names {
+                            imports.push((
+                                scope.clone(),
+                                tpl_name.to_string(),
+                                name.to_string(),
+                                alias.to_string(),
+                            ));
+                        }
+                    }
+                    Node::Block(_, ref block, _) => find_from_imports(
+                        &block.body,
+                        &MacroScope::Block(block.name.clone()),
+                        imports,
+                    ),
+                    Node::MacroDefinition(_, ref macro_def, _) => find_from_imports(
+                        &macro_def.body,
+                        &MacroScope::Macro(macro_def.name.clone()),
+                        imports,
+                    ),
+                    Node::Forloop(_, ref forloop, _) => {
+                        find_from_imports(&forloop.body, scope, imports);
+                        if let Some(ref empty_body) = forloop.empty_body {
+                            find_from_imports(empty_body, scope, imports);
+                        }
+                    }
+                    Node::Whileloop(_, ref whileloop, _) => {
+                        find_from_imports(&whileloop.body, scope, imports)
+                    }
+                    Node::FilterSection(_, ref filter_section, _) => {
+                        find_from_imports(&filter_section.body, scope, imports)
+                    }
+                    Node::Embed(_, ref embed, _) => find_from_imports(&embed.body, scope, imports),
+                    Node::If(ref if_node, _) => {
+                        for (_, _, body) in &if_node.conditions {
+                            find_from_imports(body, scope, imports);
+                        }
+                        if let Some((_, ref body)) = if_node.otherwise {
+                            find_from_imports(body, scope, imports);
+                        }
+                    }
+                    _ => continue,
+                };
+            }
+        }
+        find_from_imports(&ast, &MacroScope::Template, &mut imported_macros);
 
         // And now we find the potential parent and everything macro related (definition, import)
         let mut macros = HashMap::new();
         let mut imported_macro_files = vec![];
         let mut parent = None;
+        let mut requires = vec![];
 
         for node in &ast {
             match *node {
                 Node::Extends(_, ref name) => parent = Some(name.to_string()),
+                Node::Requires(_, ref requirements) => {
+                    for requirement in requirements {
+                        if requires.iter().any(|r: &Requirement| r.name == requirement.name) {
+                            return Err(Error::msg(format!(
+                                "`{}` is declared more than once in `{{% requires %}}`",
+                                requirement.name
+                            )));
+                        }
+                        requires.push(requirement.clone());
+                    }
+                }
                 Node::MacroDefinition(_, ref macro_def, _) => {
                     if macros.contains_key(&macro_def.name) {
                         return Err(Error::msg(format!(
@@ -96,24 +279,49 @@ impl Template {
             }
         }
 
+        // The blocks of an embed can use the same macros as the template around them
+        let mut embeds = Vec::with_capacity(embed_bodies.len());
+        for (name, embedded, body) in embed_bodies {
+            let mut embed_blocks = HashMap::new();
+            find_blocks(&body, &mut embed_blocks, false)?;
+            embeds.push(Template {
+                name,
+                path: None,
+                ast: body,
+                requires: vec![],
+                parent: Some(embedded),
+                blocks: embed_blocks,
+                macros: macros.clone(),
+                imported_macro_files: imported_macro_files.clone(),
+                imported_macros: imported_macros.clone(),
+                parents: vec![],
+                blocks_definitions: HashMap::new(),
+                from_extend: false,
+                embeds: vec![],
+            });
+        }
+
         Ok(Template {
             name: tpl_name.to_string(),
             path: tpl_path,
             ast,
+            requires,
             parent,
             blocks,
             macros,
             imported_macro_files,
+            imported_macros,
             parents: vec![],
             blocks_definitions: HashMap::new(),
             from_extend: false,
+            embeds,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Template;
+    use super::{MacroScope, Template};
 
     #[test]
     fn can_parse_ok_template() {
@@ -127,6 +335,25 @@ mod tests {
         assert_eq!(tpl.parent.unwrap(), "base.html".to_string());
     }
 
+    #[test]
+    fn can_find_requirements() {
+        let tpl =
+            Template::new("hello", None, "{% requires user, locale = \"en\" %}Hello").unwrap();
+
+        let names: Vec<_> = tpl.requires.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["user", "locale"]);
+    }
+
+    #[test]
+    fn error_on_duplicate_requirement() {
+        let tpl = Template::new("hello", None, "{% requires user %}{% requires user: object %}");
+
+        assert_eq!(
+            tpl.unwrap_err().to_string(),
+            "`user` is declared more than once in `{% requires %}`"
+        );
+    }
+
     #[test]
     fn can_find_blocks() {
         let tpl = Template::new(
@@ -153,6 +380,31 @@ mod tests {
         assert!(tpl.blocks.contains_key("extrahey"));
     }
 
+    #[test]
+    fn can_find_scoped_blocks_in_forloops() {
+        let tpl = Template::new(
+            "hello",
+            None,
+            "{% for i in items %}{% if i %}{% block item scoped %}{{ i }}{% endblock item %}{% endif %}{% endfor %}",
+        ).unwrap();
+
+        assert!(tpl.blocks["item"].scoped);
+    }
+
+    #[test]
+    fn error_on_unscoped_block_in_forloop() {
+        let tpl = Template::new(
+            "hello",
+            None,
+            "{% for i in items %}{% block item %}{{ i }}{% endblock item %}{% endfor %}",
+        );
+
+        assert_eq!(
+            tpl.unwrap_err().to_string(),
+            "Block `item` is inside a for loop and needs to be marked as `{% block item scoped %}`"
+        );
+    }
+
     #[test]
     fn can_find_macros() {
         let tpl = Template::new("hello", None, "{% macro hey() %}{% endmacro hey %}").unwrap();
@@ -167,4 +419,31 @@ mod tests {
             vec![("macros.html".to_string(), "macros".to_string())]
         );
     }
+
+    #[test]
+    fn can_find_selectively_imported_macros() {
+        let tpl = Template::new(
+            "hello",
+            None,
+            "{% from \"forms.html\" import input %}{% block body %}{% from \"forms.html\" import select as dropdown %}{% endblock body %}",
+        )
+        .unwrap();
+        assert_eq!(
+            tpl.imported_macros,
+            vec![
+                (
+                    MacroScope::Template,
+                    "forms.html".to_string(),
+                    "input".to_string(),
+                    "input".to_string()
+                ),
+                (
+                    MacroScope::Block("body".to_string()),
+                    "forms.html".to_string(),
+                    "select".to_string(),
+                    "dropdown".to_string()
+                ),
+            ]
+        );
+    }
 }